@@ -0,0 +1,94 @@
+// Compares the packed frame buffer (shift/XOR draw + dirty-row blit) against
+// the old byte-per-pixel buffer that redrew every RGBA pixel each frame.
+// This is the CPU side only: `pixels` uploads the whole texture every frame
+// either way.
+//
+//     cargo bench --bench framebuffer
+
+use chip8_emulator::Palette;
+use chip8_emulator::framebuffer::{FrameBuffer, Resolution};
+use criterion::{BenchmarkId, Criterion, black_box, criterion_group, criterion_main};
+
+// A typical game frame: erase and redraw a handful of 8x8 sprites.
+const SPRITE: [u8; 8] = [0x3C, 0x7E, 0xFF, 0xDB, 0xFF, 0x24, 0x5A, 0x81];
+const POSITIONS: [(usize, usize); 4] = [(3, 2), (20, 10), (40, 17), (57, 22)];
+
+struct ByteBuffer {
+    width: usize,
+    height: usize,
+    cells: Vec<u8>,
+}
+
+impl ByteBuffer {
+    fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            cells: vec![0; width * height],
+        }
+    }
+
+    fn draw_sprite(&mut self, x: usize, y: usize, sprite: &[u8]) -> bool {
+        let mut vf = false;
+        for (row, &byte) in sprite.iter().enumerate() {
+            for col in 0..8 {
+                if byte & (0x80 >> col) != 0 {
+                    let idx = ((y + row) % self.height) * self.width + (x + col) % self.width;
+                    vf |= self.cells[idx] == 1;
+                    self.cells[idx] ^= 1;
+                }
+            }
+        }
+        vf
+    }
+
+    fn redraw(&self, frame: &mut [u8]) {
+        for (cell, px) in self.cells.iter().zip(frame.chunks_exact_mut(4)) {
+            let v = if *cell == 1 { 0xFF } else { 0x00 };
+            px.copy_from_slice(&[v, v, v, 0xFF]);
+        }
+    }
+}
+
+fn frame(c: &mut Criterion) {
+    let mut group = c.benchmark_group("frame");
+    let palette = Palette::default();
+
+    for (name, resolution) in [("lores", Resolution::Lores), ("hires", Resolution::Hires)] {
+        let mut packed = FrameBuffer::with_resolution(resolution);
+        let (width, height) = (packed.width(), packed.height());
+        let mut bytes = ByteBuffer::new(width, height);
+        let mut rgba = vec![0u8; width * height * 4];
+
+        group.bench_function(BenchmarkId::new("byte_per_pixel", name), |b| {
+            b.iter(|| {
+                for &(x, y) in &POSITIONS {
+                    black_box(bytes.draw_sprite(x * width / 64, y * height / 32, &SPRITE));
+                    black_box(bytes.draw_sprite(x * width / 64 + 1, y * height / 32, &SPRITE));
+                }
+                bytes.redraw(&mut rgba);
+                black_box(&rgba);
+            })
+        });
+
+        group.bench_function(BenchmarkId::new("packed_dirty_rows", name), |b| {
+            b.iter(|| {
+                for &(x, y) in &POSITIONS {
+                    black_box(packed.draw_sprite(x * width / 64, y * height / 32, &SPRITE, false));
+                    black_box(packed.draw_sprite(
+                        x * width / 64 + 1,
+                        y * height / 32,
+                        &SPRITE,
+                        false,
+                    ));
+                }
+                black_box(packed.blit_dirty(&mut rgba, &palette));
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, frame);
+criterion_main!(benches);
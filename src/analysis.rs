@@ -0,0 +1,481 @@
+// Static analysis of a ROM image: follow the code reachable from 0x200 and
+// look at which opcodes it uses to guess the platform it was written for.
+
+use crate::chip8::{MEMORY_SIZE, PROGRAM_START_LOC};
+use crate::quirks::Quirks;
+use std::collections::BTreeSet;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Platform {
+    Chip8,
+    Schip,
+    XoChip,
+}
+
+impl Platform {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Platform::Chip8 => "CHIP-8",
+            Platform::Schip => "SUPER-CHIP",
+            Platform::XoChip => "XO-CHIP",
+        }
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        match self {
+            Platform::Chip8 => Quirks::CHIP8,
+            Platform::Schip => Quirks::SCHIP,
+            Platform::XoChip => Quirks::XOCHIP,
+        }
+    }
+}
+
+/// What `analyze` found. The address sets hold where each pattern was seen.
+#[derive(Debug, Clone, Default)]
+pub struct Analysis {
+    pub instructions: usize, // reachable instructions
+    pub code: BTreeSet<u16>, // where they start
+    pub schip_opcodes: BTreeSet<u16>,
+    pub xochip_opcodes: BTreeSet<u16>,
+    pub shift_vx_vy: BTreeSet<u16>,     // 8xy6/8xyE with x != y
+    pub shift_vx: BTreeSet<u16>,        // ... right after Vx was set, so shifting Vx
+    pub shift_vy: BTreeSet<u16>,        // ... right after Vy was set, so shifting Vy
+    pub jump_offset: BTreeSet<u16>,     // Bnnn, which can't be followed
+    pub jump_v0: BTreeSet<u16>,         // ... right after V0 was set, so Bnnn
+    pub jump_vx: BTreeSet<u16>,         // ... right after Vx was set, so Bxnn
+    pub index_increment: BTreeSet<u16>, // Fx55/Fx65 whose I is used again afterwards
+    pub self_modifying: BTreeSet<u16>,  // stores into reachable code
+}
+
+// How control continues after an instruction.
+enum Flow {
+    Next,
+    Stop,
+    Jump(usize),
+    Call(usize),
+    Skip,
+}
+
+fn opcode_at(rom: &[u8], addr: usize) -> Option<u16> {
+    let offset = addr.checked_sub(PROGRAM_START_LOC)?;
+    Some(u16::from_be_bytes([
+        *rom.get(offset)?,
+        *rom.get(offset + 1)?,
+    ]))
+}
+
+// XO-CHIP's `F000 nnnn` is the one four byte instruction.
+fn size_at(rom: &[u8], addr: usize) -> usize {
+    if opcode_at(rom, addr) == Some(0xF000) {
+        4
+    } else {
+        2
+    }
+}
+
+/// Trace the code reachable from 0x200 in `rom` (the bytes loaded there).
+/// Anything that doesn't decode is taken to be data and ends the path.
+pub fn analyze(rom: &[u8]) -> Analysis {
+    let mut analysis = Analysis::default();
+    let mut reached = vec![false; MEMORY_SIZE];
+    let mut pending = vec![PROGRAM_START_LOC];
+
+    while let Some(addr) = pending.pop() {
+        if addr >= MEMORY_SIZE || reached[addr] {
+            continue;
+        }
+        let Some(op) = opcode_at(rom, addr) else {
+            continue;
+        };
+        let flow = classify(op, addr as u16, &mut analysis);
+        if matches!(flow, Flow::Stop) && !is_known_stop(op) {
+            // not an instruction, so not code either
+            continue;
+        }
+        reached[addr] = true;
+        analysis.instructions += 1;
+        analysis.code.insert(addr as u16);
+        let next = addr + size_at(rom, addr);
+        match flow {
+            Flow::Next => pending.push(next),
+            Flow::Stop => {}
+            Flow::Jump(target) => pending.push(target),
+            Flow::Call(target) => pending.extend([next, target]),
+            Flow::Skip => pending.extend([next, next + size_at(rom, next)]),
+        }
+    }
+
+    scan_index_use(rom, &reached, &mut analysis);
+    scan_register_use(rom, &reached, &mut analysis);
+    analysis
+}
+
+// Instructions that end a path on purpose, as opposed to undecodable bytes.
+fn is_known_stop(op: u16) -> bool {
+    op == 0x00EE || op == 0x00FD || op & 0xF000 == 0xB000
+}
+
+fn classify(op: u16, addr: u16, analysis: &mut Analysis) -> Flow {
+    let x = (op >> 8) & 0xF;
+    let y = (op >> 4) & 0xF;
+    let n = op & 0xF;
+    let nn = op & 0xFF;
+    let nnn = (op & 0xFFF) as usize;
+    let mut schip = || {
+        analysis.schip_opcodes.insert(addr);
+    };
+    match op >> 12 {
+        0x0 => match op {
+            0x00E0 => Flow::Next,
+            0x00EE => Flow::Stop,
+            0x00FB | 0x00FC | 0x00FE | 0x00FF => {
+                schip();
+                Flow::Next
+            }
+            0x00FD => {
+                schip();
+                Flow::Stop
+            }
+            _ if op & 0xFFF0 == 0x00C0 => {
+                schip();
+                Flow::Next
+            }
+            _ if op & 0xFFF0 == 0x00D0 => {
+                analysis.xochip_opcodes.insert(addr);
+                Flow::Next
+            }
+            0x0000 => Flow::Stop,
+            // machine code routine on the VIP, nothing to follow
+            _ => Flow::Next,
+        },
+        0x1 => Flow::Jump(nnn),
+        0x2 => Flow::Call(nnn),
+        0x3 | 0x4 => Flow::Skip,
+        0x5 | 0x9 if n == 0 => Flow::Skip,
+        0x5 if n == 2 || n == 3 => {
+            analysis.xochip_opcodes.insert(addr);
+            Flow::Next
+        }
+        0x6 | 0x7 | 0xA | 0xC => Flow::Next,
+        0x8 => match n {
+            0x0..=0x5 | 0x7 => Flow::Next,
+            0x6 | 0xE => {
+                if x != y {
+                    analysis.shift_vx_vy.insert(addr);
+                }
+                Flow::Next
+            }
+            _ => Flow::Stop,
+        },
+        0xB => {
+            analysis.jump_offset.insert(addr);
+            Flow::Stop
+        }
+        0xD => {
+            if n == 0 {
+                schip();
+            }
+            Flow::Next
+        }
+        0xE if nn == 0x9E || nn == 0xA1 => Flow::Skip,
+        0xF => match nn {
+            0x07 | 0x0A | 0x15 | 0x18 | 0x1E | 0x29 | 0x33 | 0x55 | 0x65 => Flow::Next,
+            0x30 | 0x75 | 0x85 => {
+                schip();
+                Flow::Next
+            }
+            0x00 if x == 0 => {
+                analysis.xochip_opcodes.insert(addr);
+                Flow::Next
+            }
+            0x01 | 0x3A => {
+                analysis.xochip_opcodes.insert(addr);
+                Flow::Next
+            }
+            0x02 if x == 0 => {
+                analysis.xochip_opcodes.insert(addr);
+                Flow::Next
+            }
+            _ => Flow::Stop,
+        },
+        _ => Flow::Stop,
+    }
+}
+
+// Walk forward from every reachable Annn, Fx55 and Fx65 to find code that
+// depends on where I ends up, or that stores into itself.
+fn scan_index_use(rom: &[u8], reached: &[bool], analysis: &mut Analysis) {
+    for start in (0..MEMORY_SIZE).filter(|&addr| reached[addr]) {
+        let op = opcode_at(rom, start).unwrap();
+        let loads_i = op & 0xF000 == 0xA000;
+        let moves_i = op & 0xF0FF == 0xF055 || op & 0xF0FF == 0xF065;
+        if !loads_i && !moves_i {
+            continue;
+        }
+        let index = loads_i.then_some((op & 0xFFF) as usize);
+
+        let mut addr = start + size_at(rom, start);
+        while addr < MEMORY_SIZE && reached[addr] {
+            let op = opcode_at(rom, addr).unwrap();
+            let uses_i =
+                matches!(op & 0xF0FF, 0xF055 | 0xF065 | 0xF033 | 0xF01E) || op & 0xF000 == 0xD000;
+            if moves_i && uses_i {
+                analysis.index_increment.insert(start as u16);
+            }
+            let stored = match op & 0xF0FF {
+                0xF055 => Some((op >> 8 & 0xF) as usize + 1),
+                0xF033 => Some(3),
+                _ => None,
+            };
+            if let (Some(i), Some(len)) = (index, stored)
+                && (i.saturating_sub(1)..i + len).any(|byte| byte < MEMORY_SIZE && reached[byte])
+            {
+                analysis.self_modifying.insert(addr as u16);
+            }
+            // stop where I or the path changes
+            let ends = uses_i
+                || matches!(op >> 12, 0x1 | 0x2 | 0xA | 0xB)
+                || op & 0xF0FF == 0xF029
+                || op == 0x00EE;
+            if ends {
+                break;
+            }
+            addr += size_at(rom, addr);
+        }
+    }
+}
+
+// Registers `op` writes, as a bitmask.
+fn writes(op: u16) -> u16 {
+    let x = (op >> 8) & 0xF;
+    let y = (op >> 4) & 0xF;
+    let through = |last: u16| ((2u32 << last) - 1) as u16; // V0 to Vlast
+    match (op >> 12, op & 0xF, op & 0xFF) {
+        (0x6 | 0x7 | 0xC, _, _) => 1 << x,
+        (0x8, 0x0..=0x3, _) => 1 << x,
+        (0x8, 0x4..=0x7 | 0xE, _) => 1 << x | 1 << 0xF,
+        (0x5, 0x3, _) if x <= y => through(y) & !through(x) | 1 << x,
+        (0xF, _, 0x07 | 0x0A) => 1 << x,
+        (0xF, _, 0x65 | 0x85) => through(x),
+        _ => 0,
+    }
+}
+
+// Which of registers `a` and `b` was set last on the straight line of code
+// leading to `addr`, if only one of them was.
+fn set_last(rom: &[u8], reached: &[bool], addr: usize, a: u16, b: u16) -> Option<u16> {
+    let mut addr = addr;
+    // a few instructions back is as far as a guess is any good
+    for _ in 0..16 {
+        addr = addr.checked_sub(2).filter(|&prev| reached[prev])?;
+        let op = opcode_at(rom, addr)?;
+        if matches!(op >> 12, 0x1 | 0xB) || op == 0x00EE || op == 0x00FD {
+            // nothing falls through from here
+            return None;
+        }
+        let written = writes(op);
+        match (written & 1 << a != 0, written & 1 << b != 0) {
+            (true, false) => return Some(a),
+            (false, true) => return Some(b),
+            (true, true) => return None,
+            (false, false) => {}
+        }
+    }
+    None
+}
+
+// Sort the Vx<>Vy shifts and the Bnnn jumps by which register the code set
+// just before them: the one it expects to be used.
+fn scan_register_use(rom: &[u8], reached: &[bool], analysis: &mut Analysis) {
+    for &addr in &analysis.shift_vx_vy {
+        let op = opcode_at(rom, addr as usize).unwrap();
+        let (x, y) = ((op >> 8) & 0xF, (op >> 4) & 0xF);
+        match set_last(rom, reached, addr as usize, x, y) {
+            Some(r) if r == x => analysis.shift_vx.insert(addr),
+            Some(_) => analysis.shift_vy.insert(addr),
+            None => false,
+        };
+    }
+    for &addr in &analysis.jump_offset {
+        let x = (opcode_at(rom, addr as usize).unwrap() >> 8) & 0xF;
+        if x == 0 {
+            // the same either way
+            continue;
+        }
+        match set_last(rom, reached, addr as usize, 0, x) {
+            Some(0) => analysis.jump_v0.insert(addr),
+            Some(_) => analysis.jump_vx.insert(addr),
+            None => false,
+        };
+    }
+}
+
+impl Analysis {
+    /// The newest platform whose opcodes show up.
+    pub fn platform(&self) -> Platform {
+        if !self.xochip_opcodes.is_empty() {
+            Platform::XoChip
+        } else if !self.schip_opcodes.is_empty() {
+            Platform::Schip
+        } else {
+            Platform::Chip8
+        }
+    }
+
+    /// The platform's quirks preset, adjusted for what the code relies on.
+    pub fn quirks(&self) -> Quirks {
+        let mut quirks = self.platform().quirks();
+        // reusing I after a load or store only works if it advanced
+        if !self.index_increment.is_empty() {
+            quirks.memory = true;
+        }
+        // a register set just before a shift or jump is the one the code
+        // expects it to use
+        if self.shift_vx.len() != self.shift_vy.len() {
+            quirks.shifting = self.shift_vx.len() > self.shift_vy.len();
+        }
+        if self.jump_vx.len() != self.jump_v0.len() {
+            quirks.jumping = self.jump_vx.len() > self.jump_v0.len();
+        }
+        quirks
+    }
+}
+
+impl fmt::Display for Analysis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let addrs = |set: &BTreeSet<u16>| {
+            let shown: Vec<String> = set.iter().take(4).map(|a| format!("{:03X}", a)).collect();
+            let more = if set.len() > 4 { ", ..." } else { "" };
+            format!("{} ({}{})", set.len(), shown.join(", "), more)
+        };
+        writeln!(f, "Reachable:   {} instructions", self.instructions)?;
+        writeln!(f, "Platform:    {}", self.platform().name())?;
+        let patterns = [
+            ("SCHIP ops:  ", &self.schip_opcodes),
+            ("XO-CHIP ops:", &self.xochip_opcodes),
+            ("Vx<>Vy shift:", &self.shift_vx_vy),
+            ("  of Vx:    ", &self.shift_vx),
+            ("  of Vy:    ", &self.shift_vy),
+            ("Bnnn jumps: ", &self.jump_offset),
+            ("  by V0:    ", &self.jump_v0),
+            ("  by Vx:    ", &self.jump_vx),
+            ("I reused:   ", &self.index_increment),
+            ("Self-modify:", &self.self_modifying),
+        ];
+        for (label, set) in patterns {
+            if !set.is_empty() {
+                writeln!(f, "{} {}", label, addrs(set))?;
+            }
+        }
+        write!(f, "Quirks:      {}", self.quirks())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+
+    fn bundled(name: &str) -> Analysis {
+        analyze(
+            &fs::read(
+                Path::new(env!("CARGO_MANIFEST_DIR"))
+                    .join("roms")
+                    .join(name),
+            )
+            .unwrap(),
+        )
+    }
+
+    #[test]
+    fn bundled_roms_get_their_platform() {
+        assert_eq!(bundled("test_opcode.ch8").platform(), Platform::Chip8);
+        assert_eq!(bundled("guess.ch8").platform(), Platform::Chip8);
+        assert_eq!(bundled("snake.ch8").platform(), Platform::Schip);
+    }
+
+    #[test]
+    fn the_newest_opcode_picks_the_platform() {
+        let chip8 = [0x00, 0xE0, 0x60, 0x01, 0x12, 0x02];
+        assert_eq!(analyze(&chip8).platform(), Platform::Chip8);
+        assert_eq!(analyze(&chip8).instructions, 3);
+
+        // hires, then F000 nnnn whose second half isn't an instruction
+        let xochip = [0x00, 0xFF, 0xF0, 0x00, 0xFF, 0xFF, 0x12, 0x06];
+        let analysis = analyze(&xochip);
+        assert_eq!(analysis.schip_opcodes, BTreeSet::from([0x200]));
+        assert_eq!(analysis.xochip_opcodes, BTreeSet::from([0x202]));
+        assert_eq!(analysis.platform(), Platform::XoChip);
+        assert_eq!(analysis.quirks(), Quirks::XOCHIP);
+    }
+
+    #[test]
+    fn unreachable_data_is_ignored() {
+        // a jump over bytes that would read as SCHIP's 00FF
+        let rom = [0x12, 0x04, 0x00, 0xFF, 0x12, 0x04];
+        let analysis = analyze(&rom);
+        assert_eq!(analysis.instructions, 2);
+        assert_eq!(analysis.platform(), Platform::Chip8);
+
+        // both sides of a skip and a call's return are followed
+        let rom = [0x30, 0x00, 0x22, 0x08, 0x12, 0x04, 0x00, 0x00, 0x00, 0xFD];
+        let analysis = analyze(&rom);
+        assert_eq!(analysis.instructions, 4);
+        assert_eq!(analysis.platform(), Platform::Schip);
+    }
+
+    #[test]
+    fn code_relying_on_i_is_spotted() {
+        let rom = [
+            0x00, 0xFF, // hires
+            0xA2, 0x0E, // i := 0x20e
+            0xF1, 0x65, // load v1
+            0xF1, 0x55, // save v1, reusing I where the load left it
+            0xA2, 0x04, // i := 0x204
+            0xF0, 0x55, // save v0 over the load
+            0x12, 0x0C, // jump to itself
+        ];
+        let analysis = analyze(&rom);
+        assert_eq!(analysis.index_increment, BTreeSet::from([0x204]));
+        assert_eq!(analysis.self_modifying, BTreeSet::from([0x20A]));
+        assert_eq!(
+            analysis.quirks(),
+            Quirks {
+                memory: true,
+                ..Quirks::SCHIP
+            }
+        );
+
+        let rom = [0x6A, 0x02, 0x8A, 0xB6, 0xB2, 0x00];
+        let analysis = analyze(&rom);
+        assert_eq!(analysis.shift_vx_vy, BTreeSet::from([0x202]));
+        assert_eq!(analysis.jump_offset, BTreeSet::from([0x204]));
+    }
+
+    #[test]
+    fn the_register_set_last_picks_shift_and_jump_quirks() {
+        let rom = [
+            0x6A, 0x02, // va := 2
+            0x8A, 0xB6, // va >>= vb, after setting va
+            0x6B, 0x04, // vb := 4
+            0x8C, 0xBE, // vc <<= vb, after setting vb
+            0x6B, 0x04, // vb := 4
+            0x8A, 0xB6, // va >>= vb, after setting vb
+            0x61, 0x06, // v1 := 6
+            0xB1, 0x00, // jump0 0x100, after setting v1
+        ];
+        let analysis = analyze(&rom);
+        assert_eq!(analysis.shift_vx, BTreeSet::from([0x202]));
+        assert_eq!(analysis.shift_vy, BTreeSet::from([0x206, 0x20A]));
+        assert_eq!(analysis.jump_vx, BTreeSet::from([0x20E]));
+        let quirks = analysis.quirks();
+        assert!(!quirks.shifting);
+        assert!(quirks.jumping);
+
+        // nothing to go on keeps the platform's
+        let rom = [0x8A, 0xB6, 0xB1, 0x00];
+        assert_eq!(analyze(&rom).quirks(), Quirks::CHIP8);
+    }
+}
@@ -0,0 +1,287 @@
+// The launcher screen: a list of the ROMs in a directory, recently played
+// ones first, drawn as text at its own resolution.
+
+use crate::keymap;
+use crate::overlay::{self, TEXT_CELL_HEIGHT, TEXT_CELL_WIDTH};
+use crate::palette::Palette;
+use crate::romdb::{self, RomDatabase};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use winit::keyboard::KeyCode;
+
+pub const DEFAULT_DIR: &str = "roms";
+const RECENT_MAX: usize = 10;
+
+/// `recent.txt` in the user data directory, e.g.
+/// `~/.local/share/chip8-emulator/recent.txt` on Linux, or in the working
+/// directory on systems without one.
+pub fn recent_path() -> PathBuf {
+    match dirs::data_dir() {
+        Some(dir) => dir.join("chip8-emulator").join("recent.txt"),
+        None => PathBuf::from("recent.txt"),
+    }
+}
+
+/// Size of the frame the browser draws.
+pub const WIDTH: usize = 320;
+pub const HEIGHT: usize = 160;
+
+const COLUMNS: usize = WIDTH / TEXT_CELL_WIDTH;
+const ROWS: usize = HEIGHT / TEXT_CELL_HEIGHT;
+// title, a blank line, the list, then the help or status line
+const LIST_ROWS: usize = ROWS - 3;
+
+/// Files the browser lists.
+pub const EXTENSIONS: [&str; 7] = ["ch8", "c8", "sc8", "xo8", "8o", "gif", "bin"];
+
+/// Recently played ROMs, newest first, kept in a file of one path a line.
+#[derive(Debug, Clone, Default)]
+pub struct RecentRoms {
+    paths: Vec<PathBuf>,
+}
+
+impl RecentRoms {
+    pub fn load_or_default(path: &Path) -> Self {
+        let paths = fs::read_to_string(path)
+            .unwrap_or_default()
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(PathBuf::from)
+            .collect();
+        Self { paths }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), io::Error> {
+        let mut text = String::new();
+        for rom in &self.paths {
+            text.push_str(&rom.display().to_string());
+            text.push('\n');
+        }
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, text)
+    }
+
+    /// Move `rom` to the front, dropping the oldest past the limit.
+    pub fn add(&mut self, rom: &Path) {
+        let rom = fs::canonicalize(rom).unwrap_or_else(|_| rom.to_path_buf());
+        self.paths.retain(|path| *path != rom);
+        self.paths.insert(0, rom);
+        self.paths.truncate(RECENT_MAX);
+    }
+
+    pub fn paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RomEntry {
+    pub path: PathBuf,
+    pub title: String,
+    pub recent: bool,
+}
+
+/// What a key press in the browser asks for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BrowserAction {
+    Open(PathBuf),
+    Close,
+}
+
+pub struct Browser {
+    dir: PathBuf,
+    entries: Vec<RomEntry>,
+    selected: usize,
+    scroll: usize,
+    status: Option<String>,
+}
+
+// The database title of the ROM at `path`, or its file name.
+fn title(path: &Path, db: &RomDatabase) -> String {
+    let known = fs::read(path)
+        .ok()
+        .and_then(|data| db.lookup(&romdb::sha1_hex(&data)));
+    match known {
+        Some(info) => info.title,
+        None => path
+            .file_stem()
+            .map_or(String::new(), |stem| stem.to_string_lossy().into_owned()),
+    }
+}
+
+impl Browser {
+    /// List the ROMs in `dir` after the `recent` ones that still exist.
+    pub fn scan(dir: &Path, db: &RomDatabase, recent: &RecentRoms) -> Self {
+        let mut entries: Vec<RomEntry> = recent
+            .paths()
+            .iter()
+            .filter(|path| path.is_file())
+            .map(|path| RomEntry {
+                path: path.clone(),
+                title: title(path, db),
+                recent: true,
+            })
+            .collect();
+        let mut status = None;
+        match fs::read_dir(dir) {
+            Ok(files) => {
+                let mut found: Vec<RomEntry> = files
+                    .filter_map(|file| Some(file.ok()?.path()))
+                    .filter(|path| {
+                        path.extension().is_some_and(|ext| {
+                            EXTENSIONS
+                                .contains(&ext.to_string_lossy().to_ascii_lowercase().as_str())
+                        })
+                    })
+                    .filter(|path| {
+                        let canonical = fs::canonicalize(path).ok();
+                        !recent.paths().iter().any(|r| Some(r) == canonical.as_ref())
+                    })
+                    .map(|path| RomEntry {
+                        title: title(&path, db),
+                        path,
+                        recent: false,
+                    })
+                    .collect();
+                found.sort_by_key(|entry| entry.title.to_lowercase());
+                entries.extend(found);
+            }
+            Err(e) => status = Some(format!("{}: {}", dir.display(), e)),
+        }
+        Self {
+            dir: dir.to_path_buf(),
+            entries,
+            selected: 0,
+            scroll: 0,
+            status,
+        }
+    }
+
+    pub fn entries(&self) -> &[RomEntry] {
+        &self.entries
+    }
+
+    pub fn selected(&self) -> Option<&RomEntry> {
+        self.entries.get(self.selected)
+    }
+
+    /// Show `message` in place of the help line, e.g. why a ROM didn't load.
+    pub fn set_status(&mut self, message: impl Into<String>) {
+        self.status = Some(message.into());
+    }
+
+    fn select(&mut self, index: usize) {
+        self.selected = index.min(self.entries.len().saturating_sub(1));
+        if self.selected < self.scroll {
+            self.scroll = self.selected;
+        } else if self.selected >= self.scroll + LIST_ROWS {
+            self.scroll = self.selected + 1 - LIST_ROWS;
+        }
+    }
+
+    /// Arrows, Page Up/Down, Home and End move, a letter jumps to the next
+    /// title starting with it, Enter opens and Escape closes.
+    pub fn key(&mut self, code: KeyCode) -> Option<BrowserAction> {
+        let last = self.entries.len().saturating_sub(1);
+        match code {
+            KeyCode::ArrowUp => self.select(self.selected.saturating_sub(1)),
+            KeyCode::ArrowDown => self.select(self.selected + 1),
+            KeyCode::PageUp => self.select(self.selected.saturating_sub(LIST_ROWS)),
+            KeyCode::PageDown => self.select(self.selected + LIST_ROWS),
+            KeyCode::Home => self.select(0),
+            KeyCode::End => self.select(last),
+            KeyCode::Enter | KeyCode::NumpadEnter => {
+                return self
+                    .selected()
+                    .map(|entry| BrowserAction::Open(entry.path.clone()));
+            }
+            KeyCode::Escape => return Some(BrowserAction::Close),
+            _ => {
+                let letter = keymap::key_name(code)
+                    .and_then(|name| name.strip_prefix("Key"))
+                    .and_then(|name| name.chars().next());
+                if let Some(letter) = letter {
+                    let starts =
+                        |entry: &RomEntry| entry.title.to_ascii_uppercase().starts_with(letter);
+                    let next = (1..=self.entries.len())
+                        .map(|step| (self.selected + step) % self.entries.len())
+                        .find(|&i| starts(&self.entries[i]));
+                    if let Some(next) = next {
+                        self.select(next);
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// Draw the browser onto a `WIDTH` x `HEIGHT` RGBA frame.
+    pub fn draw(&self, frame: &mut [u8], palette: &Palette) {
+        let (background, foreground) = (palette.background(), palette.foreground());
+        for pixel in frame.chunks_exact_mut(4) {
+            pixel.copy_from_slice(&[background[0], background[1], background[2], 0xFF]);
+        }
+        let line = |frame: &mut [u8], row: usize, text: &str, color| {
+            let y = row * TEXT_CELL_HEIGHT + 1;
+            overlay::draw_text(frame, WIDTH, TEXT_CELL_WIDTH / 2, y, text, color);
+        };
+
+        line(
+            frame,
+            0,
+            &format!("CHIP-8 ROMS - {}", self.dir.display()),
+            foreground,
+        );
+        if self.entries.is_empty() {
+            line(
+                frame,
+                2,
+                "NO ROMS HERE. DROP A FILE ON THE WINDOW TO PLAY IT.",
+                foreground,
+            );
+        }
+        let visible = self
+            .entries
+            .iter()
+            .enumerate()
+            .skip(self.scroll)
+            .take(LIST_ROWS);
+        for (row, (i, entry)) in visible.enumerate() {
+            let name = entry
+                .path
+                .file_name()
+                .map_or(String::new(), |name| name.to_string_lossy().into_owned());
+            let marker = if entry.recent { '*' } else { ' ' };
+            let mut text = format!("{} {}", marker, entry.title);
+            if !name.is_empty() && entry.title != name {
+                let pad = (COLUMNS - 1).saturating_sub(text.len() + name.len() + 1);
+                text = format!("{}{} {}", text, " ".repeat(pad), name);
+            }
+            let text: String = text.chars().take(COLUMNS - 1).collect();
+            let color = if i == self.selected {
+                // inverted bar behind the selection
+                let top = (row + 2) * TEXT_CELL_HEIGHT;
+                for y in top..top + TEXT_CELL_HEIGHT {
+                    for x in 0..WIDTH {
+                        let at = (y * WIDTH + x) * 4;
+                        frame[at..at + 3].copy_from_slice(&foreground);
+                    }
+                }
+                background
+            } else {
+                foreground
+            };
+            line(frame, row + 2, &text, color);
+        }
+        let help = "UP/DOWN CHOOSE  ENTER PLAY  ESC BACK  * RECENT";
+        line(
+            frame,
+            ROWS - 1,
+            self.status.as_deref().unwrap_or(help),
+            foreground,
+        );
+    }
+}
@@ -0,0 +1,190 @@
+// Octo "cartridges": GIF images that carry a program's Octo source and its
+// emulator options. The payload is spread over the low nibble of every
+// pixel's colour index, two pixels per byte across all frames, and starts
+// with a big-endian 32-bit length followed by that many bytes of JSON:
+// `{"program": "...", "options": {...}}`.
+
+use crate::analysis::Platform;
+use crate::palette::{Palette, parse_color};
+use crate::quirks::Quirks;
+use serde::Deserialize;
+use std::io;
+
+/// The emulator options Octo saves with a program.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OctoOptions {
+    pub tickrate: Option<u32>,
+    pub background_color: Option<String>,
+    pub fill_color: Option<String>,
+    pub fill_color2: Option<String>,
+    pub blend_color: Option<String>,
+    pub shift_quirks: Option<bool>,
+    pub load_store_quirks: Option<bool>,
+    pub clip_quirks: Option<bool>,
+    pub jump_quirks: Option<bool>,
+    pub logic_quirks: Option<bool>,
+    pub max_size: Option<u32>,
+}
+
+impl OctoOptions {
+    /// The quirks the options turn on, over Octo's own behaviour.
+    pub fn quirks(&self) -> Option<Quirks> {
+        let flags = [
+            self.shift_quirks,
+            self.load_store_quirks,
+            self.clip_quirks,
+            self.jump_quirks,
+            self.logic_quirks,
+        ];
+        if flags.iter().all(Option::is_none) {
+            return None;
+        }
+        let mut quirks = Quirks::XOCHIP;
+        quirks.shifting = self.shift_quirks.unwrap_or(quirks.shifting);
+        quirks.memory = !self.load_store_quirks.unwrap_or(!quirks.memory);
+        quirks.clipping = self.clip_quirks.unwrap_or(quirks.clipping);
+        quirks.jumping = self.jump_quirks.unwrap_or(quirks.jumping);
+        quirks.vf_reset = self.logic_quirks.unwrap_or(quirks.vf_reset);
+        Some(quirks)
+    }
+
+    pub fn palette(&self) -> Option<Palette> {
+        let colors = [
+            &self.background_color,
+            &self.fill_color,
+            &self.fill_color2,
+            &self.blend_color,
+        ];
+        if colors.iter().all(|color| color.is_none()) {
+            return None;
+        }
+        let mut palette = [0, 1, 2, 3].map(|i| Palette::default().color(i));
+        for (slot, color) in palette.iter_mut().zip(colors) {
+            if let Some(color) = color.as_deref().and_then(parse_color) {
+                *slot = color;
+            }
+        }
+        Some(Palette::new("octo", palette))
+    }
+
+    /// The platform Octo's memory size setting stands for.
+    pub fn platform(&self) -> Option<Platform> {
+        match self.max_size? {
+            0..=3232 => Some(Platform::Chip8),
+            3233..=3583 => Some(Platform::Schip),
+            _ => Some(Platform::XoChip),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Cartridge {
+    pub program: String, // Octo source
+    #[serde(default)]
+    pub options: OctoOptions,
+}
+
+impl Cartridge {
+    /// Decode the cartridge GIF `data`.
+    pub fn decode(data: &[u8]) -> Result<Self, io::Error> {
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = options.read_info(data).map_err(io::Error::other)?;
+        let mut nibbles = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().map_err(io::Error::other)? {
+            nibbles.extend(frame.buffer.iter().map(|index| index & 0xF));
+        }
+        let bytes: Vec<u8> = nibbles
+            .chunks_exact(2)
+            .map(|pair| pair[0] << 4 | pair[1])
+            .collect();
+
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "not an Octo cartridge");
+        let size = u32::from_be_bytes(bytes.get(..4).ok_or_else(invalid)?.try_into().unwrap());
+        let json = bytes.get(4..4 + size as usize).ok_or_else(invalid)?;
+        serde_json::from_slice(json).map_err(|_| invalid())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::borrow::Cow;
+
+    // `payload` packed into a GIF the way Octo does it, split over two
+    // frames and with the label's colours in the high nibbles
+    fn gif(payload: &[u8]) -> Vec<u8> {
+        let mut pixels: Vec<u8> = payload.iter().flat_map(|b| [b >> 4, b & 0xF]).collect();
+        let width = 16;
+        let height = pixels.len().div_ceil(width * 2) as u16;
+        pixels.resize(width * height as usize * 2, 0);
+        for (i, pixel) in pixels.iter_mut().enumerate() {
+            *pixel |= (i as u8 % 3) << 4;
+        }
+        let palette: Vec<u8> = (0..48).flat_map(|i| [i as u8 * 5; 3]).collect();
+        let mut data = Vec::new();
+        let mut encoder = gif::Encoder::new(&mut data, width as u16, height, &palette).unwrap();
+        for half in pixels.chunks(pixels.len() / 2) {
+            let frame = gif::Frame {
+                width: width as u16,
+                height,
+                buffer: Cow::Borrowed(half),
+                ..Default::default()
+            };
+            encoder.write_frame(&frame).unwrap();
+        }
+        drop(encoder);
+        data
+    }
+
+    fn cartridge(json: &str) -> Vec<u8> {
+        let mut payload = (json.len() as u32).to_be_bytes().to_vec();
+        payload.extend_from_slice(json.as_bytes());
+        gif(&payload)
+    }
+
+    #[test]
+    fn program_and_options_decode() {
+        let json = r##"{
+            "program": ": main\n  loop again\n",
+            "options": {
+                "tickrate": 20,
+                "fillColor": "#FF0000",
+                "shiftQuirks": true,
+                "loadStoreQuirks": true,
+                "maxSize": 3584
+            }
+        }"##;
+        let cartridge = Cartridge::decode(&cartridge(json)).unwrap();
+        assert_eq!(cartridge.program, ": main\n  loop again\n");
+        let options = &cartridge.options;
+        assert_eq!(options.tickrate, Some(20));
+        assert_eq!(options.platform(), Some(Platform::XoChip));
+
+        let quirks = options.quirks().unwrap();
+        assert!(quirks.shifting);
+        assert!(!quirks.memory);
+        assert_eq!(quirks.jumping, Quirks::XOCHIP.jumping);
+
+        let palette = options.palette().unwrap();
+        assert_eq!(palette.color(1), [0xFF, 0, 0]);
+        assert_eq!(palette.color(0), Palette::default().color(0));
+    }
+
+    #[test]
+    fn options_are_optional() {
+        let cartridge = Cartridge::decode(&cartridge(r#"{"program": ": main"}"#)).unwrap();
+        assert!(cartridge.options.quirks().is_none());
+        assert!(cartridge.options.palette().is_none());
+        assert!(cartridge.options.platform().is_none());
+    }
+
+    #[test]
+    fn other_images_are_refused() {
+        assert!(Cartridge::decode(&cartridge("not json")).is_err());
+        assert!(Cartridge::decode(b"GIF89a").is_err());
+        // a length running past the end of the image
+        assert!(Cartridge::decode(&gif(&[0, 0, 0x10, 0, b'{', b'}'])).is_err());
+    }
+}
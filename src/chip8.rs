@@ -0,0 +1,712 @@
+use crate::font::Font;
+use crate::framebuffer::{FrameBuffer, Resolution};
+use crate::input::InputHandler;
+use crate::quirks::Quirks;
+use crate::random::{RandomSource, SplitMix64};
+use crate::rom::RomImage;
+use crate::romdb;
+use sha1::{Digest, Sha1};
+use std::io;
+use std::sync::{Arc, Mutex};
+use winit::event::{ElementState, KeyEvent};
+use winit::keyboard::PhysicalKey;
+
+pub const MEMORY_SIZE: usize = 4096;
+pub const PROGRAM_START_LOC: usize = 0x200;
+pub const MAX_ROM_SIZE: usize = MEMORY_SIZE - PROGRAM_START_LOC;
+
+pub const FONT_START_LOC: usize = 0x50;
+
+pub const FONTSET: [u8; 80] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+    0x20, 0x60, 0x20, 0x20, 0x70, // 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+    0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+    0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+    0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+    0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+    0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+];
+
+pub struct Chip8 {
+    registers: [u8; 16],
+    i: u16,
+    pc: u16,          // Program counter
+    sp: u16,          // Stack pointer
+    stack: [u16; 16], // Stack for storing return addresses
+    delay_timer: u8,
+    sound_timer: u8,
+    // keypad: [bool; 16],
+    memory: [u8; 4096],
+    rpl_flags: [u8; 16], // SCHIP user flags, `Fx75`/`Fx85`; survive resets
+
+    pub input_handler: InputHandler,
+
+    draw_flag: bool,
+    frame_buffer: Arc<Mutex<FrameBuffer>>,
+
+    quirks: Quirks,
+    font: Font,
+    key_wait: Option<KeyWait>,
+    rng: Box<dyn RandomSource>,
+    seed: u64,
+    rom_sha1: String,
+    rom: Vec<u8>, // as loaded, for hard resets
+}
+
+// An Fx0A waiting for a key to be pressed and released.
+#[derive(Clone, Copy)]
+struct KeyWait {
+    register: u8,
+    pressed: u16, // keys that went down since the wait started
+}
+
+fn nibble(value: &u16, n: u8) -> u8 {
+    ((value >> (n * 4)) & 0xF) as u8
+}
+
+impl Chip8 {
+    pub fn new_with_buffer(buffer: Arc<Mutex<FrameBuffer>>) -> Self {
+        let seed = rand::random();
+        Self {
+            registers: [0x0; 16],
+            i: 0x0,
+            pc: 0x0,
+            sp: 0x0,
+            stack: [0x0; 16],
+            delay_timer: 0x0,
+            sound_timer: 0x0,
+            // keypad: [false; 16],
+            input_handler: InputHandler::new(),
+            memory: [0x0; 4096],
+            rpl_flags: [0x0; 16],
+            draw_flag: false,
+            frame_buffer: buffer,
+            quirks: Quirks::default(),
+            font: Font::default(),
+            key_wait: None,
+            rng: Box::new(SplitMix64::new(seed)),
+            seed,
+            rom_sha1: String::new(),
+            rom: Vec::new(),
+        }
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    pub fn memory(&self) -> &[u8; MEMORY_SIZE] {
+        &self.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut [u8; MEMORY_SIZE] {
+        &mut self.memory
+    }
+
+    /// The SCHIP user flags, which the HP-48 kept between programs.
+    pub fn rpl_flags(&self) -> &[u8; 16] {
+        &self.rpl_flags
+    }
+
+    pub fn set_rpl_flags(&mut self, flags: [u8; 16]) {
+        self.rpl_flags = flags;
+    }
+
+    pub fn font(&self) -> &Font {
+        &self.font
+    }
+
+    /// Switch to `font`, loading it into memory straight away so a running
+    /// program picks it up on its next `Fx29`. The old font's bytes are
+    /// cleared, so memory only ever holds the current font.
+    pub fn set_font(&mut self, font: Font) {
+        let old = self.font.address() as usize;
+        self.memory[old..old + self.font.glyphs().len()].fill(0);
+        self.font = font;
+        self.load_font();
+    }
+
+    fn load_font(&mut self) {
+        let start = self.font.address() as usize;
+        self.memory[start..start + self.font.glyphs().len()].copy_from_slice(self.font.glyphs());
+    }
+
+    /// Restart the random number generator used by `Cxkk` from `seed`.
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.seed = seed;
+        self.rng.reseed(seed);
+    }
+
+    /// Replace the random number generator used by `Cxkk`. The new one is
+    /// started from the current seed.
+    pub fn set_rng(&mut self, mut rng: Box<dyn RandomSource>) {
+        rng.reseed(self.seed);
+        self.rng = rng;
+    }
+
+    /// The seed the random number generator was last started from.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Lowercase hex SHA-1 of the loaded ROM, empty before `load_rom`.
+    pub fn rom_sha1(&self) -> &str {
+        &self.rom_sha1
+    }
+
+    /// The ROM image as loaded at 0x200, empty before `load_rom`.
+    pub fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    /// Lowercase hex SHA-1 over registers, timers, stack, memory and the
+    /// display. Two runs that end with the same hash are in the same state.
+    pub fn state_hash(&self) -> String {
+        let mut hasher = Sha1::new();
+        hasher.update(self.registers);
+        hasher.update(self.i.to_be_bytes());
+        hasher.update(self.pc.to_be_bytes());
+        hasher.update(self.sp.to_be_bytes());
+        for addr in self.stack {
+            hasher.update(addr.to_be_bytes());
+        }
+        hasher.update([self.delay_timer, self.sound_timer]);
+        hasher.update(self.memory);
+        let screen = self.frame_buffer.lock().unwrap();
+        for y in 0..screen.height() {
+            hasher.update(screen.row_bits(y).to_be_bytes());
+        }
+        format!("{:x}", hasher.finalize())
+    }
+
+    pub fn init(&mut self) {
+        self.pc = PROGRAM_START_LOC as u16;
+        self.sp = 0x0;
+
+        self.load_font();
+    }
+
+    /// Restart the program: registers, timers, stack and display are
+    /// cleared and execution starts over at 0x200, but memory, font area
+    /// included, is kept as the program left it.
+    pub fn soft_reset(&mut self) {
+        self.registers = [0; 16];
+        self.i = 0;
+        self.stack = [0; 16];
+        self.delay_timer = 0;
+        self.sound_timer = 0;
+        self.key_wait = None;
+        self.draw_flag = false;
+        self.frame_buffer
+            .lock()
+            .unwrap()
+            .set_resolution(Resolution::Lores);
+        self.pc = PROGRAM_START_LOC as u16;
+        self.sp = 0x0;
+    }
+
+    /// Power cycle: memory is wiped and the fonts and ROM loaded again on
+    /// top of a soft reset. The random numbers start over from the seed.
+    pub fn hard_reset(&mut self) {
+        self.memory = [0; MEMORY_SIZE];
+        self.memory[PROGRAM_START_LOC..PROGRAM_START_LOC + self.rom.len()]
+            .copy_from_slice(&self.rom);
+        self.load_font();
+        self.rng.reseed(self.seed);
+        self.soft_reset();
+    }
+
+    /// Load the ROM at `path`, see `RomImage::load` for the formats read.
+    pub fn load_rom(&mut self, path: String) -> Result<(), io::Error> {
+        self.load_rom_data(&RomImage::load(&path)?.data)
+    }
+
+    /// Load a raw ROM image at 0x200.
+    pub fn load_rom_data(&mut self, rom_data: &[u8]) -> Result<(), io::Error> {
+        if rom_data.len() > MAX_ROM_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "ROM file too large",
+            ));
+        }
+
+        for (i, &byte) in rom_data.iter().enumerate() {
+            self.memory[PROGRAM_START_LOC + i] = byte;
+        }
+        self.rom_sha1 = romdb::sha1_hex(rom_data);
+        self.rom = rom_data.to_vec();
+        println!("Loaded ROM: {} bytes", rom_data.len());
+        Ok(())
+    }
+
+    pub fn handle_input(&mut self, event: KeyEvent) {
+        if event.repeat {
+            return;
+        }
+        if let PhysicalKey::Code(key_code) = event.physical_key {
+            match event.state {
+                ElementState::Pressed => {
+                    self.input_handler.key_pressed(key_code);
+                }
+                ElementState::Released => {
+                    self.input_handler.key_released(key_code);
+                }
+            }
+        }
+    }
+
+    pub fn update_timer(&mut self) {
+        if self.delay_timer > 0 {
+            self.delay_timer -= 1;
+        }
+
+        if self.sound_timer > 0 {
+            self.sound_timer -= 1;
+        }
+    }
+
+    /// True while the buzzer should sound.
+    pub fn sound_active(&self) -> bool {
+        self.sound_timer > 0
+    }
+
+    pub fn cycle(&mut self) {
+        // one key event per instruction, so a tap shorter than an
+        // instruction still shows up for one
+        if let Some(event) = self.input_handler.next_event()
+            && let Some(wait) = &mut self.key_wait
+        {
+            let bit = 1 << event.key;
+            if event.pressed {
+                wait.pressed |= bit;
+            } else if wait.pressed & bit != 0 {
+                // like the COSMAC VIP, Fx0A finishes when the key is let go
+                let register = wait.register;
+                self.key_wait = None;
+                self.register(register, event.key);
+            }
+        }
+        if self.key_wait.is_some() {
+            return;
+        }
+
+        let opcode: u16 = self.fetch();
+
+        self.pc += 2;
+
+        //decode and execute
+        self.decode_and_execute(opcode);
+    }
+    fn fetch(&self) -> u16 {
+        (self.memory[self.pc as usize] as u16) << 0x8
+            | (self.memory[(self.pc + 0x1) as usize]) as u16
+    }
+    fn decode_and_execute(&mut self, inst: u16) {
+        let _opcode: u16 = (inst >> 0xC) & 0xF;
+        match _opcode {
+            0x0 => {
+                match inst & 0x0FFF {
+                    0x00E0 => {
+                        // clear screen
+                        {
+                            self.frame_buffer.lock().unwrap().clear();
+                            self.draw_flag = true;
+                        }
+                    }
+                    0x00EE => {
+                        // return from subroutine
+                        self.pc = match self.pop() {
+                            Some(addr) => addr,
+                            None => {
+                                print!("Stack underflow");
+                                return;
+                            }
+                        };
+                    }
+                    _ => (),
+                }
+            }
+            0x1 => {
+                let index: u16 = inst & 0x0FFF;
+                self.pc = index;
+            }
+            0x2 => {
+                let index: u16 = inst & 0x0FFF;
+                if let Err(e) = self.push(self.pc) {
+                    print!("{}", e)
+                }
+                self.pc = index;
+            }
+            0x3 => {
+                let x: u8 = nibble(&inst, 2);
+                let vx: u8 = self.get_register_data(&x);
+                let kk: u8 = inst as u8;
+                if vx == kk {
+                    self.pc += 2;
+                }
+            }
+            0x4 => {
+                let x: u8 = nibble(&inst, 2);
+                let vx: u8 = self.get_register_data(&x);
+                let kk: u8 = inst as u8;
+                if vx != kk {
+                    self.pc += 2;
+                }
+            }
+            0x5 => {
+                let x: u8 = nibble(&inst, 2);
+                let vx: u8 = self.get_register_data(&x);
+                let y: u8 = nibble(&inst, 1);
+                let vy: u8 = self.get_register_data(&y);
+                if vx == vy {
+                    self.pc += 2;
+                }
+            }
+            0x6 => {
+                let x: u8 = nibble(&inst, 2);
+                let kk: u8 = inst as u8;
+                self.register(x, kk);
+            }
+            0x7 => {
+                let x: u8 = nibble(&inst, 2);
+                let vx: u8 = self.get_register_data(&x);
+                let kk: u8 = inst as u8;
+                self.register(x, vx.wrapping_add(kk));
+            }
+            0x8 => {
+                let x: u8 = nibble(&inst, 2);
+                let vx: u8 = self.get_register_data(&x);
+                let y: u8 = nibble(&inst, 1);
+                let vy: u8 = self.get_register_data(&y);
+
+                let indic: u8 = nibble(&inst, 0);
+                match indic {
+                    0x0 => {
+                        self.register(x, vy);
+                    }
+                    0x1 => {
+                        self.register(x, vx | vy);
+                        if self.quirks.vf_reset {
+                            self.register(0xF, 0x0);
+                        }
+                    }
+                    0x2 => {
+                        self.register(x, vx & vy);
+                        if self.quirks.vf_reset {
+                            self.register(0xF, 0x0);
+                        }
+                    }
+                    0x3 => {
+                        self.register(x, vx ^ vy);
+                        if self.quirks.vf_reset {
+                            self.register(0xF, 0x0);
+                        }
+                    }
+                    // the flag is written last so it wins when x is F
+                    0x4 => {
+                        let (sum, carry) = vx.overflowing_add(vy);
+                        self.register(x, sum);
+                        self.register(0xF, carry as u8);
+                    }
+                    0x5 => {
+                        let (difference, borrow) = vx.overflowing_sub(vy);
+                        self.register(x, difference);
+                        self.register(0xF, !borrow as u8);
+                    }
+                    0x6 => {
+                        let src: u8 = if self.quirks.shifting { vx } else { vy };
+                        self.register(x, src >> 0x1);
+                        self.register(0xF, src & 0x1);
+                    }
+                    0x7 => {
+                        let (difference, borrow) = vy.overflowing_sub(vx);
+                        self.register(x, difference);
+                        self.register(0xF, !borrow as u8);
+                    }
+                    0xE => {
+                        let src: u8 = if self.quirks.shifting { vx } else { vy };
+                        self.register(x, src << 1);
+                        self.register(0xF, (src >> 7) & 0x1);
+                    }
+                    _ => (),
+                }
+            }
+            0x9 => {
+                let x: u8 = nibble(&inst, 2);
+                let vx: u8 = self.get_register_data(&x);
+                let y: u8 = nibble(&inst, 1);
+                let vy: u8 = self.get_register_data(&y);
+
+                if vx != vy {
+                    self.pc += 2;
+                }
+            }
+            0xA => {
+                let n: u16 = inst & 0x0FFF;
+                self.i = n;
+            }
+            0xB => {
+                let n: u16 = inst & 0x0FFF;
+                let regi: u8 = if self.quirks.jumping {
+                    nibble(&inst, 2)
+                } else {
+                    0x0
+                };
+                let offset: u8 = self.get_register_data(&regi);
+                self.pc = n + offset as u16;
+            }
+            0xC => {
+                let x: u8 = nibble(&inst, 2);
+                let kk: u8 = inst as u8;
+                let ran_num: u8 = self.rng.next_byte();
+
+                self.register(x, ran_num & kk);
+            }
+            0xD => {
+                let x: u8 = nibble(&inst, 2);
+                let vx = self.get_register_data(&x) as usize;
+                let y: u8 = nibble(&inst, 1);
+                let vy = self.get_register_data(&y) as usize;
+
+                let n = (inst & 0x000F) as usize;
+                let i = self.i as usize;
+
+                // a sprite that runs off the end of memory wraps to the start
+                let mut sprite = [0u8; 0xF];
+                for (row, byte) in sprite[..n].iter_mut().enumerate() {
+                    *byte = self.memory[(i + row) % MEMORY_SIZE];
+                }
+                let collision = self.frame_buffer.lock().unwrap().draw_sprite(
+                    vx,
+                    vy,
+                    &sprite[..n],
+                    self.quirks.clipping,
+                );
+                self.draw_flag = true;
+                self.register(0xF, collision as u8);
+            }
+            0xE => {
+                let x: u8 = nibble(&inst, 2);
+                let vx: u8 = self.get_register_data(&x);
+
+                let indic: u16 = inst & 0x00FF;
+
+                let is_press = self.input_handler.is_key_pressed(vx);
+                match indic {
+                    0x9E if is_press => {
+                        self.pc += 2;
+                    }
+                    0xA1 if !is_press => {
+                        self.pc += 2;
+                    }
+                    _ => (),
+                }
+            }
+            0xF => {
+                let x: u8 = nibble(&inst, 2);
+                let vx: u8 = self.get_register_data(&x);
+
+                let indic: u16 = inst & 0x00FF;
+                match indic {
+                    0x7 => {
+                        self.register(x, self.delay_timer);
+                    }
+                    0xA => {
+                        // keys already down don't count, see `cycle`
+                        self.key_wait = Some(KeyWait {
+                            register: x,
+                            pressed: 0,
+                        });
+                    }
+                    0x15 => {
+                        self.delay_timer = vx;
+                    }
+                    0x18 => {
+                        self.sound_timer = vx;
+                    }
+                    0x1E => {
+                        self.i += vx as u16;
+                    }
+                    0x29 => {
+                        self.i = self.font.address() + (vx as u16 * 5);
+                    }
+                    0x33 => {
+                        self.memory[self.i as usize] = vx / 100; // 100
+                        self.memory[(self.i + 1) as usize] = (vx / 10) % 10; // 10
+                        self.memory[(self.i + 2) as usize] = vx % 10; // 1
+                    }
+                    0x55 => {
+                        let start = self.i;
+                        for v in self.registers.iter().take((x + 1) as usize) {
+                            self.memory[self.i as usize] = *v;
+                            self.i += 1;
+                        }
+                        if !self.quirks.memory {
+                            self.i = start;
+                        }
+                    }
+                    0x65 => {
+                        let start = self.i;
+                        for v in 0..x + 1 {
+                            self.register(v, self.memory[self.i as usize]);
+                            self.i += 1;
+                        }
+                        if !self.quirks.memory {
+                            self.i = start;
+                        }
+                    }
+                    0x75 => {
+                        let count = x as usize + 1;
+                        self.rpl_flags[..count].copy_from_slice(&self.registers[..count]);
+                    }
+                    0x85 => {
+                        let count = x as usize + 1;
+                        self.registers[..count].copy_from_slice(&self.rpl_flags[..count]);
+                    }
+                    _ => (),
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn get_register_data(&self, regi: &u8) -> u8 {
+        self.registers[*regi as usize]
+    }
+
+    fn register(&mut self, regi: u8, data: u8) {
+        self.registers[regi as usize] = data;
+    }
+
+    fn push(&mut self, data: u16) -> Result<(), &'static str> {
+        if self.sp as usize >= self.stack.len() {
+            return Err("Stack Overflow");
+        }
+        self.stack[self.sp as usize] = data;
+        self.sp += 1;
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Option<u16> {
+        if self.sp == 0 {
+            return None; // Stack underflow
+        }
+        self.sp -= 1;
+        Some(self.stack[self.sp as usize])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // run `program` one instruction per opcode
+    fn run(program: &[u16]) -> Chip8 {
+        let rom: Vec<u8> = program.iter().flat_map(|op| op.to_be_bytes()).collect();
+        let mut chip8 = Chip8::new_with_buffer(Arc::new(Mutex::new(FrameBuffer::new())));
+        chip8.init();
+        chip8.load_rom_data(&rom).unwrap();
+        for _ in program {
+            chip8.cycle();
+        }
+        chip8
+    }
+
+    #[test]
+    fn add_stores_the_sum_and_the_carry() {
+        let chip8 = run(&[0x6005, 0x6103, 0x8014]);
+        assert_eq!(chip8.registers[0x0], 8);
+        assert_eq!(chip8.registers[0xF], 0);
+
+        let chip8 = run(&[0x60FF, 0x6103, 0x8014]);
+        assert_eq!(chip8.registers[0x0], 2);
+        assert_eq!(chip8.registers[0xF], 1);
+    }
+
+    #[test]
+    fn subtract_stores_the_difference_and_no_borrow() {
+        let chip8 = run(&[0x6005, 0x6103, 0x8015, 0x6203, 0x6305, 0x8237]);
+        assert_eq!(chip8.registers[0x0], 2);
+        assert_eq!(chip8.registers[0x2], 2);
+        assert_eq!(chip8.registers[0xF], 1);
+
+        let chip8 = run(&[0x6003, 0x6103, 0x8015]);
+        assert_eq!(chip8.registers[0x0], 0);
+        assert_eq!(chip8.registers[0xF], 1);
+
+        let chip8 = run(&[0x6003, 0x6105, 0x8015]);
+        assert_eq!(chip8.registers[0x0], 0xFE);
+        assert_eq!(chip8.registers[0xF], 0);
+
+        let chip8 = run(&[0x6005, 0x6103, 0x8017]);
+        assert_eq!(chip8.registers[0x0], 0xFE);
+        assert_eq!(chip8.registers[0xF], 0);
+    }
+
+    #[test]
+    fn the_flag_wins_over_a_result_in_vf() {
+        assert_eq!(run(&[0x6FFF, 0x6101, 0x8F14]).registers[0xF], 1);
+        assert_eq!(run(&[0x6F01, 0x6102, 0x8F15]).registers[0xF], 0);
+        assert_eq!(run(&[0x6F02, 0x6101, 0x8F17]).registers[0xF], 0);
+        assert_eq!(run(&[0x6F81, 0x8FF6]).registers[0xF], 1);
+    }
+
+    #[test]
+    fn skips_move_past_one_instruction() {
+        // 9xy0 and 5xy0 on registers, 3xkk and 4xkk on a byte
+        let chip8 = run(&[0x6001, 0x6102, 0x9010, 0x6201, 0x6301]);
+        assert_eq!(chip8.registers[0x2..0x4], [0, 1]);
+        assert_eq!(chip8.pc, 0x20C);
+
+        let chip8 = run(&[0x6001, 0x6101, 0x9010, 0x6201]);
+        assert_eq!(chip8.registers[0x2], 1);
+
+        let chip8 = run(&[
+            0x6001, 0x6101, 0x5010, 0x6201, 0x3001, 0x6301, 0x4001, 0x6401,
+        ]);
+        assert_eq!(chip8.registers[0x2..0x5], [0, 0, 1]);
+    }
+
+    #[test]
+    fn bcd_and_register_stores_use_memory_at_i() {
+        let mut chip8 = run(&[0x60FE, 0xA300, 0xF033, 0xF265]);
+        assert_eq!(chip8.memory[0x300..0x303], [2, 5, 4]);
+        assert_eq!(chip8.registers[0x0..0x3], [2, 5, 4]);
+        // I moves past the last register, unless the quirk is off
+        assert_eq!(chip8.i, 0x303);
+
+        chip8.set_quirks(Quirks::SCHIP);
+        chip8.load_rom_data(&[0xF1, 0x55]).unwrap();
+        chip8.pc = PROGRAM_START_LOC as u16;
+        chip8.cycle();
+        assert_eq!(chip8.memory[0x303..0x305], [2, 5]);
+        assert_eq!(chip8.i, 0x303);
+    }
+
+    #[test]
+    fn a_sprite_past_the_end_of_memory_wraps() {
+        let mut chip8 = run(&[0xAFFF]);
+        chip8.memory[0xFFF] = 0x80;
+        chip8.memory[0x000] = 0x80;
+        chip8.load_rom_data(&[0xD0, 0x02]).unwrap();
+        chip8.pc = PROGRAM_START_LOC as u16;
+        chip8.cycle();
+
+        let frame_buffer = chip8.frame_buffer.lock().unwrap();
+        assert!(frame_buffer.pixel(0, 0));
+        assert!(frame_buffer.pixel(0, 1));
+    }
+}
@@ -0,0 +1,322 @@
+// The TOML configuration file. Every key is the name of a command line
+// option: `palette = "green"` means `--palette green`, `keypad = true` means
+// `--keypad` and an array repeats the option. Top-level keys are defaults,
+// `[profiles.<name>]` tables are named sets of settings and
+// `[roms.<sha1>]` tables apply to one ROM. A profile is picked by
+// `config-profile`, from the command line, the ROM's table or the top level.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use toml::{Table, Value};
+
+pub const FILE_NAME: &str = "config.toml";
+pub const PROFILE_KEY: &str = "config-profile";
+
+/// `config.toml` in the user configuration directory, e.g.
+/// `~/.config/chip8-emulator/config.toml` on Linux.
+pub fn default_path() -> Option<PathBuf> {
+    Some(dirs::config_dir()?.join("chip8-emulator").join(FILE_NAME))
+}
+
+/// One merged setting and the layer it came from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Setting {
+    pub key: String,
+    pub value: Value,
+    pub source: String, // "global", "profile <name>" or "rom <sha1>"
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    path: Option<PathBuf>,
+    global: Table,
+    profiles: BTreeMap<String, Table>,
+    roms: BTreeMap<String, Table>,
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// `font_address` is accepted for `font-address`
+fn option_name(key: &str) -> String {
+    key.replace('_', "-")
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self, io::Error> {
+        let text = fs::read_to_string(path)?;
+        let mut config =
+            Self::parse(&text).map_err(|e| invalid(format!("{}: {}", path.display(), e)))?;
+        config.path = Some(path.to_path_buf());
+        Ok(config)
+    }
+
+    /// Load `path`, or an empty config if there is no such file.
+    pub fn load_or_default(path: &Path) -> Result<Self, io::Error> {
+        if path.exists() {
+            Self::load(path)
+        } else {
+            Ok(Self::default())
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, io::Error> {
+        let mut global: Table = text
+            .parse()
+            .map_err(|e: toml::de::Error| invalid(e.message().to_string()))?;
+        let mut sections = |name: &str| -> Result<BTreeMap<String, Table>, io::Error> {
+            let Some(value) = global.remove(name) else {
+                return Ok(BTreeMap::new());
+            };
+            let Value::Table(table) = value else {
+                return Err(invalid(format!("{} must be a table", name)));
+            };
+            table
+                .into_iter()
+                .map(|(key, value)| match value {
+                    Value::Table(section) => Ok((key, section)),
+                    _ => Err(invalid(format!("{}.{} must be a table", name, key))),
+                })
+                .collect()
+        };
+        let profiles = sections("profiles")?;
+        let roms = sections("roms")?
+            .into_iter()
+            .map(|(sha1, table)| (sha1.to_ascii_lowercase(), table))
+            .collect();
+        Ok(Self {
+            path: None,
+            global,
+            profiles,
+            roms,
+        })
+    }
+
+    /// The file this was loaded from, if any.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Whether there is a table for the ROM with `sha1`.
+    pub fn has_rom(&self, sha1: &str) -> bool {
+        self.roms.contains_key(&sha1.to_ascii_lowercase())
+    }
+
+    pub fn profile_names(&self) -> Vec<String> {
+        self.profiles.keys().cloned().collect()
+    }
+
+    /// The settings for the ROM with `rom_sha1`, or for no particular ROM:
+    /// the top level, then the profile, then the ROM's table, later ones
+    /// replacing earlier ones. `profile` picks the profile over whatever the
+    /// file says.
+    pub fn settings(
+        &self,
+        rom_sha1: Option<&str>,
+        profile: Option<&str>,
+    ) -> Result<Vec<Setting>, io::Error> {
+        let rom = rom_sha1.and_then(|sha1| {
+            let sha1 = sha1.to_ascii_lowercase();
+            self.roms.get(&sha1).map(|table| (sha1, table))
+        });
+        let profile_name = |table: &Table| match table.get(PROFILE_KEY) {
+            Some(Value::String(name)) => Some(name.clone()),
+            _ => None,
+        };
+        let profile = profile
+            .map(str::to_string)
+            .or_else(|| profile_name(rom.as_ref()?.1))
+            .or_else(|| profile_name(&self.global));
+
+        let mut layers = vec![("global".to_string(), &self.global)];
+        if let Some(name) = profile {
+            let table = self.profiles.get(&name).ok_or_else(|| {
+                let names = self.profile_names().join(", ");
+                invalid(format!("unknown config profile {}, have: {}", name, names))
+            })?;
+            layers.push((format!("profile {}", name), table));
+        }
+        if let Some((sha1, table)) = &rom {
+            layers.push((format!("rom {}", sha1), table));
+        }
+
+        let mut settings: Vec<Setting> = Vec::new();
+        for (source, table) in layers {
+            for (key, value) in table {
+                let key = option_name(key);
+                if key == PROFILE_KEY {
+                    continue;
+                }
+                if matches!(value, Value::Table(_)) {
+                    return Err(invalid(format!("{}: {} can't be a table", source, key)));
+                }
+                settings.retain(|setting| setting.key != key);
+                settings.push(Setting {
+                    key,
+                    value: value.clone(),
+                    source: source.clone(),
+                });
+            }
+        }
+        Ok(settings)
+    }
+
+    /// `settings` as command line options.
+    pub fn args(
+        &self,
+        rom_sha1: Option<&str>,
+        profile: Option<&str>,
+    ) -> Result<Vec<String>, io::Error> {
+        let mut args = Vec::new();
+        for setting in self.settings(rom_sha1, profile)? {
+            let option = format!("--{}", setting.key);
+            let values = match setting.value {
+                Value::Array(values) => values,
+                value => vec![value],
+            };
+            for value in values {
+                match value {
+                    Value::Boolean(true) => args.push(option.clone()),
+                    Value::Boolean(false) => {}
+                    Value::String(text) => args.extend([option.clone(), text]),
+                    Value::Integer(_) | Value::Float(_) => {
+                        args.extend([option.clone(), value.to_string()])
+                    }
+                    _ => {
+                        return Err(invalid(format!(
+                            "{}: unsupported value {}",
+                            setting.key, value
+                        )));
+                    }
+                }
+            }
+        }
+        Ok(args)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHA1: &str = "0123456789abcdef0123456789abcdef01234567";
+
+    fn config() -> Config {
+        Config::parse(&format!(
+            r#"
+            palette = "green"
+            speed = 700
+            keypad = true
+            config-profile = "slow"
+
+            [profiles.slow]
+            speed = 300
+            font_address = "0x50"
+
+            [profiles.fast]
+            speed = 2000
+            keypad = false
+
+            [roms.{}]
+            palette = "amber"
+            patch = ["a.ips", "b.bps"]
+            config-profile = "fast"
+            "#,
+            SHA1.to_ascii_uppercase()
+        ))
+        .unwrap()
+    }
+
+    fn value<'a>(settings: &'a [Setting], key: &str) -> (&'a Value, &'a str) {
+        let setting = settings.iter().find(|s| s.key == key).unwrap();
+        (&setting.value, &setting.source)
+    }
+
+    #[test]
+    fn later_layers_win() {
+        let config = config();
+        let settings = config.settings(None, None).unwrap();
+        assert_eq!(
+            value(&settings, "speed"),
+            (&Value::Integer(300), "profile slow")
+        );
+        assert_eq!(
+            value(&settings, "palette"),
+            (&Value::String("green".into()), "global")
+        );
+        assert!(settings.iter().all(|s| s.key != PROFILE_KEY));
+
+        // the ROM's table picks its own profile
+        let settings = config.settings(Some(SHA1), None).unwrap();
+        assert_eq!(
+            value(&settings, "speed"),
+            (&Value::Integer(2000), "profile fast")
+        );
+        let rom = format!("rom {}", SHA1);
+        assert_eq!(
+            value(&settings, "palette"),
+            (&Value::String("amber".into()), rom.as_str())
+        );
+    }
+
+    #[test]
+    fn the_command_line_profile_wins() {
+        let settings = config().settings(Some(SHA1), Some("slow")).unwrap();
+        assert_eq!(
+            value(&settings, "speed"),
+            (&Value::Integer(300), "profile slow")
+        );
+        assert!(config().settings(None, Some("missing")).is_err());
+    }
+
+    #[test]
+    fn settings_become_options() {
+        // in layer order, and `keypad = false` from the profile drops it
+        let args = config().args(Some(SHA1), None).unwrap();
+        assert_eq!(
+            args,
+            [
+                "--speed",
+                "2000",
+                "--palette",
+                "amber",
+                "--patch",
+                "a.ips",
+                "--patch",
+                "b.bps"
+            ]
+        );
+        let args = config().args(None, None).unwrap();
+        assert!(args.contains(&"--keypad".to_string()));
+        assert!(args.contains(&"--font-address".to_string()));
+    }
+
+    #[test]
+    fn rom_tables_match_any_case() {
+        let config = config();
+        assert!(config.has_rom(SHA1));
+        assert!(config.has_rom(&SHA1.to_ascii_uppercase()));
+        assert!(!config.has_rom("ffff"));
+    }
+
+    #[test]
+    fn bad_files_are_refused() {
+        for text in [
+            "speed = ",
+            "profiles = 1",
+            "[profiles]\nslow = 1",
+            "[roms]\nabc = \"x\"",
+        ] {
+            assert!(Config::parse(text).is_err(), "{:?}", text);
+        }
+        let nested = Config::parse("[profiles.p.inner]\nx = 1").unwrap();
+        assert!(nested.settings(None, Some("p")).is_err());
+        let table = Config::parse("speed = { a = 1 }").unwrap();
+        assert!(table.settings(None, None).is_err());
+        let date = Config::parse("when = 1979-05-27").unwrap();
+        assert!(date.args(None, None).is_err());
+    }
+}
@@ -0,0 +1,275 @@
+// A disassembler that writes Octo statements, the same syntax `octo`
+// assembles, so the listing assembles back into the same ROM. Only code
+// `analysis` can reach is decoded; everything else is listed as bytes.
+
+use crate::analysis;
+use crate::chip8::PROGRAM_START_LOC;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+
+// bytes per line of data
+const DATA_WIDTH: usize = 4;
+
+/// One line of the listing: an instruction, or a run of data bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub label: Option<String>, // when something jumps or calls here
+    pub text: String,
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(label) = &self.label {
+            writeln!(f, ": {}", label)?;
+        }
+        let hex: String = self.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        write!(f, "  {:<24}  # {:03x}  {}", self.text, self.address, hex)
+    }
+}
+
+// the start is `main`, where Octo begins
+fn label_name(address: u16) -> String {
+    if address as usize == PROGRAM_START_LOC {
+        return "main".to_string();
+    }
+    format!("label-{:03x}", address)
+}
+
+// bytes `op` takes up
+fn size(op: u16) -> usize {
+    if op == 0xF000 { 4 } else { 2 }
+}
+
+/// The Octo statement for `op`, with `long` the word after it for
+/// XO-CHIP's `F000`. `target` names jump and call destinations. None when
+/// `op` isn't an instruction.
+pub fn statement(op: u16, long: u16, target: impl Fn(u16) -> String) -> Option<String> {
+    let x = (op >> 8) & 0xF;
+    let y = (op >> 4) & 0xF;
+    let n = op & 0xF;
+    let nn = op & 0xFF;
+    let nnn = op & 0xFFF;
+    let text = match op >> 12 {
+        0x0 => match op {
+            0x00E0 => "clear".to_string(),
+            0x00EE => "return".to_string(),
+            0x00FB => "scroll-right".to_string(),
+            0x00FC => "scroll-left".to_string(),
+            0x00FD => "exit".to_string(),
+            0x00FE => "lores".to_string(),
+            0x00FF => "hires".to_string(),
+            _ if op & 0xFFF0 == 0x00C0 => format!("scroll-down {}", n),
+            _ if op & 0xFFF0 == 0x00D0 => format!("scroll-up {}", n),
+            0x0000 => return None,
+            _ => format!("native 0x{:03X}", nnn),
+        },
+        0x1 => format!("jump {}", target(nnn)),
+        0x2 => match target(nnn) {
+            address if address.starts_with("0x") => format!(":call {}", address),
+            label => label,
+        },
+        // Octo's `if ... then` skips when the condition is false
+        0x3 => format!("if v{:x} != 0x{:02X} then", x, nn),
+        0x4 => format!("if v{:x} == 0x{:02X} then", x, nn),
+        0x5 => match n {
+            0x0 => format!("if v{:x} != v{:x} then", x, y),
+            0x2 => format!("save v{:x} - v{:x}", x, y),
+            0x3 => format!("load v{:x} - v{:x}", x, y),
+            _ => return None,
+        },
+        0x6 => format!("v{:x} := 0x{:02X}", x, nn),
+        0x7 => format!("v{:x} += 0x{:02X}", x, nn),
+        0x8 => {
+            let operator = match n {
+                0x0 => ":=",
+                0x1 => "|=",
+                0x2 => "&=",
+                0x3 => "^=",
+                0x4 => "+=",
+                0x5 => "-=",
+                0x6 => ">>=",
+                0x7 => "=-",
+                0xE => "<<=",
+                _ => return None,
+            };
+            format!("v{:x} {} v{:x}", x, operator, y)
+        }
+        0x9 if n == 0 => format!("if v{:x} == v{:x} then", x, y),
+        0xA => format!("i := 0x{:03X}", nnn),
+        0xB => format!("jump0 0x{:03X}", nnn),
+        0xC => format!("v{:x} := random 0x{:02X}", x, nn),
+        0xD => format!("sprite v{:x} v{:x} {}", x, y, n),
+        0xE if nn == 0x9E => format!("if v{:x} -key then", x),
+        0xE if nn == 0xA1 => format!("if v{:x} key then", x),
+        0xF => match nn {
+            0x00 if x == 0 => format!("i := long 0x{:04X}", long),
+            0x01 => format!("plane {}", x),
+            0x02 if x == 0 => "audio".to_string(),
+            0x07 => format!("v{:x} := delay", x),
+            0x0A => format!("v{:x} := key", x),
+            0x15 => format!("delay := v{:x}", x),
+            0x18 => format!("buzzer := v{:x}", x),
+            0x1E => format!("i += v{:x}", x),
+            0x29 => format!("i := hex v{:x}", x),
+            0x30 => format!("i := bighex v{:x}", x),
+            0x33 => format!("bcd v{:x}", x),
+            0x3A => format!("pitch := v{:x}", x),
+            0x55 => format!("save v{:x}", x),
+            0x65 => format!("load v{:x}", x),
+            0x75 => format!("saveflags v{:x}", x),
+            0x85 => format!("loadflags v{:x}", x),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    Some(text)
+}
+
+/// List `rom` (the bytes loaded at 0x200) as instructions where the code
+/// can be traced to and data everywhere else.
+pub fn disassemble(rom: &[u8]) -> Vec<Line> {
+    let code = analysis::analyze(rom).code;
+    let word = |offset: usize| match rom.get(offset..offset + 2) {
+        Some(bytes) => u16::from_be_bytes([bytes[0], bytes[1]]),
+        None => 0,
+    };
+
+    let decodes = |offset: usize| {
+        let address = (PROGRAM_START_LOC + offset) as u16;
+        let op = word(offset);
+        code.contains(&address)
+            && offset + size(op) <= rom.len()
+            && statement(op, word(offset + 2), |_| String::new()).is_some()
+    };
+    // a label can't go inside an instruction or past the end
+    let mut inside = HashSet::new();
+    let mut offset = 0;
+    while offset < rom.len() {
+        if decodes(offset) {
+            let size = size(word(offset));
+            inside.extend((1..size).map(|i| (PROGRAM_START_LOC + offset + i) as u16));
+            offset += size;
+        } else {
+            offset += 1;
+        }
+    }
+    let end = (PROGRAM_START_LOC + rom.len()) as u16;
+    let mut labels = BTreeMap::new();
+    if !rom.is_empty() {
+        labels.insert(
+            PROGRAM_START_LOC as u16,
+            label_name(PROGRAM_START_LOC as u16),
+        );
+    }
+    for &address in &code {
+        let op = word(address as usize - PROGRAM_START_LOC);
+        let to = op & 0xFFF;
+        if matches!(op >> 12, 0x1 | 0x2)
+            && (PROGRAM_START_LOC as u16..end).contains(&to)
+            && !inside.contains(&to)
+        {
+            labels.insert(to, label_name(to));
+        }
+    }
+    let target = |address: u16| {
+        labels
+            .get(&address)
+            .cloned()
+            .unwrap_or_else(|| format!("0x{:03X}", address))
+    };
+
+    let mut lines: Vec<Line> = Vec::new();
+    let mut offset = 0;
+    while offset < rom.len() {
+        let address = (PROGRAM_START_LOC + offset) as u16;
+        let op = word(offset);
+        let decoded = decodes(offset)
+            .then(|| statement(op, word(offset + 2), target))
+            .flatten();
+        let label = labels.get(&address).cloned();
+        match decoded {
+            Some(text) => {
+                let end = offset + size(op);
+                lines.push(Line {
+                    address,
+                    bytes: rom[offset..end].to_vec(),
+                    label,
+                    text,
+                });
+                offset = end;
+            }
+            None => {
+                // carry on a run of data unless something lands here
+                if let Some(last) = lines.last_mut()
+                    && label.is_none()
+                    && last.text.is_empty()
+                    && last.bytes.len() < DATA_WIDTH
+                {
+                    last.bytes.push(rom[offset]);
+                } else {
+                    lines.push(Line {
+                        address,
+                        bytes: vec![rom[offset]],
+                        label,
+                        text: String::new(),
+                    });
+                }
+                offset += 1;
+            }
+        }
+    }
+    for line in lines.iter_mut().filter(|line| line.text.is_empty()) {
+        let bytes: Vec<String> = line.bytes.iter().map(|b| format!("0x{:02X}", b)).collect();
+        line.text = bytes.join(" ");
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::octo;
+    use std::fs;
+    use std::path::Path;
+
+    fn reassemble(rom: &[u8]) -> Vec<u8> {
+        let listing: String = disassemble(rom)
+            .iter()
+            .map(|line| format!("{}\n", line))
+            .collect();
+        octo::assemble(&listing).unwrap_or_else(|e| panic!("{}\n{}", e, listing))
+    }
+
+    #[test]
+    fn bundled_roms_reassemble() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("roms");
+        let mut checked = 0;
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_some_and(|ext| ext == "ch8") {
+                let rom = fs::read(&path).unwrap();
+                assert_eq!(reassemble(&rom), rom, "{}", path.display());
+                checked += 1;
+            }
+        }
+        assert!(checked > 0);
+    }
+
+    #[test]
+    fn every_byte_reassembles() {
+        let rom: Vec<u8> = (0..=255).collect();
+        assert_eq!(reassemble(&rom), rom);
+    }
+
+    #[test]
+    fn targets_without_a_line_stay_numbers() {
+        // calls into its own second byte, then jumps past the end
+        let rom = [0x22, 0x03, 0xA2, 0x00, 0x1F, 0x00];
+        let lines = disassemble(&rom);
+        assert_eq!(lines[0].label.as_deref(), Some("main"));
+        assert_eq!(lines[0].text, ":call 0x203");
+        assert_eq!(reassemble(&rom), rom);
+    }
+}
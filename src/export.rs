@@ -0,0 +1,147 @@
+// Lossless video and audio streams for headless rendering: Y4M frames and a
+// WAV of the buzzer, both locked to 60 frames per second so they can be
+// muxed by external tools, e.g.
+//
+//     ffmpeg -i out.y4m -i out.wav -c:v libx264 -c:a aac out.mp4
+
+use crate::framebuffer::FrameBuffer;
+use crate::palette::Palette;
+use crate::postfx::{self, PostFilter};
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+pub const SAMPLE_RATE: u32 = 44_100;
+pub const BUZZER_HZ: u32 = 440;
+const SAMPLES_PER_FRAME: u32 = SAMPLE_RATE / 60;
+const AMPLITUDE: i16 = 8_000;
+
+/// YUV4MPEG2 stream at 60 fps, 4:4:4 BT.601 studio range.
+pub struct Y4mWriter {
+    out: BufWriter<File>,
+    width: usize,
+    height: usize,
+    scale: usize,
+    filter: PostFilter,
+    scaled: Vec<u8>,
+    frames: u64,
+}
+
+impl Y4mWriter {
+    /// The video size is fixed when the first frame arrives: its resolution
+    /// times `scale`. Later frames at another resolution are resized to fit.
+    pub fn create(path: &Path, scale: usize, filter: PostFilter) -> Result<Self, io::Error> {
+        Ok(Self {
+            out: BufWriter::new(File::create(path)?),
+            width: 0,
+            height: 0,
+            scale: scale.max(1),
+            filter,
+            scaled: Vec::new(),
+            frames: 0,
+        })
+    }
+
+    pub fn write_frame(&mut self, fb: &FrameBuffer, palette: &Palette) -> Result<(), io::Error> {
+        let (w, h) = (fb.width(), fb.height());
+        if self.frames == 0 {
+            self.width = w * self.scale;
+            self.height = h * self.scale;
+            writeln!(
+                self.out,
+                "YUV4MPEG2 W{} H{} F60:1 Ip A1:1 C444",
+                self.width, self.height
+            )?;
+        }
+
+        let scale = (self.width / w).max(1);
+        self.scaled.resize(w * scale * h * scale * 4, 0);
+        self.filter
+            .apply(&fb.to_rgba(palette), w, h, scale, &mut self.scaled);
+        let rgba = if (w * scale, h * scale) == (self.width, self.height) {
+            &self.scaled
+        } else {
+            &postfx::resize_nearest(&self.scaled, w * scale, h * scale, self.width, self.height)
+        };
+
+        let pixels = rgba.chunks_exact(4);
+        let mut planes = vec![0u8; self.width * self.height * 3];
+        let (y_plane, chroma) = planes.split_at_mut(self.width * self.height);
+        let (u_plane, v_plane) = chroma.split_at_mut(self.width * self.height);
+        for (i, px) in pixels.enumerate() {
+            let (r, g, b) = (px[0] as i32, px[1] as i32, px[2] as i32);
+            y_plane[i] = (((66 * r + 129 * g + 25 * b + 128) >> 8) + 16) as u8;
+            u_plane[i] = (((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128) as u8;
+            v_plane[i] = (((112 * r - 94 * g - 18 * b + 128) >> 8) + 128) as u8;
+        }
+        self.out.write_all(b"FRAME\n")?;
+        self.out.write_all(&planes)?;
+        self.frames += 1;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<u64, io::Error> {
+        self.out.flush()?;
+        Ok(self.frames)
+    }
+}
+
+/// 16-bit mono PCM WAV of the buzzer: a square wave while the sound timer
+/// is running, silence otherwise, exactly 1/60 s of samples per frame.
+pub struct WavWriter {
+    out: BufWriter<File>,
+    samples: u32,
+    phase: u32,
+}
+
+impl WavWriter {
+    pub fn create(path: &Path) -> Result<Self, io::Error> {
+        let mut out = BufWriter::new(File::create(path)?);
+        // sizes are patched in `finish`
+        write_wav_header(&mut out, 0)?;
+        Ok(Self {
+            out,
+            samples: 0,
+            phase: 0,
+        })
+    }
+
+    pub fn write_frame(&mut self, sound: bool) -> Result<(), io::Error> {
+        let mut buf = Vec::with_capacity(SAMPLES_PER_FRAME as usize * 2);
+        for _ in 0..SAMPLES_PER_FRAME {
+            // keep the phase running through silence so tones never click
+            self.phase = (self.phase + BUZZER_HZ) % SAMPLE_RATE;
+            let sample = match sound {
+                false => 0,
+                true if self.phase < SAMPLE_RATE / 2 => AMPLITUDE,
+                true => -AMPLITUDE,
+            };
+            buf.extend_from_slice(&sample.to_le_bytes());
+        }
+        self.out.write_all(&buf)?;
+        self.samples += SAMPLES_PER_FRAME;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<u32, io::Error> {
+        self.out.seek(SeekFrom::Start(0))?;
+        write_wav_header(&mut self.out, self.samples * 2)?;
+        self.out.flush()?;
+        Ok(self.samples)
+    }
+}
+
+fn write_wav_header(out: &mut impl Write, data_len: u32) -> Result<(), io::Error> {
+    out.write_all(b"RIFF")?;
+    out.write_all(&(36 + data_len).to_le_bytes())?;
+    out.write_all(b"WAVEfmt ")?;
+    out.write_all(&16u32.to_le_bytes())?; // fmt chunk size
+    out.write_all(&1u16.to_le_bytes())?; // PCM
+    out.write_all(&1u16.to_le_bytes())?; // mono
+    out.write_all(&SAMPLE_RATE.to_le_bytes())?;
+    out.write_all(&(SAMPLE_RATE * 2).to_le_bytes())?; // byte rate
+    out.write_all(&2u16.to_le_bytes())?; // block align
+    out.write_all(&16u16.to_le_bytes())?; // bits per sample
+    out.write_all(b"data")?;
+    out.write_all(&data_len.to_le_bytes())
+}
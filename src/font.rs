@@ -0,0 +1,219 @@
+// The hex digit sprites `Fx29` points at. Interpreters of the time each drew
+// their own, which shows wherever a game prints a score.
+
+use crate::chip8::{FONT_START_LOC, FONTSET, PROGRAM_START_LOC};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Bytes in a font: 16 sprites of 5 rows.
+pub const FONT_SIZE: usize = 80;
+
+const VIP: [u8; FONT_SIZE] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+    0x60, 0x20, 0x20, 0x20, 0x70, // 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+    0xA0, 0xA0, 0xF0, 0x20, 0x20, // 4
+    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+    0xF0, 0x10, 0x10, 0x10, 0x10, // 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+    0xF0, 0x50, 0x70, 0x50, 0xF0, // B
+    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+    0xF0, 0x50, 0x50, 0x50, 0xF0, // D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+    0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+];
+
+const DREAM6800: [u8; FONT_SIZE] = [
+    0xE0, 0xA0, 0xA0, 0xA0, 0xE0, // 0
+    0x40, 0x40, 0x40, 0x40, 0x40, // 1
+    0xE0, 0x20, 0xE0, 0x80, 0xE0, // 2
+    0xE0, 0x20, 0xE0, 0x20, 0xE0, // 3
+    0x80, 0xA0, 0xA0, 0xE0, 0x20, // 4
+    0xE0, 0x80, 0xE0, 0x20, 0xE0, // 5
+    0xE0, 0x80, 0xE0, 0xA0, 0xE0, // 6
+    0xE0, 0x20, 0x20, 0x20, 0x20, // 7
+    0xE0, 0xA0, 0xE0, 0xA0, 0xE0, // 8
+    0xE0, 0xA0, 0xE0, 0x20, 0xE0, // 9
+    0xE0, 0xA0, 0xE0, 0xA0, 0xA0, // A
+    0xC0, 0xA0, 0xE0, 0xA0, 0xC0, // B
+    0xE0, 0x80, 0x80, 0x80, 0xE0, // C
+    0xC0, 0xA0, 0xA0, 0xA0, 0xC0, // D
+    0xE0, 0x80, 0xE0, 0x80, 0xE0, // E
+    0xE0, 0x80, 0xC0, 0x80, 0x80, // F
+];
+
+const ETI660: [u8; FONT_SIZE] = [
+    0xE0, 0xA0, 0xA0, 0xA0, 0xE0, // 0
+    0x20, 0x20, 0x20, 0x20, 0x20, // 1
+    0xE0, 0x20, 0xE0, 0x80, 0xE0, // 2
+    0xE0, 0x20, 0xE0, 0x20, 0xE0, // 3
+    0xA0, 0xA0, 0xE0, 0x20, 0x20, // 4
+    0xE0, 0x80, 0xE0, 0x20, 0xE0, // 5
+    0xE0, 0x80, 0xE0, 0xA0, 0xE0, // 6
+    0xE0, 0x20, 0x20, 0x20, 0x20, // 7
+    0xE0, 0xA0, 0xE0, 0xA0, 0xE0, // 8
+    0xE0, 0xA0, 0xE0, 0x20, 0xE0, // 9
+    0xE0, 0xA0, 0xE0, 0xA0, 0xA0, // A
+    0x80, 0x80, 0xE0, 0xA0, 0xE0, // B
+    0xE0, 0x80, 0x80, 0x80, 0xE0, // C
+    0x20, 0x20, 0xE0, 0xA0, 0xE0, // D
+    0xE0, 0x80, 0xE0, 0x80, 0xE0, // E
+    0xE0, 0x80, 0xC0, 0x80, 0x80, // F
+];
+
+// CHIP-48 introduced the font most emulators use.
+const BUILTIN: [(&str, &[u8; FONT_SIZE]); 4] = [
+    ("chip48", &FONTSET),
+    ("vip", &VIP),
+    ("dream6800", &DREAM6800),
+    ("eti660", &ETI660),
+];
+
+/// A hex font and where in memory it is loaded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Font {
+    name: String,
+    glyphs: [u8; FONT_SIZE],
+    address: u16,
+}
+
+impl Default for Font {
+    fn default() -> Self {
+        Self::new("chip48", FONTSET)
+    }
+}
+
+impl Font {
+    pub fn new(name: &str, glyphs: [u8; FONT_SIZE]) -> Self {
+        Self {
+            name: name.to_string(),
+            glyphs,
+            address: FONT_START_LOC as u16,
+        }
+    }
+
+    pub fn builtin_names() -> impl Iterator<Item = &'static str> {
+        BUILTIN.iter().map(|(name, _)| *name)
+    }
+
+    pub fn builtin(name: &str) -> Option<Self> {
+        BUILTIN
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(n, glyphs)| Self::new(n, **glyphs))
+    }
+
+    /// The built-in font after `self` in `builtin_names` order, wrapping
+    /// around, at the same address.
+    pub fn next_builtin(&self) -> Self {
+        let next = BUILTIN
+            .iter()
+            .position(|(n, _)| *n == self.name)
+            .map_or(0, |i| (i + 1) % BUILTIN.len());
+        Self::new(BUILTIN[next].0, *BUILTIN[next].1).with_address(self.address)
+    }
+
+    /// Load a font file: the 80 bytes of the 16 sprites, 0 to F.
+    pub fn load(path: &Path) -> Result<Self, io::Error> {
+        let data = fs::read(path)?;
+        let glyphs = data.try_into().map_err(|data: Vec<u8>| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("a font is {} bytes, not {}", FONT_SIZE, data.len()),
+            )
+        })?;
+        let name = path
+            .file_stem()
+            .map_or(String::new(), |stem| stem.to_string_lossy().into_owned());
+        Ok(Self::new(&name, glyphs))
+    }
+
+    /// Move the font to `address`, which must leave it below 0x200.
+    pub fn with_address(mut self, address: u16) -> Self {
+        self.address = address;
+        self
+    }
+
+    /// Whether a font at `address` fits below the program.
+    pub fn valid_address(address: u16) -> bool {
+        address as usize + FONT_SIZE <= PROGRAM_START_LOC
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn glyphs(&self) -> &[u8; FONT_SIZE] {
+        &self.glyphs
+    }
+
+    pub fn address(&self) -> u16 {
+        self.address
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::tests::with_rom;
+
+    #[test]
+    fn only_addresses_below_the_program_are_valid() {
+        assert!(Font::valid_address(0));
+        assert!(Font::valid_address(FONT_START_LOC as u16));
+        assert!(Font::valid_address((PROGRAM_START_LOC - FONT_SIZE) as u16));
+        assert!(!Font::valid_address(
+            (PROGRAM_START_LOC - FONT_SIZE + 1) as u16
+        ));
+        assert!(!Font::valid_address(0xFFFF));
+    }
+
+    #[test]
+    fn builtins_cycle_at_the_same_address() {
+        let names: Vec<_> = Font::builtin_names().collect();
+        let mut font = Font::builtin("VIP").unwrap().with_address(0x100);
+        assert_eq!(font.name(), "vip");
+        for _ in 0..names.len() {
+            font = font.next_builtin();
+            assert_eq!(font.address(), 0x100);
+        }
+        assert_eq!(font.name(), "vip");
+        assert_eq!(Font::builtin("nope"), None);
+        assert_eq!(Font::builtin(names[0]), Some(Font::default()));
+    }
+
+    #[test]
+    fn font_files_are_80_bytes() {
+        let path = std::env::temp_dir().join(format!("chip8-font-{}.bin", std::process::id()));
+        fs::write(&path, [0xAA; FONT_SIZE]).unwrap();
+        let font = Font::load(&path).unwrap();
+        assert_eq!(font.glyphs(), &[0xAA; FONT_SIZE]);
+        assert!(font.name().starts_with("chip8-font-"));
+        fs::write(&path, [0xAA; FONT_SIZE - 1]).unwrap();
+        assert!(Font::load(&path).is_err());
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn digits_are_found_at_the_font_address() {
+        let rom = [
+            0x61, 0x07, // ld v1, 7
+            0xF1, 0x29, // ld f, v1
+            0xD0, 0x05, // drw v0, v0, 5
+            0x12, 0x06, // jp 0x206
+        ];
+        let mut runner = with_rom(&rom, 600);
+        runner
+            .chip8()
+            .set_font(Font::builtin("eti660").unwrap().with_address(0x40));
+        runner.step_frame();
+        let screen = runner.screen();
+        let rows: Vec<_> = (0..5).map(|y| (screen.row_bits(y) >> 56) as u8).collect();
+        assert_eq!(rows, ETI660[7 * 5..8 * 5]);
+    }
+}
@@ -0,0 +1,333 @@
+use crate::palette::Palette;
+use std::ops::{BitAnd, BitXor, BitXorAssign, Shl, Shr};
+
+pub const LORES_WIDTH: usize = 64;
+pub const LORES_HEIGHT: usize = 32;
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    Lores, // 64x32, original CHIP-8
+    Hires, // 128x64, SCHIP extended mode
+}
+
+/// One packed display row. Bit `WIDTH - 1` is the leftmost pixel.
+pub trait Row:
+    Copy
+    + Default
+    + PartialEq
+    + BitAnd<Output = Self>
+    + BitXor<Output = Self>
+    + BitXorAssign
+    + Shl<usize, Output = Self>
+    + Shr<usize, Output = Self>
+{
+    const WIDTH: usize;
+
+    fn from_byte(byte: u8) -> Self;
+    fn rotate_right(self, n: u32) -> Self;
+    fn widen(self) -> u128;
+}
+
+impl Row for u64 {
+    const WIDTH: usize = 64;
+
+    fn from_byte(byte: u8) -> Self {
+        byte as u64
+    }
+    fn rotate_right(self, n: u32) -> Self {
+        u64::rotate_right(self, n)
+    }
+    fn widen(self) -> u128 {
+        self as u128
+    }
+}
+
+impl Row for u128 {
+    const WIDTH: usize = 128;
+
+    fn from_byte(byte: u8) -> Self {
+        byte as u128
+    }
+    fn rotate_right(self, n: u32) -> Self {
+        u128::rotate_right(self, n)
+    }
+    fn widen(self) -> u128 {
+        self
+    }
+}
+
+/// A monochrome plane of `H` packed rows plus a bitmask of rows changed
+/// since the last call to `take_dirty`.
+#[derive(Clone)]
+pub struct Plane<R: Row, const H: usize> {
+    rows: [R; H],
+    dirty: u64,
+}
+
+impl<R: Row, const H: usize> Plane<R, H> {
+    const ALL_ROWS: u64 = if H >= 64 { u64::MAX } else { (1 << H) - 1 };
+
+    fn new() -> Self {
+        Self {
+            rows: [R::default(); H],
+            dirty: Self::ALL_ROWS,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.rows = [R::default(); H];
+        self.dirty = Self::ALL_ROWS;
+    }
+
+    // XOR a sprite in at (x, y). The start position always wraps; the sprite
+    // itself wraps too, or is cut off at the edges when `clip` is set.
+    // Returns true on collision.
+    fn draw(&mut self, x: usize, y: usize, sprite: &[u8], clip: bool) -> bool {
+        let (x, y) = (x % R::WIDTH, y % H);
+        let mut collision = false;
+        for (line, &byte) in sprite.iter().enumerate() {
+            if clip && y + line >= H {
+                break;
+            }
+            if byte == 0 {
+                continue;
+            }
+            let left = R::from_byte(byte) << (R::WIDTH - 8);
+            let bits = if clip {
+                left >> x
+            } else {
+                left.rotate_right(x as u32)
+            };
+            let row_y = (y + line) % H;
+            let row = &mut self.rows[row_y];
+            if *row & bits != R::default() {
+                collision = true;
+            }
+            *row ^= bits;
+            self.dirty |= 1 << row_y;
+        }
+        collision
+    }
+}
+
+#[derive(Clone)]
+pub enum FrameBuffer {
+    Lores(Box<Plane<u64, LORES_HEIGHT>>),
+    Hires(Box<Plane<u128, HIRES_HEIGHT>>),
+}
+
+impl Default for FrameBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FrameBuffer {
+    pub fn new() -> Self {
+        Self::with_resolution(Resolution::Lores)
+    }
+
+    pub fn with_resolution(resolution: Resolution) -> Self {
+        match resolution {
+            Resolution::Lores => FrameBuffer::Lores(Box::new(Plane::new())),
+            Resolution::Hires => FrameBuffer::Hires(Box::new(Plane::new())),
+        }
+    }
+
+    pub fn resolution(&self) -> Resolution {
+        match self {
+            FrameBuffer::Lores(_) => Resolution::Lores,
+            FrameBuffer::Hires(_) => Resolution::Hires,
+        }
+    }
+
+    /// Switch resolution. The display is cleared and every row marked dirty.
+    pub fn set_resolution(&mut self, resolution: Resolution) {
+        *self = Self::with_resolution(resolution);
+    }
+
+    pub fn width(&self) -> usize {
+        match self {
+            FrameBuffer::Lores(_) => LORES_WIDTH,
+            FrameBuffer::Hires(_) => HIRES_WIDTH,
+        }
+    }
+
+    pub fn height(&self) -> usize {
+        match self {
+            FrameBuffer::Lores(_) => LORES_HEIGHT,
+            FrameBuffer::Hires(_) => HIRES_HEIGHT,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        match self {
+            FrameBuffer::Lores(plane) => plane.clear(),
+            FrameBuffer::Hires(plane) => plane.clear(),
+        }
+    }
+
+    /// `Dxyn`: XOR `sprite` (one byte per line) at (x, y). Pixels past the
+    /// right or bottom edge wrap around, or are dropped when `clip` is set.
+    /// Returns true if any lit pixel was turned off.
+    pub fn draw_sprite(&mut self, x: usize, y: usize, sprite: &[u8], clip: bool) -> bool {
+        match self {
+            FrameBuffer::Lores(plane) => plane.draw(x, y, sprite, clip),
+            FrameBuffer::Hires(plane) => plane.draw(x, y, sprite, clip),
+        }
+    }
+
+    /// Row `y` widened to `u128`; pixel `x` is bit `width() - 1 - x`.
+    pub fn row_bits(&self, y: usize) -> u128 {
+        match self {
+            FrameBuffer::Lores(plane) => plane.rows[y].widen(),
+            FrameBuffer::Hires(plane) => plane.rows[y].widen(),
+        }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        (self.row_bits(y) >> (self.width() - 1 - x)) & 1 == 1
+    }
+
+    pub fn mark_all_dirty(&mut self) {
+        match self {
+            FrameBuffer::Lores(plane) => plane.dirty = Plane::<u64, LORES_HEIGHT>::ALL_ROWS,
+            FrameBuffer::Hires(plane) => plane.dirty = Plane::<u128, HIRES_HEIGHT>::ALL_ROWS,
+        }
+    }
+
+    /// Return the dirty-row bitmask (bit `y` set for row `y`) and reset it.
+    pub fn take_dirty(&mut self) -> u64 {
+        match self {
+            FrameBuffer::Lores(plane) => std::mem::take(&mut plane.dirty),
+            FrameBuffer::Hires(plane) => std::mem::take(&mut plane.dirty),
+        }
+    }
+
+    /// Render every row into a new RGBA image of `width() * height()` pixels.
+    pub fn to_rgba(&self, palette: &Palette) -> Vec<u8> {
+        let mut frame = vec![0; self.width() * self.height() * 4];
+        let mut copy = self.clone();
+        copy.mark_all_dirty();
+        copy.blit_dirty(&mut frame, palette);
+        frame
+    }
+
+    /// Write the dirty rows into an RGBA `frame` of `width() * height()`
+    /// pixels using `palette`, leaving clean rows untouched. Returns the
+    /// number of rows written.
+    pub fn blit_dirty(&mut self, frame: &mut [u8], palette: &Palette) -> usize {
+        let width = self.width();
+        let mut dirty = self.take_dirty();
+        let mut written = 0;
+        while dirty != 0 {
+            let y = dirty.trailing_zeros() as usize;
+            dirty &= dirty - 1;
+
+            let bytes = (self.row_bits(y) << (HIRES_WIDTH - width)).to_be_bytes();
+            let line = &mut frame[y * width * 4..(y + 1) * width * 4];
+            for (byte, out) in bytes.iter().zip(line.chunks_exact_mut(32)) {
+                out.copy_from_slice(palette.expand_byte(*byte));
+            }
+            written += 1;
+        }
+        written
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lit(screen: &FrameBuffer) -> Vec<(usize, usize)> {
+        let mut pixels = Vec::new();
+        for y in 0..screen.height() {
+            for x in 0..screen.width() {
+                if screen.pixel(x, y) {
+                    pixels.push((x, y));
+                }
+            }
+        }
+        pixels
+    }
+
+    #[test]
+    fn sprites_xor_and_report_collisions() {
+        let mut screen = FrameBuffer::new();
+        assert!(!screen.draw_sprite(3, 2, &[0b1000_0001], false));
+        assert_eq!(lit(&screen), [(3, 2), (10, 2)]);
+        assert!(screen.draw_sprite(3, 2, &[0b1000_0000], false));
+        assert_eq!(lit(&screen), [(10, 2)]);
+        assert!(!screen.draw_sprite(0, 0, &[0b0100_0000, 0, 0b1100_0000], false));
+        assert_eq!(lit(&screen), [(1, 0), (0, 2), (1, 2), (10, 2)]);
+    }
+
+    #[test]
+    fn sprites_wrap_around_both_edges() {
+        let mut screen = FrameBuffer::new();
+        screen.draw_sprite(60, 31, &[0xFF, 0x80], false);
+        assert_eq!(
+            lit(&screen),
+            [
+                (60, 0),
+                (0, 31),
+                (1, 31),
+                (2, 31),
+                (3, 31),
+                (60, 31),
+                (61, 31),
+                (62, 31),
+                (63, 31)
+            ]
+        );
+
+        let mut screen = FrameBuffer::with_resolution(Resolution::Hires);
+        screen.draw_sprite(127, 63, &[0xC0], false);
+        assert_eq!(lit(&screen), [(0, 63), (127, 63)]);
+    }
+
+    #[test]
+    fn clipped_sprites_stop_at_the_edges() {
+        let mut screen = FrameBuffer::new();
+        screen.draw_sprite(62, 30, &[0xFF, 0xFF, 0xFF], true);
+        assert_eq!(lit(&screen), [(62, 30), (63, 30), (62, 31), (63, 31)]);
+
+        // the start position still wraps
+        let mut screen = FrameBuffer::new();
+        screen.draw_sprite(64 + 2, 32 + 1, &[0x80], true);
+        assert_eq!(lit(&screen), [(2, 1)]);
+    }
+
+    #[test]
+    fn only_drawn_rows_are_dirty() {
+        let mut screen = FrameBuffer::new();
+        assert_eq!(screen.take_dirty(), u32::MAX as u64);
+        assert_eq!(screen.take_dirty(), 0);
+        // blank sprite lines don't touch their row
+        screen.draw_sprite(0, 4, &[0x80, 0, 0x80], false);
+        assert_eq!(screen.take_dirty(), 0b101 << 4);
+        screen.clear();
+        assert_eq!(screen.take_dirty(), u32::MAX as u64);
+
+        let mut screen = FrameBuffer::with_resolution(Resolution::Hires);
+        assert_eq!(screen.take_dirty(), u64::MAX);
+    }
+
+    #[test]
+    fn blit_writes_only_dirty_rows() {
+        let palette = Palette::new("test", [[1, 2, 3], [4, 5, 6], [0; 3], [0; 3]]);
+        let mut screen = FrameBuffer::new();
+        let mut frame = vec![0x11; LORES_WIDTH * LORES_HEIGHT * 4];
+        assert_eq!(screen.blit_dirty(&mut frame, &palette), LORES_HEIGHT);
+        assert!(frame.chunks(4).all(|p| p == [1, 2, 3, 0xFF]));
+
+        screen.draw_sprite(1, 5, &[0x80], false);
+        frame.fill(0x11);
+        assert_eq!(screen.blit_dirty(&mut frame, &palette), 1);
+        let row = &frame[5 * LORES_WIDTH * 4..6 * LORES_WIDTH * 4];
+        assert_eq!(&row[..8], [1, 2, 3, 0xFF, 4, 5, 6, 0xFF]);
+        assert!(frame[..5 * LORES_WIDTH * 4].iter().all(|&b| b == 0x11));
+    }
+}
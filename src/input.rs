@@ -0,0 +1,379 @@
+use crate::keymap::Keymap;
+use std::collections::VecDeque;
+use winit::keyboard::KeyCode;
+
+/// A CHIP-8 key going down or up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PadEvent {
+    pub key: u8,
+    pub pressed: bool,
+}
+
+pub const DEFAULT_TURBO_RATE: u8 = 10;
+
+/// Host keys come in through the keymap and are queued as `PadEvent`s in
+/// the order they happened. The core takes them off the queue one
+/// instruction at a time, so `keypad` only changes between instructions
+/// and a press and release that arrive together are both seen.
+///
+/// Between the host keys and the queue sit turbo, which pulses held keys,
+/// and macros, which replay recorded key states frame by frame. Both run
+/// on emulated frames through `on_frame`.
+pub struct InputHandler {
+    pub keypad: [bool; 16],
+    keymap: Keymap,
+    held: Vec<KeyCode>, // host keys down, so chords release cleanly
+    logical: u16,       // CHIP-8 keys held by the player
+    sent: u16,          // keys queued as down after turbo and macros
+    frame: u64,
+    turbo: [u8; 16],        // pulse period in frames, 0 = off
+    turbo_start: [u64; 16], // frame the key went down, pulses count from it
+    macros: Vec<(KeyCode, Vec<u16>)>,
+    playing: Option<(KeyCode, usize)>, // key of the macro, next frame
+    macro_bits: u16,
+    macro_recording: Option<Vec<u16>>,
+    macro_unbound: Option<Vec<u16>>, // recorded, bound to the next key pressed
+    queue: VecDeque<PadEvent>,
+    // every event queued since the last `take_recorded`, while recording
+    recorded: Option<Vec<PadEvent>>,
+}
+
+impl Default for InputHandler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InputHandler {
+    pub fn new() -> Self {
+        Self::with_keymap(Keymap::default())
+    }
+
+    pub fn with_keymap(keymap: Keymap) -> Self {
+        Self {
+            keypad: [false; 16],
+            keymap,
+            held: Vec::new(),
+            logical: 0,
+            sent: 0,
+            frame: 0,
+            turbo: [0; 16],
+            turbo_start: [0; 16],
+            macros: Vec::new(),
+            playing: None,
+            macro_bits: 0,
+            macro_recording: None,
+            macro_unbound: None,
+            queue: VecDeque::new(),
+            recorded: None,
+        }
+    }
+
+    pub fn keymap(&self) -> &Keymap {
+        &self.keymap
+    }
+
+    /// Switch to `keymap`, releasing every key so nothing stays stuck down.
+    pub fn set_keymap(&mut self, keymap: Keymap) {
+        self.keymap = keymap;
+        self.held.clear();
+        self.logical = 0;
+        self.playing = None;
+        self.macro_bits = 0;
+        self.sync();
+        self.set_keypad_bits(0);
+    }
+
+    /// Let go of every key and stop a macro playing or being recorded, as
+    /// after a reset. The keymap, turbo keys and bound macros stay.
+    pub fn release_all(&mut self) {
+        self.keypad = [false; 16];
+        self.held.clear();
+        self.logical = 0;
+        self.sent = 0;
+        self.playing = None;
+        self.macro_bits = 0;
+        self.macro_recording = None;
+        self.macro_unbound = None;
+        self.queue.clear();
+    }
+
+    /// A host key went down. The CHIP-8 key it is bound to is pressed
+    /// unless another of its host keys already holds it down.
+    pub fn key_pressed(&mut self, key_code: KeyCode) {
+        if self.held.contains(&key_code) {
+            return;
+        }
+        if let Some(frames) = self.macro_unbound.take() {
+            if key_code != KeyCode::Escape {
+                self.bind_macro(key_code, frames);
+            }
+            return;
+        }
+        if self.macros.iter().any(|(code, _)| *code == key_code) {
+            self.playing = Some((key_code, 0));
+            return;
+        }
+        self.held.push(key_code);
+        if let Some(chip8_key) = self.keymap.lookup(key_code)
+            && self.held_for(chip8_key) == 1
+        {
+            self.set_key(chip8_key, true);
+        }
+    }
+
+    /// A host key went up. The CHIP-8 key stays down while any other host
+    /// key bound to it is still held, e.g. both players sharing a key.
+    pub fn key_released(&mut self, key_code: KeyCode) {
+        let Some(i) = self.held.iter().position(|&code| code == key_code) else {
+            return;
+        };
+        self.held.swap_remove(i);
+        if let Some(chip8_key) = self.keymap.lookup(key_code)
+            && self.held_for(chip8_key) == 0
+        {
+            self.set_key(chip8_key, false);
+        }
+    }
+
+    /// The player pressed or released CHIP-8 key `key`, bypassing the
+    /// keymap but going through turbo.
+    pub fn set_key(&mut self, key: u8, pressed: bool) {
+        if key >= 16 {
+            return;
+        }
+        let bit = 1 << key;
+        if pressed && self.logical & bit == 0 {
+            self.turbo_start[key as usize] = self.frame;
+        }
+        if pressed {
+            self.logical |= bit;
+        } else {
+            self.logical &= !bit;
+        }
+        self.sync();
+    }
+
+    /// CHIP-8 keys the player is holding, before turbo and macros.
+    pub fn held_keys(&self) -> u16 {
+        self.logical
+    }
+
+    /// Pulse `key` `rate` times a second while it is held, or turn turbo off
+    /// with `None`.
+    pub fn set_turbo(&mut self, key: u8, rate: Option<u8>) {
+        if key < 16 {
+            self.turbo[key as usize] = rate.map_or(0, |rate| (60 / rate.max(1)).max(2));
+            self.sync();
+        }
+    }
+
+    pub fn turbo(&self, key: u8) -> bool {
+        key < 16 && self.turbo[key as usize] != 0
+    }
+
+    /// Start recording a macro from the keys the player holds each frame.
+    pub fn start_macro(&mut self) {
+        self.macro_recording = Some(Vec::new());
+        self.macro_unbound = None;
+    }
+
+    /// Stop recording. The macro is bound to the next host key pressed,
+    /// Escape throws it away. Returns its length in frames.
+    pub fn stop_macro(&mut self) -> usize {
+        let frames = self.macro_recording.take().unwrap_or_default();
+        let len = frames.len();
+        if len > 0 {
+            self.macro_unbound = Some(frames);
+        }
+        len
+    }
+
+    pub fn is_recording_macro(&self) -> bool {
+        self.macro_recording.is_some()
+    }
+
+    /// Pressing `code` plays `frames`, one keypad bitmask per frame. A
+    /// macro already on `code` is replaced, stopping it if it is playing.
+    pub fn bind_macro(&mut self, code: KeyCode, frames: Vec<u16>) {
+        if self.playing.is_some_and(|(playing, _)| playing == code) {
+            self.playing = None;
+        }
+        self.macros.retain(|(c, _)| *c != code);
+        self.macros.push((code, frames));
+    }
+
+    /// Advance turbo and macros by one emulated frame. Called at the end of
+    /// each frame so what it queues is in place before the next one.
+    pub fn on_frame(&mut self) {
+        self.frame += 1;
+        if let Some(frames) = &mut self.macro_recording {
+            frames.push(self.logical);
+        }
+        self.macro_bits = 0;
+        if let Some((code, next)) = self.playing {
+            let frames = self.macros.iter().find(|(c, _)| *c == code);
+            match frames.and_then(|(_, frames)| frames.get(next)) {
+                Some(&bits) => {
+                    self.macro_bits = bits;
+                    self.playing = Some((code, next + 1));
+                }
+                None => self.playing = None,
+            }
+        }
+        self.sync();
+    }
+
+    // Queue whatever brings the keypad to the held keys plus macro keys,
+    // with turbo keys in the released half of their pulse left up.
+    fn sync(&mut self) {
+        let mut wanted = self.logical | self.macro_bits;
+        for key in 0..16 {
+            let period = self.turbo[key] as u64;
+            if period != 0 && (self.frame - self.turbo_start[key]) % period >= period / 2 {
+                wanted &= !(1 << key);
+            }
+        }
+        for key in 0..16 {
+            let bit = 1 << key;
+            if (wanted ^ self.sent) & bit != 0 {
+                self.queue_key(key, wanted & bit != 0);
+            }
+        }
+        self.sent = wanted;
+    }
+
+    // host keys held down for `chip8_key`
+    fn held_for(&self, chip8_key: u8) -> usize {
+        let bound = self.keymap.keys(chip8_key);
+        self.held.iter().filter(|code| bound.contains(code)).count()
+    }
+
+    /// Queue a press or release of CHIP-8 key `key` as is, bypassing the
+    /// keymap, turbo and macros.
+    pub fn queue_key(&mut self, key: u8, pressed: bool) {
+        if key >= 16 {
+            return;
+        }
+        let event = PadEvent { key, pressed };
+        self.queue.push_back(event);
+        if let Some(recorded) = &mut self.recorded {
+            recorded.push(event);
+        }
+    }
+
+    /// Take the oldest queued event and apply it to `keypad`.
+    pub fn next_event(&mut self) -> Option<PadEvent> {
+        let event = self.queue.pop_front()?;
+        self.keypad[event.key as usize] = event.pressed;
+        Some(event)
+    }
+
+    pub fn is_key_pressed(&self, key: u8) -> bool {
+        if key < 16 {
+            self.keypad[key as usize]
+        } else {
+            false
+        }
+    }
+
+    /// The keypad as a bitmask, bit n set while key n is down.
+    pub fn keypad_bits(&self) -> u16 {
+        (0..16)
+            .filter(|&k| self.keypad[k])
+            .fold(0, |bits, k| bits | 1 << k)
+    }
+
+    /// Queue whatever presses and releases turn the keypad into `bits`,
+    /// counting events that are still queued.
+    pub fn set_keypad_bits(&mut self, bits: u16) {
+        let pending = self.queue.iter().fold(self.keypad_bits(), |state, event| {
+            let bit = 1 << event.key;
+            if event.pressed {
+                state | bit
+            } else {
+                state & !bit
+            }
+        });
+        for key in 0..16 {
+            let bit = 1 << key;
+            if (pending ^ bits) & bit != 0 {
+                self.queue_key(key, bits & bit != 0);
+            }
+        }
+    }
+
+    /// Start or stop keeping a copy of queued events for `take_recorded`.
+    pub fn record_events(&mut self, on: bool) {
+        self.recorded = on.then(Vec::new);
+    }
+
+    /// Events queued since the last call, oldest first.
+    pub fn take_recorded(&mut self) -> Vec<PadEvent> {
+        self.recorded
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::tests::with_rom;
+
+    #[test]
+    fn events_come_off_the_queue_in_order() {
+        let mut input = InputHandler::new();
+        input.key_pressed(KeyCode::KeyW);
+        input.key_released(KeyCode::KeyW);
+        input.queue_key(0x3, true);
+        input.queue_key(0x10, true);
+
+        let mut seen = Vec::new();
+        while let Some(event) = input.next_event() {
+            seen.push((event.key, event.pressed, input.keypad_bits()));
+        }
+        assert_eq!(
+            seen,
+            [(0x5, true, 1 << 5), (0x5, false, 0), (0x3, true, 1 << 3)]
+        );
+    }
+
+    #[test]
+    fn keypad_bits_queue_the_difference() {
+        let mut input = InputHandler::new();
+        input.queue_key(0x1, true);
+        input.set_keypad_bits(1 << 2);
+        let mut seen = Vec::new();
+        while let Some(event) = input.next_event() {
+            seen.push((event.key, event.pressed));
+        }
+        assert_eq!(seen, [(0x1, true), (0x1, false), (0x2, true)]);
+    }
+
+    #[test]
+    fn key_wait_finishes_on_release() {
+        let rom = [
+            0xF1, 0x0A, // ld v1, k
+            0xF1, 0x29, // ld f, v1
+            0xD0, 0x05, // drw v0, v0, 5
+            0x12, 0x06, // jp 0x206
+        ];
+        let mut runner = with_rom(&rom, 600);
+        // a key already down when Fx0A starts doesn't count
+        runner.chip8().input_handler.queue_key(0x3, true);
+        runner.step_frame();
+        runner.chip8().input_handler.queue_key(0x3, false);
+        runner.chip8().input_handler.queue_key(0x5, true);
+        runner.step_frame();
+        assert_eq!(runner.screen().row_bits(0), 0);
+
+        runner.chip8().input_handler.queue_key(0x5, false);
+        runner.step_frame();
+        // the glyph for 5
+        let screen = runner.screen();
+        let rows: Vec<_> = (0..5).map(|y| (screen.row_bits(y) >> 56) as u8).collect();
+        assert_eq!(rows, [0xF0, 0x80, 0xF0, 0x10, 0xF0]);
+    }
+}
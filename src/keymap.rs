@@ -0,0 +1,648 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use winit::keyboard::KeyCode;
+
+pub const DEFAULT_PATH: &str = "keymap.cfg";
+
+/// The CHIP-8 keys in keypad order, left to right and top to bottom.
+pub const KEYPAD_LAYOUT: [u8; 16] = [
+    0x1, 0x2, 0x3, 0xC, 0x4, 0x5, 0x6, 0xD, 0x7, 0x8, 0x9, 0xE, 0xA, 0x0, 0xB, 0xF,
+];
+
+// Host keys that can be bound, by their winit names.
+const KEY_NAMES: &[(&str, KeyCode)] = &[
+    ("Digit0", KeyCode::Digit0),
+    ("Digit1", KeyCode::Digit1),
+    ("Digit2", KeyCode::Digit2),
+    ("Digit3", KeyCode::Digit3),
+    ("Digit4", KeyCode::Digit4),
+    ("Digit5", KeyCode::Digit5),
+    ("Digit6", KeyCode::Digit6),
+    ("Digit7", KeyCode::Digit7),
+    ("Digit8", KeyCode::Digit8),
+    ("Digit9", KeyCode::Digit9),
+    ("KeyA", KeyCode::KeyA),
+    ("KeyB", KeyCode::KeyB),
+    ("KeyC", KeyCode::KeyC),
+    ("KeyD", KeyCode::KeyD),
+    ("KeyE", KeyCode::KeyE),
+    ("KeyF", KeyCode::KeyF),
+    ("KeyG", KeyCode::KeyG),
+    ("KeyH", KeyCode::KeyH),
+    ("KeyI", KeyCode::KeyI),
+    ("KeyJ", KeyCode::KeyJ),
+    ("KeyK", KeyCode::KeyK),
+    ("KeyL", KeyCode::KeyL),
+    ("KeyM", KeyCode::KeyM),
+    ("KeyN", KeyCode::KeyN),
+    ("KeyO", KeyCode::KeyO),
+    ("KeyP", KeyCode::KeyP),
+    ("KeyQ", KeyCode::KeyQ),
+    ("KeyR", KeyCode::KeyR),
+    ("KeyS", KeyCode::KeyS),
+    ("KeyT", KeyCode::KeyT),
+    ("KeyU", KeyCode::KeyU),
+    ("KeyV", KeyCode::KeyV),
+    ("KeyW", KeyCode::KeyW),
+    ("KeyX", KeyCode::KeyX),
+    ("KeyY", KeyCode::KeyY),
+    ("KeyZ", KeyCode::KeyZ),
+    ("Numpad0", KeyCode::Numpad0),
+    ("Numpad1", KeyCode::Numpad1),
+    ("Numpad2", KeyCode::Numpad2),
+    ("Numpad3", KeyCode::Numpad3),
+    ("Numpad4", KeyCode::Numpad4),
+    ("Numpad5", KeyCode::Numpad5),
+    ("Numpad6", KeyCode::Numpad6),
+    ("Numpad7", KeyCode::Numpad7),
+    ("Numpad8", KeyCode::Numpad8),
+    ("Numpad9", KeyCode::Numpad9),
+    ("NumpadAdd", KeyCode::NumpadAdd),
+    ("NumpadSubtract", KeyCode::NumpadSubtract),
+    ("NumpadMultiply", KeyCode::NumpadMultiply),
+    ("NumpadDivide", KeyCode::NumpadDivide),
+    ("NumpadDecimal", KeyCode::NumpadDecimal),
+    ("NumpadEnter", KeyCode::NumpadEnter),
+    ("ArrowUp", KeyCode::ArrowUp),
+    ("ArrowDown", KeyCode::ArrowDown),
+    ("ArrowLeft", KeyCode::ArrowLeft),
+    ("ArrowRight", KeyCode::ArrowRight),
+    ("Space", KeyCode::Space),
+    ("Enter", KeyCode::Enter),
+    ("Tab", KeyCode::Tab),
+    ("ShiftLeft", KeyCode::ShiftLeft),
+    ("ShiftRight", KeyCode::ShiftRight),
+    ("ControlLeft", KeyCode::ControlLeft),
+    ("ControlRight", KeyCode::ControlRight),
+    ("AltLeft", KeyCode::AltLeft),
+    ("AltRight", KeyCode::AltRight),
+    ("Comma", KeyCode::Comma),
+    ("Period", KeyCode::Period),
+    ("Slash", KeyCode::Slash),
+    ("Semicolon", KeyCode::Semicolon),
+    ("Quote", KeyCode::Quote),
+    ("BracketLeft", KeyCode::BracketLeft),
+    ("BracketRight", KeyCode::BracketRight),
+    ("Backslash", KeyCode::Backslash),
+    ("Minus", KeyCode::Minus),
+    ("Equal", KeyCode::Equal),
+    ("Backquote", KeyCode::Backquote),
+    ("Insert", KeyCode::Insert),
+    ("Delete", KeyCode::Delete),
+    ("Home", KeyCode::Home),
+    ("End", KeyCode::End),
+    ("PageUp", KeyCode::PageUp),
+    ("PageDown", KeyCode::PageDown),
+];
+
+/// Parse a host key name. Takes the winit names (`KeyW`, `Digit1`,
+/// `ArrowUp`, ...) in any case, plus the shorthands `w`, `1`, `up`, `down`,
+/// `left` and `right`.
+pub fn parse_key(name: &str) -> Option<KeyCode> {
+    if let Some((_, code)) = KEY_NAMES.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)) {
+        return Some(*code);
+    }
+    let long = match name.to_ascii_lowercase().as_str() {
+        "up" | "down" | "left" | "right" => format!("Arrow{}", name),
+        short if short.len() == 1 && short.as_bytes()[0].is_ascii_digit() => {
+            format!("Digit{}", short)
+        }
+        short if short.len() == 1 && short.as_bytes()[0].is_ascii_lowercase() => {
+            format!("Key{}", short)
+        }
+        _ => return None,
+    };
+    KEY_NAMES
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(&long))
+        .map(|(_, code)| *code)
+}
+
+pub fn key_name(code: KeyCode) -> Option<&'static str> {
+    KEY_NAMES.iter().find(|(_, c)| *c == code).map(|(n, _)| *n)
+}
+
+/// Which host keys press each CHIP-8 key. A CHIP-8 key can have any number
+/// of host keys, a host key drives at most one CHIP-8 key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keymap {
+    keys: [Vec<KeyCode>; 16],
+}
+
+impl Default for Keymap {
+    /// The usual 1234/QWER/ASDF/ZXCV layout.
+    fn default() -> Self {
+        let rows = [
+            [
+                KeyCode::Digit1,
+                KeyCode::Digit2,
+                KeyCode::Digit3,
+                KeyCode::Digit4,
+            ],
+            [KeyCode::KeyQ, KeyCode::KeyW, KeyCode::KeyE, KeyCode::KeyR],
+            [KeyCode::KeyA, KeyCode::KeyS, KeyCode::KeyD, KeyCode::KeyF],
+            [KeyCode::KeyZ, KeyCode::KeyX, KeyCode::KeyC, KeyCode::KeyV],
+        ];
+        let mut keymap = Keymap::empty();
+        for (chip8_key, code) in KEYPAD_LAYOUT.iter().zip(rows.as_flattened()) {
+            keymap.keys[*chip8_key as usize].push(*code);
+        }
+        keymap
+    }
+}
+
+impl Keymap {
+    pub fn empty() -> Self {
+        Self {
+            keys: Default::default(),
+        }
+    }
+
+    /// Host keys bound to `chip8_key`.
+    pub fn keys(&self, chip8_key: u8) -> &[KeyCode] {
+        &self.keys[chip8_key as usize & 0xF]
+    }
+
+    /// Bind `codes` to `chip8_key`, replacing its old keys. The codes are
+    /// taken away from any other CHIP-8 key they were bound to.
+    pub fn bind(&mut self, chip8_key: u8, codes: &[KeyCode]) {
+        for keys in &mut self.keys {
+            keys.retain(|code| !codes.contains(code));
+        }
+        self.keys[chip8_key as usize & 0xF] = codes.to_vec();
+    }
+
+    /// The CHIP-8 key `code` is bound to.
+    pub fn lookup(&self, code: KeyCode) -> Option<u8> {
+        self.keys
+            .iter()
+            .position(|keys| keys.contains(&code))
+            .map(|k| k as u8)
+    }
+
+    /// `chip8_key = Name, Name` for every CHIP-8 key whose binding differs
+    /// from `base`.
+    fn diff_lines(&self, base: &Keymap) -> String {
+        let mut text = String::new();
+        for chip8_key in KEYPAD_LAYOUT {
+            let keys = self.keys(chip8_key);
+            if keys == base.keys(chip8_key) {
+                continue;
+            }
+            let names: Vec<&str> = keys.iter().filter_map(|&c| key_name(c)).collect();
+            let line = format!("{:X} = {}", chip8_key, names.join(", "));
+            text += line.trim_end();
+            text += "\n";
+        }
+        text
+    }
+}
+
+type Bindings = Vec<(u8, Vec<KeyCode>)>;
+
+// Built-in profiles: two players on one keyboard, each with their own
+// cluster bound at the same time.
+type BuiltinBindings = &'static [(u8, &'static [KeyCode])];
+
+const BUILTIN_PROFILES: &[(&str, BuiltinBindings)] = &[
+    // left paddle on 1/4, right paddle on C/D
+    (
+        "pong",
+        &[
+            (0x1, &[KeyCode::KeyW]),
+            (0x4, &[KeyCode::KeyS]),
+            (0xC, &[KeyCode::ArrowUp]),
+            (0xD, &[KeyCode::ArrowDown]),
+        ],
+    ),
+    // one player on the 2/4/6/8 cross plus 5, the other on the numpad
+    (
+        "cross",
+        &[
+            (0x2, &[KeyCode::KeyW, KeyCode::Numpad8]),
+            (0x4, &[KeyCode::KeyA, KeyCode::Numpad4]),
+            (0x6, &[KeyCode::KeyD, KeyCode::Numpad6]),
+            (0x8, &[KeyCode::KeyS, KeyCode::Numpad2]),
+            (0x5, &[KeyCode::Space, KeyCode::Numpad5]),
+        ],
+    ),
+];
+
+// Per-ROM settings: an optional profile and bindings on top of it.
+#[derive(Debug, Clone, Default)]
+struct RomSection {
+    profile: Option<String>,
+    bindings: Bindings,
+}
+
+/// A keymap file: the bindings every ROM gets, named profiles, and per-ROM
+/// overrides keyed by the ROM's SHA-1.
+///
+/// The file is `chip8_key = host keys` lines, e.g. `5 = KeyW, ArrowUp`,
+/// with `#` comments. Lines after a `[profile <name>]` header define a
+/// profile, lines after a `[<sha1>]` header only apply to that ROM. Both
+/// replace the listed keys; keys a section doesn't list keep their default
+/// binding. A ROM section can start from a profile with `profile = <name>`.
+#[derive(Debug, Clone, Default)]
+pub struct KeymapConfig {
+    pub default: Keymap,
+    profiles: BTreeMap<String, Bindings>,
+    roms: BTreeMap<String, RomSection>,
+}
+
+enum Section {
+    Default,
+    Profile(String),
+    Rom(String),
+}
+
+impl KeymapConfig {
+    pub fn load(path: &Path) -> Result<Self, io::Error> {
+        let text = fs::read_to_string(path)?;
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+        let mut config = KeymapConfig::default();
+        let mut section = Section::Default;
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(header) = line.strip_prefix('[') {
+                let header = header
+                    .strip_suffix(']')
+                    .ok_or_else(|| invalid(format!("invalid section: {}", line)))?
+                    .trim();
+                section = match header.strip_prefix("profile ") {
+                    Some(name) => {
+                        let name = name.trim().to_string();
+                        config.profiles.entry(name.clone()).or_default();
+                        Section::Profile(name)
+                    }
+                    None => {
+                        let sha1 = header.to_ascii_lowercase();
+                        config.roms.entry(sha1.clone()).or_default();
+                        Section::Rom(sha1)
+                    }
+                };
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| invalid(format!("expected key = value: {}", line)))?;
+            let (key, value) = (key.trim(), value.trim());
+            if key == "profile" {
+                match &section {
+                    Section::Rom(sha1) => {
+                        config.roms.get_mut(sha1).unwrap().profile = Some(value.to_string())
+                    }
+                    _ => return Err(invalid("profile = only goes in ROM sections".to_string())),
+                }
+                continue;
+            }
+            let chip8_key = u8::from_str_radix(key, 16)
+                .ok()
+                .filter(|&k| k < 16)
+                .ok_or_else(|| invalid(format!("invalid CHIP-8 key: {}", key)))?;
+            let codes = value
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(|name| {
+                    parse_key(name).ok_or_else(|| invalid(format!("unknown key: {}", name)))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            match &section {
+                Section::Default => config.default.bind(chip8_key, &codes),
+                Section::Profile(name) => config
+                    .profiles
+                    .get_mut(name)
+                    .unwrap()
+                    .push((chip8_key, codes)),
+                Section::Rom(sha1) => config
+                    .roms
+                    .get_mut(sha1)
+                    .unwrap()
+                    .bindings
+                    .push((chip8_key, codes)),
+            }
+        }
+        for (sha1, rom) in &config.roms {
+            if let Some(name) = &rom.profile
+                && config.profile(name).is_none()
+            {
+                return Err(invalid(format!("[{}]: unknown profile {}", sha1, name)));
+            }
+        }
+        Ok(config)
+    }
+
+    /// `load`, or the built-in layout if `path` doesn't exist.
+    pub fn load_or_default(path: &Path) -> Result<Self, io::Error> {
+        match Self::load(path) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            result => result,
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), io::Error> {
+        let mut text = String::from("# CHIP-8 key = host keys\n");
+        text += &self.default.diff_lines(&Keymap::empty());
+        for (name, bindings) in &self.profiles {
+            text += &format!("\n[profile {}]\n", name);
+            text += &apply(&self.default, bindings).diff_lines(&self.default);
+        }
+        for (sha1, rom) in &self.roms {
+            text += &format!("\n[{}]\n", sha1);
+            let base = self.base(rom.profile.as_deref());
+            if let Some(name) = &rom.profile {
+                text += &format!("profile = {}\n", name);
+            }
+            text += &apply(&base, &rom.bindings).diff_lines(&base);
+        }
+        fs::write(path, text)
+    }
+
+    /// Built-in and user profile names; user profiles replace built-ins of
+    /// the same name.
+    pub fn profile_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = BUILTIN_PROFILES
+            .iter()
+            .map(|(name, _)| name.to_string())
+            .chain(self.profiles.keys().cloned())
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    /// The default keymap with profile `name` applied.
+    pub fn profile(&self, name: &str) -> Option<Keymap> {
+        if let Some(bindings) = self.profiles.get(name) {
+            return Some(apply(&self.default, bindings));
+        }
+        let (_, bindings) = BUILTIN_PROFILES.iter().find(|(n, _)| *n == name)?;
+        let mut keymap = self.default.clone();
+        for (chip8_key, codes) in *bindings {
+            keymap.bind(*chip8_key, codes);
+        }
+        Some(keymap)
+    }
+
+    /// The keymap for the ROM with hash `sha1`: its profile, or `profile`
+    /// instead when given, then its own bindings. `None` for an unknown
+    /// profile.
+    pub fn keymap(&self, sha1: &str, profile: Option<&str>) -> Option<Keymap> {
+        let rom = self.roms.get(sha1);
+        let profile = profile.or(rom.and_then(|rom| rom.profile.as_deref()));
+        if let Some(name) = profile {
+            self.profile(name)?;
+        }
+        let base = self.base(profile);
+        Some(match rom {
+            Some(rom) => apply(&base, &rom.bindings),
+            None => base,
+        })
+    }
+
+    /// Whether the file has a section for the ROM with hash `sha1`.
+    pub fn has_rom(&self, sha1: &str) -> bool {
+        self.roms.contains_key(sha1)
+    }
+
+    /// The profile the ROM with hash `sha1` is set to use.
+    pub fn rom_profile(&self, sha1: &str) -> Option<&str> {
+        self.roms.get(sha1)?.profile.as_deref()
+    }
+
+    /// Store `keymap` as the override for the ROM with hash `sha1`, on top
+    /// of `profile`, keeping only the keys that differ from it.
+    pub fn set_rom(&mut self, sha1: &str, keymap: &Keymap, profile: Option<&str>) {
+        let base = self.base(profile);
+        let bindings = KEYPAD_LAYOUT
+            .iter()
+            .filter(|&&k| keymap.keys(k) != base.keys(k))
+            .map(|&k| (k, keymap.keys(k).to_vec()))
+            .collect();
+        let rom = RomSection {
+            profile: profile.map(str::to_string),
+            bindings,
+        };
+        self.roms.insert(sha1.to_string(), rom);
+    }
+
+    fn base(&self, profile: Option<&str>) -> Keymap {
+        profile
+            .and_then(|name| self.profile(name))
+            .unwrap_or_else(|| self.default.clone())
+    }
+}
+
+fn apply(base: &Keymap, bindings: &[(u8, Vec<KeyCode>)]) -> Keymap {
+    let mut keymap = base.clone();
+    for (chip8_key, codes) in bindings {
+        keymap.bind(*chip8_key, codes);
+    }
+    keymap
+}
+
+/// Interactive rebinding: walks the keypad in layout order, binding each
+/// CHIP-8 key to the next host key pressed.
+#[derive(Debug, Clone)]
+pub struct Remapper {
+    keymap: Keymap,
+    slot: usize,
+}
+
+impl Remapper {
+    pub fn new(keymap: Keymap) -> Self {
+        Self { keymap, slot: 0 }
+    }
+
+    /// The CHIP-8 key waiting for a binding, `None` once all are done.
+    pub fn current_key(&self) -> Option<u8> {
+        KEYPAD_LAYOUT.get(self.slot).copied()
+    }
+
+    /// Bind the current key to `code` alone and move on. Keys that can't be
+    /// saved to a keymap file are refused.
+    pub fn press(&mut self, code: KeyCode) -> bool {
+        let Some(chip8_key) = self.current_key() else {
+            return false;
+        };
+        if key_name(code).is_none() {
+            return false;
+        }
+        self.keymap.bind(chip8_key, &[code]);
+        self.slot += 1;
+        true
+    }
+
+    /// Keep the current key's binding and move on.
+    pub fn skip(&mut self) {
+        self.slot = (self.slot + 1).min(KEYPAD_LAYOUT.len());
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.current_key().is_none()
+    }
+
+    pub fn keymap(&self) -> &Keymap {
+        &self.keymap
+    }
+
+    pub fn prompt(&self) -> String {
+        match self.current_key() {
+            Some(chip8_key) => {
+                let names: Vec<&str> = self
+                    .keymap
+                    .keys(chip8_key)
+                    .iter()
+                    .filter_map(|&c| key_name(c))
+                    .collect();
+                format!(
+                    "Press a key for CHIP-8 key {:X} (now {}), Backspace keeps it, Esc cancels",
+                    chip8_key,
+                    if names.is_empty() {
+                        "unbound".to_string()
+                    } else {
+                        names.join(", ")
+                    }
+                )
+            }
+            None => "Keymap done".to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHA1: &str = "0123456789abcdef0123456789abcdef01234567";
+
+    // `text` written to a keymap file of its own and loaded
+    fn load(name: &str, text: &str) -> Result<KeymapConfig, io::Error> {
+        let path =
+            std::env::temp_dir().join(format!("chip8-keymap-{}-{}.cfg", std::process::id(), name));
+        fs::write(&path, text).unwrap();
+        let config = KeymapConfig::load(&path);
+        let _ = fs::remove_file(&path);
+        config
+    }
+
+    #[test]
+    fn key_names_and_shorthands_parse() {
+        assert_eq!(parse_key("KeyW"), Some(KeyCode::KeyW));
+        assert_eq!(parse_key("keyw"), Some(KeyCode::KeyW));
+        assert_eq!(parse_key("w"), Some(KeyCode::KeyW));
+        assert_eq!(parse_key("7"), Some(KeyCode::Digit7));
+        assert_eq!(parse_key("up"), Some(KeyCode::ArrowUp));
+        assert_eq!(parse_key("Left"), Some(KeyCode::ArrowLeft));
+        assert_eq!(parse_key("nope"), None);
+        assert_eq!(key_name(KeyCode::Numpad5), Some("Numpad5"));
+    }
+
+    #[test]
+    fn binding_a_key_takes_it_from_the_old_one() {
+        let mut keymap = Keymap::default();
+        assert_eq!(keymap.lookup(KeyCode::KeyW), Some(0x5));
+        keymap.bind(0x2, &[KeyCode::KeyW, KeyCode::ArrowUp]);
+        assert_eq!(keymap.lookup(KeyCode::KeyW), Some(0x2));
+        assert!(keymap.keys(0x5).is_empty());
+        assert_eq!(keymap.keys(0x2), [KeyCode::KeyW, KeyCode::ArrowUp]);
+    }
+
+    #[test]
+    fn sections_layer_on_the_default() {
+        let text = format!(
+            "# comment\n\
+             5 = up\n\
+             [profile arrows]\n\
+             8 = down # trailing comment\n\
+             [{}]\n\
+             profile = arrows\n\
+             4 = left\n",
+            SHA1.to_ascii_uppercase()
+        );
+        let config = load("sections", &text).unwrap();
+        assert_eq!(config.default.keys(0x5), [KeyCode::ArrowUp]);
+        assert_eq!(config.default.keys(0x8), [KeyCode::KeyS]);
+
+        let arrows = config.profile("arrows").unwrap();
+        assert_eq!(arrows.keys(0x5), [KeyCode::ArrowUp]);
+        assert_eq!(arrows.keys(0x8), [KeyCode::ArrowDown]);
+
+        assert!(config.has_rom(SHA1));
+        assert_eq!(config.rom_profile(SHA1), Some("arrows"));
+        let rom = config.keymap(SHA1, None).unwrap();
+        assert_eq!(rom.keys(0x4), [KeyCode::ArrowLeft]);
+        assert_eq!(rom.keys(0x8), [KeyCode::ArrowDown]);
+
+        // another profile picked on top of the ROM's own bindings
+        let pong = config.keymap(SHA1, Some("pong")).unwrap();
+        assert_eq!(pong.keys(0xC), [KeyCode::ArrowUp]);
+        assert_eq!(pong.keys(0x4), [KeyCode::ArrowLeft]);
+        assert_eq!(config.keymap(SHA1, Some("missing")), None);
+    }
+
+    #[test]
+    fn builtin_profiles_bind_both_players() {
+        let config = KeymapConfig::default();
+        let cross = config.profile("cross").unwrap();
+        assert_eq!(cross.lookup(KeyCode::KeyW), Some(0x2));
+        assert_eq!(cross.lookup(KeyCode::Numpad8), Some(0x2));
+        assert!(config.profile_names().contains(&"pong".to_string()));
+    }
+
+    #[test]
+    fn bad_files_are_refused() {
+        for (i, text) in [
+            "G = w\n",
+            "5 = nokey\n",
+            "5 w\n",
+            "[profile x\n",
+            "profile = pong\n",
+            "[0123]\nprofile = missing\n",
+        ]
+        .iter()
+        .enumerate()
+        {
+            assert!(load(&format!("bad-{}", i), text).is_err(), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn saved_files_load_back() {
+        let mut config = load("save-in", "5 = up\n[profile p]\n8 = down\n").unwrap();
+        let mut keymap = config.profile("p").unwrap();
+        keymap.bind(0xF, &[KeyCode::Space]);
+        config.set_rom(SHA1, &keymap, Some("p"));
+
+        let path =
+            std::env::temp_dir().join(format!("chip8-keymap-{}-saved.cfg", std::process::id()));
+        config.save(&path).unwrap();
+        let loaded = KeymapConfig::load(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        assert_eq!(loaded.default, config.default);
+        assert_eq!(loaded.profile("p"), config.profile("p"));
+        assert_eq!(loaded.rom_profile(SHA1), Some("p"));
+        assert_eq!(loaded.keymap(SHA1, None), Some(keymap));
+    }
+
+    #[test]
+    fn remapper_walks_the_keypad() {
+        let mut remapper = Remapper::new(Keymap::default());
+        assert_eq!(remapper.current_key(), Some(KEYPAD_LAYOUT[0]));
+        assert!(remapper.press(KeyCode::KeyI));
+        remapper.skip();
+        assert_eq!(remapper.current_key(), Some(KEYPAD_LAYOUT[2]));
+        assert_eq!(remapper.keymap().keys(KEYPAD_LAYOUT[0]), [KeyCode::KeyI]);
+        for _ in 2..16 {
+            remapper.skip();
+        }
+        assert!(remapper.is_done());
+        assert!(!remapper.press(KeyCode::KeyO));
+    }
+}
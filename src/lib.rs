@@ -0,0 +1,32 @@
+pub mod analysis;
+pub mod browser;
+pub mod cartridge;
+pub mod chip8;
+pub mod config;
+pub mod disasm;
+pub mod export;
+pub mod font;
+pub mod framebuffer;
+pub mod input;
+pub mod keymap;
+pub mod movie;
+pub mod octo;
+pub mod overlay;
+pub mod palette;
+pub mod patch;
+pub mod phosphor;
+pub mod postfx;
+pub mod quirks;
+pub mod random;
+pub mod recorder;
+pub mod rom;
+pub mod romdb;
+pub mod runner;
+pub mod savedata;
+pub mod screenshot;
+pub mod viewport;
+
+pub use chip8::Chip8;
+pub use framebuffer::FrameBuffer;
+pub use input::InputHandler;
+pub use palette::Palette;
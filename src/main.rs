@@ -1,102 +1,929 @@
+use chip8_emulator::Palette;
+use chip8_emulator::analysis;
+use chip8_emulator::browser::{self, Browser, BrowserAction, RecentRoms};
+use chip8_emulator::chip8::{MAX_ROM_SIZE, MEMORY_SIZE};
+use chip8_emulator::config::{self, Config, Setting};
+use chip8_emulator::disasm;
+use chip8_emulator::export::{WavWriter, Y4mWriter};
+use chip8_emulator::font::Font;
+use chip8_emulator::framebuffer::{FrameBuffer, LORES_HEIGHT, LORES_WIDTH};
+use chip8_emulator::input::DEFAULT_TURBO_RATE;
+use chip8_emulator::keymap::{self, Keymap, KeymapConfig, Remapper};
+use chip8_emulator::movie::{Movie, MovieState};
+use chip8_emulator::octo;
+use chip8_emulator::overlay::{self, Anchor};
+use chip8_emulator::palette::{Rgb, parse_color};
+use chip8_emulator::patch::{self, Patch};
+use chip8_emulator::phosphor::{DEFAULT_FADE_FRAMES, PhosphorFilter, PhosphorMode};
+use chip8_emulator::postfx::{self, PostFilter};
+use chip8_emulator::quirks::Quirks;
+use chip8_emulator::recorder::Recorder;
+use chip8_emulator::rom::{self, RomFormat, RomImage};
+use chip8_emulator::romdb::{self, RomDatabase, RomInfo};
+use chip8_emulator::runner::{FRAME_HZ, Runner};
+use chip8_emulator::savedata::{self, SaveSlot};
+use chip8_emulator::screenshot;
+use chip8_emulator::viewport::{Rect, ScaleMode, Scaler};
 use crossbeam_channel::{select, unbounded};
 use pixels::{Error, Pixels, SurfaceTexture};
-use rand::Rng;
 use std::collections::HashMap;
-use std::fs;
-use std::io;
+use std::io::{self, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU16, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::{Duration, Instant};
 use winit::dpi::LogicalSize;
-use winit::event::{ElementState, Event, KeyEvent, StartCause, WindowEvent};
+use winit::event::{
+    ElementState, Event, KeyEvent, MouseButton, StartCause, TouchPhase, WindowEvent,
+};
 use winit::event_loop::{ControlFlow, EventLoop};
-use winit::keyboard::{KeyCode, PhysicalKey};
-use winit::window::WindowBuilder;
-use winit_input_helper::WinitInputHelper;
+use winit::keyboard::{KeyCode, ModifiersState, PhysicalKey};
+use winit::window::{Fullscreen, WindowBuilder};
 
-const FPS: f32 = 16.67; // 60 frames per second or 60 Hz
 const FPS60: Duration = Duration::from_micros(16_67);
 // const FPS60: Duration = Duration::from_secs(3);
-const SCREEN_WIDTH: u8 = 64;
-const SCREEN_HEIGHT: u8 = 32;
-const FONTSET_START_ADDRESS: u16 = 0x50;
 
-const MEMORY_SIZE: usize = 4096;
-const PROGRAM_START_LOC: usize = 0x200;
+const INSTRUCTION_HZ: u64 = 700;
 
-const FONT_START_LOC: usize = 0x50;
+const TITLE: &str = "Chip8 Emulator";
 
-const INSTRUCTION_HZ: u64 = 700;
-const RENDER_HZ: u64 = 120;
-const TIMER_HZ: u64 = 60;
-
-const FONTSET: [u8; 80] = [
-    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
-    0x20, 0x60, 0x20, 0x20, 0x70, // 1
-    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
-    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
-    0x90, 0x90, 0xF0, 0x10, 0x10, // 4
-    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
-    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
-    0xF0, 0x10, 0x20, 0x40, 0x40, // 7
-    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
-    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
-    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
-    0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
-    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
-    0xE0, 0x90, 0x90, 0x90, 0xE0, // D
-    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
-    0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+const USAGE: &str = "usage: chip8-emulator [run] [options] [rom]
+       chip8-emulator headless [options] <rom>
+       chip8-emulator test [options] --expect <png> <rom>
+       chip8-emulator bench [options] <rom>
+       chip8-emulator info <rom>
+       chip8-emulator disasm [-o <file>] <rom>
+       chip8-emulator asm [-o <file>] <source.8o>
+       chip8-emulator config dump [options] [rom]
+
+commands:
+  run                    play a ROM in a window (the default)
+  headless               run a ROM without a window or audio device
+  test                   run a ROM headless and compare the final screen
+                         with a PNG
+  bench                  run a ROM as fast as possible and report the speed
+  info                   what the ROM database and static analysis say
+  disasm                 list a ROM as Octo statements
+  asm                    assemble Octo source into a ROM
+  config dump            the settings the config file and options add up to
+
+exit status: 0 on success, 1 when the command fails (a failed test or
+--verify included), 2 for an invalid command line or config file.
+
+A rom is a raw image (.ch8, .c8, .sc8, .xo8), Octo source (.8o) or an Octo
+cartridge (.gif), or - to read it from stdin.
+
+Settings are read from a TOML config file first, then the command line.
+Its keys are option names: top-level keys apply everywhere, [profiles.NAME]
+tables when that profile is picked and [roms.SHA1] tables to one ROM, e.g.
+
+  palette = \"green\"
+  config-profile = \"modern\"
+  [profiles.schip]
+  quirks = \"schip\"
+  speed = 1000
+  [roms.0123...cdef]
+  config-profile = \"schip\"
+
+config dump prints the settings that add up for a ROM and where each
+comes from.
+
+options:
+  --config <file>        config file (default config.toml in the user
+                         config directory, e.g. ~/.config/chip8-emulator)
+  --config-profile <name>
+                         profile from the config file, over the one it picks
+  --speed <hz>           instructions per second, over the ROM database
+                         (default 700)
+  --scale <n>            window size as a multiple of 64x32 (default 10)
+  --fullscreen           start in fullscreen (toggle: F11 or Alt+Enter)
+  --scaling <mode>       how the screen fills the window: integer (whole
+                         multiples, default), fit (keep the shape) or
+                         stretch (cycle: Shift+F11)
+  --border <#RRGGBB>     colour around the screen (default black)
+  --palette <name|file>  colour palette: classic, green, amber, gameboy,
+                         high-contrast, colorblind, or a palette file
+  --fg <#RRGGBB>         foreground (plane 1) colour
+  --bg <#RRGGBB>         background colour
+  --plane2 <#RRGGBB>     XO-CHIP plane 2 colour
+  --blend <#RRGGBB>      XO-CHIP colour where both planes are set
+  --phosphor <mode>      anti-flicker filter: off, fade, blend (OR of the
+                         last two frames)
+  --fade-frames <n>      frames a pixel takes to fade out (default 4)
+  --filter <name>        post-processing filter: none, scanlines, grid,
+                         bloom, rounded, smooth
+  --filter-scale <n>     upscale factor the filter works at (default 4)
+  --screenshot-dir <dir> where F12 saves screenshots and F9 recordings
+                         (default screenshots)
+  --record <file>        record gameplay from startup to a .gif or .png
+                         (APNG) file at 60 fps
+  --rom-db <file>        ROM database in the community chip-8-database
+                         JSON format, over the built-in one (default
+                         romdb.json if present)
+  --patch <file>         apply an IPS or BPS patch to the ROM; repeat for
+                         several (game.ips and game.bps next to game.ch8
+                         are applied too)
+  --no-auto-patch        don't apply patches found next to the ROM
+  --quirks <quirks>      interpreter quirks: a preset (chip8, schip,
+                         xochip, default), none, or a comma separated list
+                         of vf_reset, memory, shifting, jumping, clipping
+  --record-movie <file>  record keypad input per frame to a movie file; a
+                         ROM loaded from the browser starts it over
+  --play-movie <file>    replay a movie recorded with the same ROM
+  --keymap <file>        key bindings with per-ROM overrides, saved by F6
+                         (default keymap.cfg)
+  --profile <name>       keymap profile from the keymap file or built in:
+                         pong (W/S and Up/Down) or cross (WASD and the
+                         numpad), for two players on one keyboard
+  --turbo <keys>         CHIP-8 keys that auto-fire while held, e.g. 5,6
+  --turbo-rate <n>       auto-fire presses per second (default 10)
+  --keypad               show the clickable on-screen keypad (toggle: F7)
+  --rom-dir <dir>        where the ROM browser looks for ROMs (default roms)
+  --font <name|file>     hex digit font: chip48, vip, dream6800, eti660,
+                         or an 80 byte font file
+  --font-address <addr>  where the font is loaded, in hex (default 050)
+  --save-dir <dir>       where SCHIP flags and save data are kept between
+                         runs, a file per ROM (default saves in the user
+                         data directory)
+  --save-memory <range>  also keep this memory range of the ROM, e.g.
+                         300-31f; remembered in its save file
+  --seed <n>             seed for the Cxkk random numbers (default random,
+                         0 when headless); a played movie uses its own
+
+headless, test and bench options:
+  --headless             same as the headless command
+  --frames <n>           frames (1/60 s) to run (default 600)
+  --video <file>         write scaled, filtered frames as a Y4M stream
+  --audio <file>         write the buzzer as a 16-bit 44.1 kHz WAV
+  --verify               with --play-movie, exit with status 1 unless the
+                         final state matches the recording
+  --expect <png>         test: the screen the ROM should end on, as saved
+                         by --update
+  --update               test: save the final screen to --expect instead
+
+disasm and asm options:
+  -o, --output <file>    where to write (default: disasm to stdout, asm to
+                         the source file with .ch8; - for stdout)
+
+Without a rom the ROM browser opens. A ROM file dropped on the window is
+loaded in place of the running one.
+
+hotkeys:
+  F1                     open/close the ROM browser
+  F2                     cycle built-in palettes
+  Shift+F2               cycle built-in fonts
+  F3                     cycle phosphor filter modes
+  F4                     cycle post-processing filters
+  F5                     soft reset: restart the program, memory kept
+  Shift+F5               hard reset: reload the ROM into wiped memory
+  F6                     remap the keypad for this ROM
+  F7                     show/hide the on-screen keypad
+  F8                     toggle auto-fire on the CHIP-8 keys held down
+  F11, Alt+Enter         toggle fullscreen
+  Shift+F11              cycle scaling modes
+  F10                    start/stop recording a macro; the next key
+                         pressed plays it back (Esc discards it)
+  F9                     start/stop recording a GIF
+  F12                    save a screenshot (native and scaled PNG)";
+
+// Options whose value is a decimal number, an integer in the config file.
+const NUMBERS: [&str; 7] = [
+    "--fade-frames",
+    "--filter-scale",
+    "--frames",
+    "--seed",
+    "--turbo-rate",
+    "--speed",
+    "--scale",
 ];
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Command {
+    Run,
+    Headless,
+    Test,
+    Bench,
+    Info,
+    Disasm,
+    Asm,
+    DumpConfig, // `config dump`
+}
+
+impl Command {
+    fn name(self) -> &'static str {
+        match self {
+            Command::Run => "run",
+            Command::Headless => "headless",
+            Command::Test => "test",
+            Command::Bench => "bench",
+            Command::Info => "info",
+            Command::Disasm => "disasm",
+            Command::Asm => "asm",
+            Command::DumpConfig => "config dump",
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Options {
+    command: Command,
+    rom: Option<String>,
+    palette: Option<Palette>,
+    phosphor: PhosphorMode,
+    fade_frames: u8,
+    filter: PostFilter,
+    filter_scale: usize,
+    screenshot_dir: PathBuf,
+    record: Option<PathBuf>,
+    frames: u64,
+    video: Option<PathBuf>,
+    audio: Option<PathBuf>,
+    quirks: Option<Quirks>,
+    rom_db: Option<PathBuf>,
+    patches: Vec<PathBuf>,
+    auto_patch: bool,
+    record_movie: Option<PathBuf>,
+    play_movie: Option<PathBuf>,
+    verify: bool,
+    seed: Option<u64>,
+    font: Option<Font>,
+    font_address: Option<u16>,
+    save_dir: PathBuf,
+    save_memory: Option<Range<u16>>,
+    keymap: PathBuf,
+    profile: Option<String>,
+    turbo: u16,
+    turbo_rate: u8,
+    keypad: bool,
+    rom_dir: PathBuf,
+    speed: Option<u64>,
+    scale: u32,
+    fullscreen: bool,
+    scaling: ScaleMode,
+    border: Rgb,
+    output: Option<PathBuf>,
+    expect: Option<PathBuf>,
+    update: bool,
+    config_path: Option<PathBuf>,
+    config_profile: Option<String>,
+    config: Config,      // as loaded, for `for_rom`
+    cli: Vec<String>,    // the command line after the command
+    given: Vec<Setting>, // the options on the command line, for `config dump`
+}
+
+impl Options {
+    /// The options again with the config file's settings for the ROM with
+    /// `rom_sha1` in place of the general ones.
+    fn for_rom(&self, rom_sha1: &str) -> Result<Options, String> {
+        configured(
+            self.command,
+            &self.cli,
+            self.config.clone(),
+            Some(rom_sha1),
+            self.config_profile.as_deref(),
+        )
+    }
+}
+
+// What the window thread tells the emulator thread.
+enum WorkerMessage {
+    Key(KeyEvent),
+    SetKeymap(Box<Keymap>),
+    Pad(u8, bool), // on-screen keypad key pressed or released
+    ToggleTurbo,
+    ToggleMacro,
+    Load(Box<RomStart>), // run a freshly loaded ROM instead
+    Reset { hard: bool },
+    NextFont,
+}
+
+// What the window thread keeps about the running ROM.
+struct RomSettings {
+    title: Option<String>,
+    sha1: String,
+    palette: Palette,
+    profile: Option<String>,
+    keymap: Keymap,
+}
+
+// A ROM the window thread has loaded and checked, for the emulator thread to
+// start once the one running is saved and gone.
+struct RomStart {
+    options: Options, // for this ROM
+    image: Option<RomImage>,
+    info: Option<RomInfo>,
+    keymap: Keymap,
+    save: Option<SaveSlot>,
+}
+
+impl RomSettings {
+    fn window_title(&self) -> String {
+        match &self.title {
+            Some(title) => format!("{} - {}", TITLE, title),
+            None => TITLE.to_string(),
+        }
+    }
+}
+
+// `info` and `config dump` come first, then the options. The command line
+// alone says where the config file is; its settings then go in front of the
+// command line's so those win.
+fn parse_args() -> Result<Options, String> {
+    let mut cli: Vec<String> = std::env::args().skip(1).collect();
+    let (command, words) = match (cli.first().map(String::as_str), cli.get(1)) {
+        (Some("run"), _) => (Command::Run, 1),
+        (Some("headless"), _) => (Command::Headless, 1),
+        (Some("test"), _) => (Command::Test, 1),
+        (Some("bench"), _) => (Command::Bench, 1),
+        (Some("info"), _) => (Command::Info, 1),
+        (Some("disasm"), _) => (Command::Disasm, 1),
+        (Some("asm"), _) => (Command::Asm, 1),
+        (Some("config"), Some(dump)) if dump == "dump" => (Command::DumpConfig, 2),
+        (Some("config"), _) => return Err("config needs a subcommand: dump".to_string()),
+        _ => (Command::Run, 0),
+    };
+    cli.drain(..words);
+    let base = parse_options(command, &cli)?;
+    let config = match &base.config_path {
+        Some(path) => Config::load(path),
+        None => config::default_path()
+            .map_or(Ok(Config::default()), |path| Config::load_or_default(&path)),
+    }
+    .map_err(|e| format!("config {}", e))?;
+    configured(command, &cli, config, None, base.config_profile.as_deref())
+}
+
+// Parse `cli` after the settings `config` has for the ROM, if any.
+fn configured(
+    command: Command,
+    cli: &[String],
+    config: Config,
+    rom_sha1: Option<&str>,
+    profile: Option<&str>,
+) -> Result<Options, String> {
+    let in_config = |e: String| match config.path() {
+        Some(path) => format!("{}: {}", path.display(), e),
+        None => e,
+    };
+    let mut args = config
+        .args(rom_sha1, profile)
+        .map_err(|e| in_config(e.to_string()))?;
+    args.extend(cli.iter().cloned());
+    // the command line was checked on its own, so errors are the config's
+    let mut options = parse_options(command, &args).map_err(in_config)?;
+    options.config = config;
+    options.cli = cli.to_vec();
+    options.given = parse_options(command, cli)?.given;
+    Ok(options)
+}
+
+fn parse_options(mut command: Command, args: &[String]) -> Result<Options, String> {
+    let mut rom = None;
+    let mut palette: Option<Palette> = None;
+    let mut phosphor = PhosphorMode::Off;
+    let mut fade_frames = DEFAULT_FADE_FRAMES;
+    let mut filter = PostFilter::None;
+    let mut filter_scale = postfx::DEFAULT_SCALE;
+    let mut screenshot_dir = PathBuf::from(screenshot::DEFAULT_DIR);
+    let mut record = None;
+    let mut headless = false;
+    let mut frames = 600;
+    let mut video = None;
+    let mut audio = None;
+    let mut quirks = None;
+    let mut rom_db = None;
+    let mut patches = Vec::new();
+    let mut auto_patch = true;
+    let mut record_movie = None;
+    let mut play_movie = None;
+    let mut verify = false;
+    let mut seed = None;
+    let mut font = None;
+    let mut font_address = None;
+    let mut save_dir = savedata::default_dir();
+    let mut save_memory = None;
+    let mut keymap = PathBuf::from(keymap::DEFAULT_PATH);
+    let mut profile = None;
+    let mut turbo = 0;
+    let mut turbo_rate = DEFAULT_TURBO_RATE;
+    let mut keypad = false;
+    let mut rom_dir = PathBuf::from(browser::DEFAULT_DIR);
+    let mut speed = None;
+    let mut scale = 10;
+    let mut fullscreen = false;
+    let mut scaling = ScaleMode::Integer;
+    let mut border = [0, 0, 0];
+    let mut output = None;
+    let mut expect = None;
+    let mut update = false;
+    let mut config_path = None;
+    let mut config_profile = None;
+    // applied over whichever palette ends up chosen
+    let mut colors = Vec::new();
+    let mut given = Vec::new();
+    let mut args = args.iter().cloned();
+    while let Some(arg) = args.next() {
+        let mut taken = None;
+        let mut value = || {
+            let text = args.next().ok_or(format!("{} needs a value", arg))?;
+            taken = Some(text.clone());
+            Ok::<_, String>(text)
+        };
+        match arg.as_str() {
+            "-h" | "--help" => {
+                // ignore a closed pipe, e.g. `--help | head`
+                let _ = writeln!(io::stdout(), "{}", USAGE);
+                std::process::exit(0);
+            }
+            "--palette" => {
+                let name = value()?;
+                palette = Some(match Palette::builtin(&name) {
+                    Some(builtin) => builtin,
+                    None => Palette::load(Path::new(&name))
+                        .map_err(|e| format!("palette {}: {}", name, e))?,
+                });
+            }
+            "--fg" | "--bg" | "--plane2" | "--blend" => {
+                let text = value()?;
+                let color = parse_color(&text).ok_or(format!("invalid colour: {}", text))?;
+                colors.push((arg[2..].to_string(), color));
+            }
+            "--phosphor" => {
+                let name = value()?;
+                phosphor =
+                    PhosphorMode::parse(&name).ok_or(format!("unknown phosphor mode: {}", name))?;
+            }
+            "--fade-frames" => {
+                let text = value()?;
+                fade_frames = match text.parse() {
+                    Ok(n) if n > 0 => n,
+                    _ => return Err(format!("invalid fade frame count: {}", text)),
+                };
+            }
+            "--filter" => {
+                let name = value()?;
+                filter = PostFilter::parse(&name).ok_or(format!("unknown filter: {}", name))?;
+            }
+            "--filter-scale" => {
+                let text = value()?;
+                filter_scale = match text.parse() {
+                    Ok(n) if (1..=16).contains(&n) => n,
+                    _ => return Err(format!("invalid filter scale: {}", text)),
+                };
+            }
+            "--screenshot-dir" => screenshot_dir = PathBuf::from(value()?),
+            "--record" => record = Some(PathBuf::from(value()?)),
+            "--headless" => headless = true,
+            "--frames" => {
+                let text = value()?;
+                frames = text
+                    .parse()
+                    .map_err(|_| format!("invalid frame count: {}", text))?;
+            }
+            "--video" => video = Some(PathBuf::from(value()?)),
+            "--audio" => audio = Some(PathBuf::from(value()?)),
+            "--quirks" => {
+                let text = value()?;
+                quirks = Some(Quirks::parse(&text).ok_or(format!("invalid quirks: {}", text))?);
+            }
+            "--rom-db" => rom_db = Some(PathBuf::from(value()?)),
+            "--patch" => patches.push(PathBuf::from(value()?)),
+            "--no-auto-patch" => auto_patch = false,
+            "--record-movie" => record_movie = Some(PathBuf::from(value()?)),
+            "--play-movie" => play_movie = Some(PathBuf::from(value()?)),
+            "--verify" => verify = true,
+            "--keymap" => keymap = PathBuf::from(value()?),
+            "--profile" => profile = Some(value()?),
+            "--turbo" => {
+                let text = value()?;
+                for key in text.split(',') {
+                    let key = u8::from_str_radix(key.trim(), 16)
+                        .ok()
+                        .filter(|&k| k < 16)
+                        .ok_or(format!("invalid CHIP-8 key: {}", key))?;
+                    turbo |= 1 << key;
+                }
+            }
+            "--turbo-rate" => {
+                let text = value()?;
+                turbo_rate = text
+                    .parse()
+                    .ok()
+                    .filter(|&rate| rate > 0)
+                    .ok_or(format!("invalid turbo rate: {}", text))?;
+            }
+            "--keypad" => keypad = true,
+            "--rom-dir" => rom_dir = PathBuf::from(value()?),
+            "--font" => {
+                let name = value()?;
+                font = Some(match Font::builtin(&name) {
+                    Some(builtin) => builtin,
+                    None => {
+                        Font::load(Path::new(&name)).map_err(|e| format!("font {}: {}", name, e))?
+                    }
+                });
+            }
+            "--font-address" => {
+                let text = value()?;
+                let address = u16::from_str_radix(text.trim_start_matches("0x"), 16)
+                    .ok()
+                    .filter(|&address| Font::valid_address(address))
+                    .ok_or(format!("invalid font address: {}", text))?;
+                font_address = Some(address);
+            }
+            "--save-dir" => save_dir = PathBuf::from(value()?),
+            "--save-memory" => {
+                let text = value()?;
+                let range = text
+                    .split_once('-')
+                    .and_then(|(start, end)| {
+                        let start = u16::from_str_radix(start, 16).ok()?;
+                        let end = u16::from_str_radix(end, 16).ok()?;
+                        (start <= end && (end as usize) < MEMORY_SIZE).then_some(start..end + 1)
+                    })
+                    .ok_or(format!("invalid memory range: {}", text))?;
+                save_memory = Some(range);
+            }
+            "--speed" => {
+                let text = value()?;
+                speed = Some(
+                    text.parse()
+                        .ok()
+                        .filter(|&hz| hz > 0)
+                        .ok_or(format!("invalid speed: {}", text))?,
+                );
+            }
+            "--scale" => {
+                let text = value()?;
+                scale = match text.parse() {
+                    Ok(n) if (1..=40).contains(&n) => n,
+                    _ => return Err(format!("invalid scale: {}", text)),
+                };
+            }
+            "--fullscreen" => fullscreen = true,
+            "--scaling" => {
+                let name = value()?;
+                scaling =
+                    ScaleMode::parse(&name).ok_or(format!("unknown scaling mode: {}", name))?;
+            }
+            "--border" => {
+                let text = value()?;
+                border = parse_color(&text).ok_or(format!("invalid colour: {}", text))?;
+            }
+            "-o" | "--output" => output = Some(PathBuf::from(value()?)),
+            "--expect" => expect = Some(PathBuf::from(value()?)),
+            "--update" => update = true,
+            "--config" => config_path = Some(PathBuf::from(value()?)),
+            "--config-profile" => config_profile = Some(value()?),
+            "--seed" => {
+                let text = value()?;
+                seed = Some(
+                    text.parse()
+                        .map_err(|_| format!("invalid seed: {}", text))?,
+                );
+            }
+            _ if arg.starts_with('-') && arg != rom::STDIN_PATH => {
+                return Err(format!("unknown option: {}", arg));
+            }
+            _ => {
+                rom = Some(arg);
+                continue;
+            }
+        }
+        let key = match arg.as_str() {
+            "-o" => "output",
+            long => &long[2..],
+        };
+        let value = match taken {
+            Some(text) if NUMBERS.contains(&arg.as_str()) => text
+                .parse()
+                .map_or(toml::Value::String(text), toml::Value::Integer),
+            Some(text) => toml::Value::String(text),
+            None => toml::Value::Boolean(true),
+        };
+        given.push(Setting {
+            key: key.to_string(),
+            value,
+            source: "command line".to_string(),
+        });
+    }
+    if headless && command == Command::Run {
+        command = Command::Headless;
+    }
+    let needs_rom = !matches!(command, Command::Run | Command::DumpConfig);
+    if needs_rom && rom.is_none() {
+        let what = if command == Command::Asm {
+            "a source file"
+        } else {
+            "a rom"
+        };
+        return Err(format!("{} needs {}", command.name(), what));
+    }
+    if command == Command::Test && expect.is_none() {
+        return Err("test needs --expect <png>".to_string());
+    }
+    if (expect.is_some() || update) && command != Command::Test {
+        return Err("--expect and --update are for test".to_string());
+    }
+    if verify && play_movie.is_none() {
+        return Err("--verify is for --play-movie".to_string());
+    }
+    if output.is_some() && !matches!(command, Command::Disasm | Command::Asm) {
+        return Err("--output is for disasm and asm".to_string());
+    }
+    for (name, color) in colors {
+        palette = palette.unwrap_or_default().with_color(&name, color);
+    }
+    Ok(Options {
+        command,
+        rom,
+        palette,
+        phosphor,
+        fade_frames,
+        filter,
+        filter_scale,
+        screenshot_dir,
+        record,
+        frames,
+        video,
+        audio,
+        quirks,
+        rom_db,
+        patches,
+        auto_patch,
+        record_movie,
+        play_movie,
+        verify,
+        seed,
+        font,
+        font_address,
+        save_dir,
+        save_memory,
+        keymap,
+        profile,
+        turbo,
+        turbo_rate,
+        keypad,
+        rom_dir,
+        speed,
+        scale,
+        fullscreen,
+        scaling,
+        border,
+        output,
+        expect,
+        update,
+        config_path,
+        config_profile,
+        config: Config::default(),
+        cli: Vec::new(),
+        given,
+    })
+}
+
 fn main() -> Result<(), Error> {
+    let options = match parse_args() {
+        Ok(options) => options,
+        Err(e) => {
+            let _ = writeln!(io::stderr(), "Error: {}\n\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
+    let result = match options.command {
+        Command::Run => return run_window(options),
+        Command::Headless => run_headless(options),
+        Command::Test => run_test(&options),
+        Command::Bench => run_bench(&options),
+        Command::Info => run_info(&options),
+        Command::Disasm => run_disasm(&options),
+        Command::Asm => run_asm(&options),
+        Command::DumpConfig => run_config_dump(&options),
+    };
+    if let Err(e) = result {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn run_window(options: Options) -> Result<(), Error> {
+    let mut phosphor = PhosphorFilter::new(options.phosphor, options.fade_frames);
+    let mut filter = options.filter;
+    let filter_scale = options.filter_scale;
+    // unfiltered frame when a post-processing filter is active
+    let mut native_frame: Vec<u8> = Vec::new();
+    let mut scaling = options.scaling;
+    // where the screen is in the window, for the on-screen keypad
+    let mut view = Rect::default();
+    let mut recording: Option<(Recorder, Instant)> = None;
+    if let Some(path) = &options.record {
+        match Recorder::create(path, filter_scale) {
+            Ok(recorder) => recording = Some((recorder, Instant::now())),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let mut keymap_config = match KeymapConfig::load_or_default(&options.keymap) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Error: {}: {}", options.keymap.display(), e);
+            std::process::exit(1);
+        }
+    };
+    // keypad remapping in progress, F6
+    let mut remap: Option<Remapper> = None;
+    let mut show_keypad = options.keypad;
+    // keys held on the on-screen keypad by the mouse and by each touch
+    let mut cursor = (0.0, 0.0);
+    let mut clicked: Option<u8> = None;
+    let mut touches: HashMap<u64, u8> = HashMap::new();
+    let mut modifiers = ModifiersState::empty();
+    // the emulator's keypad bits, for highlighting the on-screen keypad
+    let keypad_state = Arc::new(AtomicU16::new(0));
+    // frames the emulator has run, so the phosphor filter moves on per frame
+    let frames_run = Arc::new(AtomicU64::new(0));
+    let mut frames_shown = 0;
+
+    let (start, mut current) = match start_rom(&options, &keymap_config, options.rom.as_deref()) {
+        Ok(started) => started,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+
     // let (tx, rx) = mpsc::channel::<&[u8]>();
-    let (sender, reciever) = unbounded::<KeyEvent>();
+    let (sender, reciever) = unbounded::<WorkerMessage>();
     let event_loop = EventLoop::new().unwrap();
-    let mut input = WinitInputHelper::new();
     let window = {
-        let size = LogicalSize::new(SCREEN_WIDTH as f64 * 10.0, SCREEN_HEIGHT as f64 * 10.0);
+        let size = LogicalSize::new(
+            (LORES_WIDTH as u32 * options.scale) as f64,
+            (LORES_HEIGHT as u32 * options.scale) as f64,
+        );
         WindowBuilder::new()
-            .with_title("Chip8 Emulator")
+            .with_title(TITLE)
             .with_inner_size(size)
-            .with_min_inner_size(size)
+            .with_min_inner_size(LogicalSize::new(LORES_WIDTH as f64, LORES_HEIGHT as f64))
+            .with_fullscreen(options.fullscreen.then_some(Fullscreen::Borderless(None)))
             .build(&event_loop)
             .unwrap()
     };
 
-    let screen_buffer = Arc::new(Mutex::new([[1u8; 64]; 32]));
+    let screen_buffer = Arc::new(Mutex::new(FrameBuffer::new()));
     let _screen_buffer = Arc::clone(&screen_buffer);
 
     let mut pixels = {
         let window_size = window.inner_size();
         let surface_texture = SurfaceTexture::new(window_size.width, window_size.height, &window);
-        Pixels::new(SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32, surface_texture)?
+        Pixels::new(LORES_WIDTH as u32, LORES_HEIGHT as u32, surface_texture)?
     };
+    let mut scaler = Scaler::new(
+        pixels.device(),
+        pixels.render_texture_format(),
+        options.border,
+    );
+    let mut surface_size = window.inner_size();
 
-    let worker = thread::spawn(move || {
-        let mut chip8 = Chip8::new_with_buffer(_screen_buffer);
-        chip8.init();
-
-        // let mut draw_flag: bool = true;
-        let instruction_interval = Duration::from_nanos(1_000_000_000 / INSTRUCTION_HZ);
-        // let render_interval = Duration::from_nanos(1_000_000_000 / RENDER_HZ);
-        let timer_interval = Duration::from_nanos(1_000_000_000 / TIMER_HZ);
+    let (mut runner, mut save) = run_rom(start, &screen_buffer);
+    window.set_title(&current.window_title());
+    let mut recent = RecentRoms::load_or_default(&browser::recent_path());
+    if let Some(path) = &options.rom
+        && path != rom::STDIN_PATH
+        && !current.sha1.is_empty()
+    {
+        remember_rom(&mut recent, Path::new(path));
+    }
+    let mut browser = None;
+    if options.rom.is_none() {
+        browser = Some(Browser::scan(
+            &options.rom_dir,
+            &load_rom_db(&options),
+            &recent,
+        ));
+        window.set_title(&format!("{} - ROMs", TITLE));
+    }
+    let turbo_rate = options.turbo_rate;
+    let mut movie = match start_movie(&options, &mut runner) {
+        Ok(movie) => movie,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let record_movie = options.record_movie.clone();
+    let worker_keypad_state = Arc::clone(&keypad_state);
+    let worker_frames_run = Arc::clone(&frames_run);
+    let worker_screen = Arc::clone(&screen_buffer);
 
-        let mut last_instruction_tick = Instant::now();
-        let mut last_render_tick = Instant::now();
-        let mut last_timer_tick = Instant::now();
+    let worker = thread::spawn(move || {
+        let frame_interval = Duration::from_nanos(1_000_000_000 / FRAME_HZ);
+        let mut next_frame = Instant::now();
 
         // loop for chip8 emulator
         loop {
-            let now = Instant::now();
+            //using while loop for catch up missed frames (gen-ai suggestion)
+            while Instant::now() >= next_frame {
+                // keypad changes only take effect between frames so movies replay exactly
+                if let Some(state) = &mut movie
+                    && !state.before_frame(&mut runner.chip8().input_handler)
+                    && let Some(MovieState::Playing { movie, .. }) = movie.take()
+                {
+                    report_playback(&movie, &mut runner);
+                }
+                runner.step_frame();
+                next_frame += frame_interval;
+                worker_frames_run.fetch_add(1, Ordering::Relaxed);
+                worker_keypad_state.store(
+                    runner.chip8().input_handler.keypad_bits(),
+                    Ordering::Relaxed,
+                );
+            }
 
             select! {
                 recv(reciever) -> message => {
                     //update_input
                     match message {
-                        Ok(result) => {
-                            chip8.handle_input(result);
-                            chip8.input_handler.display_key_states();
+                        Ok(WorkerMessage::Key(event)) => {
+                            if !matches!(movie, Some(MovieState::Playing { .. })) {
+                                runner.chip8().handle_input(event);
+                            }
+                        },
+                        Ok(WorkerMessage::SetKeymap(keymap)) => {
+                            runner.chip8().input_handler.set_keymap(*keymap);
+                        },
+                        Ok(WorkerMessage::Pad(key, pressed)) => {
+                            if !matches!(movie, Some(MovieState::Playing { .. })) {
+                                runner.chip8().input_handler.set_key(key, pressed);
+                            }
+                        },
+                        Ok(WorkerMessage::ToggleTurbo) => {
+                            let input = &mut runner.chip8().input_handler;
+                            let held = input.held_keys();
+                            if held == 0 {
+                                println!("Hold the keys to auto-fire, then press F8");
+                            }
+                            for key in (0..16).filter(|k| held & (1 << k) != 0) {
+                                let on = !input.turbo(key);
+                                input.set_turbo(key, on.then_some(turbo_rate));
+                                println!("Turbo {:X}: {}", key, if on { "on" } else { "off" });
+                            }
+                        },
+                        Ok(WorkerMessage::Load(start)) => {
+                            match movie.take() {
+                                // nothing worth keeping was recorded before the first ROM
+                                Some(MovieState::Recording(old)) if !old.rom_sha1.is_empty() => {
+                                    finish_movie(old, &mut runner, record_movie.as_ref().unwrap());
+                                }
+                                Some(MovieState::Playing { .. }) => println!("Movie playback stopped"),
+                                _ => {}
+                            }
+                            store_save(save.as_ref(), &mut runner);
+                            *runner.screen() = FrameBuffer::new();
+                            drop(runner);
+                            (runner, save) = run_rom(*start, &worker_screen);
+                            if let Some(path) = &record_movie {
+                                println!("Recording {} for the new ROM", path.display());
+                                movie = Some(MovieState::Recording(Movie::new(&mut runner)));
+                            }
+                            next_frame = Instant::now();
+                        },
+                        Ok(WorkerMessage::Reset { hard }) => {
+                            if movie.is_some() {
+                                // a reset isn't part of the recorded input
+                                println!("Can't reset while a movie is recording or playing");
+                            } else {
+                                runner.reset(hard);
+                                println!("{} reset", if hard { "Hard" } else { "Soft" });
+                            }
+                        },
+                        Ok(WorkerMessage::NextFont) => {
+                            if movie.is_some() {
+                                println!("Can't change the font while a movie is recording or playing");
+                            } else {
+                                let font = runner.chip8().font().next_builtin();
+                                println!("Font: {}", font.name());
+                                runner.chip8().set_font(font);
+                            }
+                        },
+                        Ok(WorkerMessage::ToggleMacro) => {
+                            let input = &mut runner.chip8().input_handler;
+                            if input.is_recording_macro() {
+                                match input.stop_macro() {
+                                    0 => println!("Macro empty"),
+                                    frames => println!(
+                                        "Recorded a {} frame macro, press the key to bind it to",
+                                        frames
+                                    ),
+                                }
+                            } else {
+                                input.start_macro();
+                                println!("Recording macro");
+                            }
                         },
                         Err(error) => {
                             println!("Error: {}", error);
@@ -105,41 +932,16 @@ fn main() -> Result<(), Error> {
                     }
 
                 },
-                default() => {}
+                default(next_frame.saturating_duration_since(Instant::now())) => {}
             }
+        }
 
-            //using while loop for catch up missing execution instructions (gen-ai suggestion)
-            while now.duration_since(last_instruction_tick) >= instruction_interval {
-                // fetch, decode, and execute instructions
-                //chip8 fetch
-                //chip8 decode and execute
-                last_instruction_tick += instruction_interval;
-            }
-
-            // if draw_flag && now.duration_since(last_render_tick) >= render_interval {
-            //     // render frame buffer to screen
-            //     // let buffer = _screen_buffer.lock().unwrap();
-            //     last_render_tick += render_interval;
-            //     draw_flag = false;
-            // }
-
-            while now.duration_since(last_timer_tick) >= timer_interval {
-                // update delay_timers and sound_timers
-                last_timer_tick += timer_interval;
-            }
-
-            let next_instruction_tick = last_instruction_tick + instruction_interval;
-            // let next_render_tick = last_render_tick + render_interval;
-            let next_timer_tick = last_timer_tick + timer_interval;
-
-            let next_tick = next_instruction_tick
-                // .min(next_render_tick)
-                .min(next_timer_tick);
-            let sleep_duration = next_tick.duration_since(now);
-            if sleep_duration > Duration::from_millis(0) {
-                std::thread::sleep(sleep_duration);
-            }
+        if let (Some(MovieState::Recording(movie)), Some(path)) = (movie, record_movie)
+            && !movie.rom_sha1.is_empty()
+        {
+            finish_movie(movie, &mut runner, &path);
         }
+        store_save(save.as_ref(), &mut runner);
     });
 
     let res = event_loop.run(|event, event_loop_window_target| {
@@ -156,11 +958,200 @@ fn main() -> Result<(), Error> {
                 event: WindowEvent::KeyboardInput { event, .. },
                 ..
             } => {
-                if let Err(send_err) = sender.try_send(event) {
+                if let Some(remapper) = &mut remap {
+                    // the remap screen takes every key until it is done
+                    if event.state == ElementState::Pressed
+                        && !event.repeat
+                        && let PhysicalKey::Code(code) = event.physical_key
+                    {
+                        match code {
+                            KeyCode::Escape => {
+                                println!("Keymap unchanged");
+                                remap = None;
+                            }
+                            KeyCode::Backspace => remapper.skip(),
+                            _ => {
+                                if !remapper.press(code) {
+                                    println!("{:?} can't be bound", code);
+                                }
+                            }
+                        }
+                        if let Some(remapper) = remap.take_if(|r| r.is_done()) {
+                            current.keymap = remapper.keymap().clone();
+                            save_keymap(
+                                &mut keymap_config,
+                                &options.keymap,
+                                &current.sha1,
+                                &current.keymap,
+                                current.profile.as_deref(),
+                            );
+                            let keymap = Box::new(current.keymap.clone());
+                            let _ = sender.send(WorkerMessage::SetKeymap(keymap));
+                        }
+                        match &remap {
+                            Some(remapper) => {
+                                println!("{}", remapper.prompt());
+                                window.set_title(&format!("{} - {}", TITLE, remapper.prompt()));
+                            }
+                            None => {
+                                window.set_title(&current.window_title());
+                                screen_buffer.lock().unwrap().mark_all_dirty();
+                            }
+                        }
+                    }
+                    window.request_redraw();
+                    return;
+                }
+                if event.state == ElementState::Pressed
+                    && let PhysicalKey::Code(code) = event.physical_key
+                    && let Some(list) = &mut browser
+                {
+                    // the browser takes every key while it is open
+                    let action = match code {
+                        KeyCode::F1 => Some(BrowserAction::Close),
+                        _ => list.key(code),
+                    };
+                    match action {
+                        Some(BrowserAction::Open(path)) => {
+                            match start_rom(&options, &keymap_config, path.to_str()) {
+                                Ok((start, settings)) => {
+                                    remember_rom(&mut recent, &path);
+                                    current = settings;
+                                    window.set_title(&current.window_title());
+                                    let _ = sender.send(WorkerMessage::Load(Box::new(start)));
+                                    browser = None;
+                                }
+                                Err(e) => list.set_status(format!("ERROR: {}", e)),
+                            }
+                        }
+                        // nothing to go back to without a ROM
+                        Some(BrowserAction::Close) if !current.sha1.is_empty() => {
+                            browser = None;
+                            window.set_title(&current.window_title());
+                        }
+                        _ => {}
+                    }
+                    if browser.is_none() {
+                        screen_buffer.lock().unwrap().mark_all_dirty();
+                    }
+                    window.request_redraw();
+                    return;
+                }
+                if event.state == ElementState::Pressed && !event.repeat {
+                    match event.physical_key {
+                        PhysicalKey::Code(KeyCode::F1) => {
+                            browser = Some(Browser::scan(
+                                &options.rom_dir,
+                                &load_rom_db(&options),
+                                &recent,
+                            ));
+                            window.set_title(&format!("{} - ROMs", TITLE));
+                            // keys go to the browser now, release held ones
+                            let keymap = Box::new(current.keymap.clone());
+                            let _ = sender.send(WorkerMessage::SetKeymap(keymap));
+                            window.request_redraw();
+                            return;
+                        }
+                        PhysicalKey::Code(KeyCode::F2) if modifiers.shift_key() => {
+                            let _ = sender.send(WorkerMessage::NextFont);
+                        }
+                        PhysicalKey::Code(KeyCode::F2) => {
+                            current.palette = current.palette.next_builtin();
+                            println!("Palette: {}", current.palette.name());
+                            screen_buffer.lock().unwrap().mark_all_dirty();
+                        }
+                        PhysicalKey::Code(KeyCode::F3) => {
+                            phosphor.set_mode(phosphor.mode().next());
+                            println!("Phosphor: {}", phosphor.mode().name());
+                        }
+                        PhysicalKey::Code(KeyCode::F4) => {
+                            filter = filter.next();
+                            println!("Filter: {}", filter.name());
+                        }
+                        PhysicalKey::Code(KeyCode::F5) => {
+                            let hard = modifiers.shift_key();
+                            let _ = sender.send(WorkerMessage::Reset { hard });
+                        }
+                        PhysicalKey::Code(KeyCode::F6) => {
+                            let remapper = Remapper::new(current.keymap.clone());
+                            println!("{}", remapper.prompt());
+                            window.set_title(&format!("{} - {}", TITLE, remapper.prompt()));
+                            remap = Some(remapper);
+                            // nothing reaches the emulator while remapping, release held keys
+                            let keymap = Box::new(current.keymap.clone());
+                            let _ = sender.send(WorkerMessage::SetKeymap(keymap));
+                            window.request_redraw();
+                            return;
+                        }
+                        PhysicalKey::Code(KeyCode::F7) => {
+                            show_keypad = !show_keypad;
+                            screen_buffer.lock().unwrap().mark_all_dirty();
+                        }
+                        PhysicalKey::Code(KeyCode::F8) => {
+                            let _ = sender.send(WorkerMessage::ToggleTurbo);
+                        }
+                        PhysicalKey::Code(KeyCode::F11) if modifiers.shift_key() => {
+                            scaling = scaling.next();
+                            println!("Scaling: {}", scaling.name());
+                            screen_buffer.lock().unwrap().mark_all_dirty();
+                        }
+                        PhysicalKey::Code(KeyCode::F11) => toggle_fullscreen(&window),
+                        PhysicalKey::Code(KeyCode::Enter) if modifiers.alt_key() => {
+                            toggle_fullscreen(&window);
+                            return;
+                        }
+                        PhysicalKey::Code(KeyCode::F10) => {
+                            let _ = sender.send(WorkerMessage::ToggleMacro);
+                        }
+                        PhysicalKey::Code(KeyCode::F9) => match recording.take() {
+                            Some((recorder, _)) => finish_recording(recorder),
+                            None => {
+                                let path = options.screenshot_dir.join(format!(
+                                    "chip8-{}.gif",
+                                    screenshot::timestamp(std::time::SystemTime::now())
+                                ));
+                                let created = std::fs::create_dir_all(&options.screenshot_dir)
+                                    .and_then(|_| Recorder::create(&path, filter_scale));
+                                match created {
+                                    Ok(recorder) => {
+                                        println!("Recording to {}", path.display());
+                                        recording = Some((recorder, Instant::now()));
+                                    }
+                                    Err(e) => println!("Error: recording failed: {}", e),
+                                }
+                            }
+                        },
+                        PhysicalKey::Code(KeyCode::F12) => {
+                            let buf = screen_buffer.lock().unwrap();
+                            match screenshot::save(
+                                &options.screenshot_dir,
+                                &buf,
+                                &current.palette,
+                                filter,
+                                filter_scale,
+                            ) {
+                                Ok([raw, scaled]) => {
+                                    println!("Saved {} and {}", raw.display(), scaled.display())
+                                }
+                                Err(e) => println!("Error: screenshot failed: {}", e),
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                if let PhysicalKey::Code(code) = event.physical_key
+                    && is_hotkey(code)
+                {
+                    // neither presses nor releases reach the emulator, where
+                    // turbo, macros and movies would pick them up
+                    window.request_redraw();
+                    return;
+                }
+                if let Err(send_err) = sender.try_send(WorkerMessage::Key(event)) {
                     use crossbeam_channel::TrySendError;
                     match send_err {
-                        TrySendError::Full(f) => {}
-                        TrySendError::Disconnected(d) => {
+                        TrySendError::Full(_) => {}
+                        TrySendError::Disconnected(_) => {
                             println!("Disconnected receiver")
                         }
                     }
@@ -168,22 +1159,202 @@ fn main() -> Result<(), Error> {
                 }
                 window.request_redraw();
             }
+            Event::WindowEvent {
+                event: WindowEvent::ModifiersChanged(state),
+                ..
+            } => modifiers = state.state(),
+            Event::WindowEvent {
+                event: WindowEvent::Resized(size),
+                ..
+            } => {
+                if size.width == 0 || size.height == 0 {
+                    // minimized
+                    return;
+                }
+                surface_size = size;
+                if pixels.resize_surface(size.width, size.height).is_err() {
+                    event_loop_window_target.exit();
+                    return;
+                }
+                screen_buffer.lock().unwrap().mark_all_dirty();
+                window.request_redraw();
+            }
+            Event::WindowEvent {
+                event: WindowEvent::DroppedFile(path),
+                ..
+            } => match start_rom(&options, &keymap_config, path.to_str()) {
+                Ok((start, settings)) => {
+                    remember_rom(&mut recent, &path);
+                    current = settings;
+                    window.set_title(&current.window_title());
+                    let _ = sender.send(WorkerMessage::Load(Box::new(start)));
+                    browser = None;
+                    remap = None;
+                    screen_buffer.lock().unwrap().mark_all_dirty();
+                }
+                Err(e) => match &mut browser {
+                    Some(list) => list.set_status(format!("ERROR: {}", e)),
+                    None => println!("Error: {}: {}", path.display(), e),
+                },
+            },
+            Event::WindowEvent {
+                event: WindowEvent::CursorMoved { position, .. },
+                ..
+            } => cursor = (position.x as f32, position.y as f32),
+            Event::WindowEvent {
+                event:
+                    WindowEvent::MouseInput {
+                        state,
+                        button: MouseButton::Left,
+                        ..
+                    },
+                ..
+            } => {
+                let key = match state {
+                    ElementState::Pressed
+                        if show_keypad && remap.is_none() && browser.is_none() =>
+                    {
+                        clicked = keypad_key_under(view, &screen_buffer, cursor);
+                        clicked.map(|key| (key, true))
+                    }
+                    ElementState::Pressed => None,
+                    ElementState::Released => clicked.take().map(|key| (key, false)),
+                };
+                if let Some((key, pressed)) = key {
+                    let _ = sender.send(WorkerMessage::Pad(key, pressed));
+                }
+            }
+            Event::WindowEvent {
+                event: WindowEvent::Touch(touch),
+                ..
+            } => {
+                let position = (touch.location.x as f32, touch.location.y as f32);
+                let key = match touch.phase {
+                    TouchPhase::Started if show_keypad && remap.is_none() && browser.is_none() => {
+                        keypad_key_under(view, &screen_buffer, position).map(|key| {
+                            touches.insert(touch.id, key);
+                            (key, true)
+                        })
+                    }
+                    TouchPhase::Ended | TouchPhase::Cancelled => {
+                        touches.remove(&touch.id).map(|key| (key, false))
+                    }
+                    _ => None,
+                };
+                if let Some((key, pressed)) = key {
+                    let _ = sender.send(WorkerMessage::Pad(key, pressed));
+                }
+            }
             Event::WindowEvent {
                 event: WindowEvent::RedrawRequested,
                 ..
             } => {
-                // Redraw the window
-                let buf = screen_buffer.lock().unwrap();
-                let frame = pixels.frame_mut();
-                for (i, row) in buf.iter().enumerate() {
-                    for (j, col) in row.iter().enumerate() {
-                        let v = if *col == 1 { 0xFF } else { 0x00 };
-                        let ofset = (i * SCREEN_WIDTH as usize + j) * 4;
-                        frame[ofset..ofset + 4].copy_from_slice(&[v, v, v, 0xFF]);
+                if let Some(list) = &browser {
+                    let (width, height) = (browser::WIDTH as u32, browser::HEIGHT as u32);
+                    if (pixels.texture().width() != width || pixels.texture().height() != height)
+                        && pixels.resize_buffer(width, height).is_err()
+                    {
+                        event_loop_window_target.exit();
+                        return;
+                    }
+                    list.draw(pixels.frame_mut(), &current.palette);
+                    let (width, height) = (width as usize, height as usize);
+                    let (window_width, window_height) =
+                        (surface_size.width as usize, surface_size.height as usize);
+                    let view = scaling.view(width, height, window_width, window_height);
+                    let rendered = pixels.render_with(|encoder, target, context| {
+                        let texture = &context.texture;
+                        scaler.render(&context.device, encoder, texture, target, view);
+                        Ok(())
+                    });
+                    if rendered.is_err() {
+                        event_loop_window_target.exit();
+                    }
+                    event_loop_window_target
+                        .set_control_flow(ControlFlow::WaitUntil(Instant::now() + FPS60));
+                    return;
+                }
+                // Only rows that changed since last frame are converted to RGBA;
+                // `pixels` uploads the whole texture either way
+                {
+                    let mut buf = screen_buffer.lock().unwrap();
+                    let (width, height) = (buf.width(), buf.height());
+                    let scale = if filter == PostFilter::None {
+                        1
+                    } else {
+                        filter_scale
+                    };
+                    // the texture stays at the filter's size, `scaler` scales it
+                    let (texture_width, texture_height) =
+                        ((width * scale) as u32, (height * scale) as u32);
+                    if pixels.texture().width() != texture_width
+                        || pixels.texture().height() != texture_height
+                    {
+                        if pixels.resize_buffer(texture_width, texture_height).is_err() {
+                            event_loop_window_target.exit();
+                            return;
+                        }
+                        buf.mark_all_dirty();
+                    }
+                    // overlays cover some rows, so every row is redrawn under them
+                    let keypad = match &remap {
+                        Some(remapper) => {
+                            remapper.current_key().map(|key| (Anchor::Center, 1 << key))
+                        }
+                        None if show_keypad => {
+                            Some((Anchor::Right, keypad_state.load(Ordering::Relaxed)))
+                        }
+                        None => None,
+                    };
+                    if keypad.is_some() {
+                        buf.mark_all_dirty();
+                    }
+                    let draw_overlay = |frame: &mut [u8]| {
+                        if let Some((anchor, highlight)) = keypad {
+                            overlay::draw_keypad(
+                                frame,
+                                width,
+                                height,
+                                anchor,
+                                highlight,
+                                &current.palette,
+                            );
+                        }
+                    };
+                    let (window_width, window_height) =
+                        (surface_size.width as usize, surface_size.height as usize);
+                    view = scaling.view(width * scale, height * scale, window_width, window_height);
+                    let frames = frames_run.load(Ordering::Relaxed);
+                    let new_frames = frames - frames_shown;
+                    frames_shown = frames;
+                    if filter == PostFilter::None {
+                        phosphor.apply(&mut buf, pixels.frame_mut(), &current.palette, new_frames);
+                        draw_overlay(pixels.frame_mut());
+                    } else {
+                        if native_frame.len() != width * height * 4 {
+                            native_frame = vec![0; width * height * 4];
+                            buf.mark_all_dirty();
+                        }
+                        phosphor.apply(&mut buf, &mut native_frame, &current.palette, new_frames);
+                        draw_overlay(&mut native_frame);
+                        filter.apply(&native_frame, width, height, scale, pixels.frame_mut());
+                    }
+
+                    if let Some((recorder, started)) = &mut recording {
+                        let frame_number = started.elapsed().as_micros() as u64 * 60 / 1_000_000;
+                        if let Err(e) = recorder.capture(frame_number, &buf, &current.palette) {
+                            println!("Error: recording stopped: {}", e);
+                            recording = None;
+                        }
                     }
                 }
 
-                if pixels.render().is_err() {
+                let rendered = pixels.render_with(|encoder, target, context| {
+                    let texture = &context.texture;
+                    scaler.render(&context.device, encoder, texture, target, view);
+                    Ok(())
+                });
+                if rendered.is_err() {
                     event_loop_window_target.exit();
                 }
 
@@ -204,499 +1375,761 @@ fn main() -> Result<(), Error> {
     });
 
     println!("shuting down...");
+    if let Some((recorder, _)) = recording {
+        finish_recording(recorder);
+    }
     //close sender
     drop(sender);
     worker.join().unwrap();
     res.map_err(|e| Error::UserDefined(Box::new(e)))
 }
 
-struct InputHandler {
-    keypad: [bool; 16],
-    key_mapping: HashMap<KeyCode, u8>,
+fn finish_recording(recorder: Recorder) {
+    let path = recorder.path().to_path_buf();
+    match recorder.finish() {
+        Ok(frames) => println!("Saved {} ({} frames)", path.display(), frames),
+        Err(e) => println!("Error: saving {} failed: {}", path.display(), e),
+    }
 }
 
-impl InputHandler {
-    fn new() -> Self {
-        let mut key_mapping = HashMap::new();
-
-        key_mapping.insert(KeyCode::Digit1, 0x1);
-        key_mapping.insert(KeyCode::Digit2, 0x2);
-        key_mapping.insert(KeyCode::Digit3, 0x3);
-        key_mapping.insert(KeyCode::Digit4, 0xC);
+// Run `options.frames` frames without a window, writing whichever of the
+// video, audio and recording outputs were requested.
+fn run_headless(options: Options) -> Result<(), io::Error> {
+    let (options, mut runner, palette) = headless_runner(&options)?;
+    let mut movie = start_movie(&options, &mut runner)?;
+    let frames = match &movie {
+        Some(MovieState::Playing { movie, .. }) => movie.frames.len() as u64,
+        _ => options.frames,
+    };
 
-        key_mapping.insert(KeyCode::KeyQ, 0x4);
-        key_mapping.insert(KeyCode::KeyW, 0x5);
-        key_mapping.insert(KeyCode::KeyE, 0x6);
-        key_mapping.insert(KeyCode::KeyR, 0xD);
+    let mut video = match &options.video {
+        Some(path) => Some(Y4mWriter::create(
+            path,
+            options.filter_scale,
+            options.filter,
+        )?),
+        None => None,
+    };
+    let mut audio = match &options.audio {
+        Some(path) => Some(WavWriter::create(path)?),
+        None => None,
+    };
+    let mut recorder = match &options.record {
+        Some(path) => Some(Recorder::create(path, options.filter_scale)?),
+        None => None,
+    };
 
-        key_mapping.insert(KeyCode::KeyA, 0x7);
-        key_mapping.insert(KeyCode::KeyS, 0x8);
-        key_mapping.insert(KeyCode::KeyD, 0x9);
-        key_mapping.insert(KeyCode::KeyF, 0xE);
+    for _ in 0..frames {
+        if let Some(state) = &mut movie {
+            state.before_frame(&mut runner.chip8().input_handler);
+        }
+        let frame = runner.frame();
+        let sound = runner.step_frame();
+        let screen = runner.screen();
+        if let Some(video) = &mut video {
+            video.write_frame(&screen, &palette)?;
+        }
+        if let Some(audio) = &mut audio {
+            audio.write_frame(sound)?;
+        }
+        if let Some(recorder) = &mut recorder {
+            recorder.capture(frame, &screen, &palette)?;
+        }
+    }
 
-        key_mapping.insert(KeyCode::KeyZ, 0xA);
-        key_mapping.insert(KeyCode::KeyX, 0x0);
-        key_mapping.insert(KeyCode::KeyC, 0xB);
-        key_mapping.insert(KeyCode::KeyV, 0xF);
+    if let Some(video) = video {
+        video.finish()?;
+    }
+    if let Some(audio) = audio {
+        audio.finish()?;
+    }
+    if let Some(recorder) = recorder {
+        finish_recording(recorder);
+    }
+    println!("Ran {} frames", frames);
 
-        Self {
-            keypad: [false; 16],
-            key_mapping,
+    match (movie, &options.record_movie) {
+        (Some(MovieState::Playing { movie, .. }), _) => {
+            let matches = report_playback(&movie, &mut runner);
+            if options.verify && !matches {
+                return Err(io::Error::other("final state does not match the movie"));
+            }
         }
+        (Some(MovieState::Recording(movie)), Some(path)) => finish_movie(movie, &mut runner, path),
+        _ => {}
     }
+    Ok(())
+}
 
-    fn key_pressed(&mut self, key_code: KeyCode) {
-        if let Some(&chip8_key) = self.key_mapping.get(&key_code) {
-            self.keypad[chip8_key as usize] = true;
-        }
+// Set up a runner for `options.rom` without a window, along with the options
+// for that ROM and its palette. The random numbers are seeded with 0 unless
+// the options say otherwise, so runs repeat.
+fn headless_runner(options: &Options) -> Result<(Options, Runner, Palette), io::Error> {
+    let rom = options.rom.as_deref().unwrap_or_default();
+    let image = load_rom_image(options, rom)?;
+    let options = options
+        .for_rom(&config_sha1(options, &image))
+        .map_err(io::Error::other)?;
+    let mut runner = Runner::new(options.speed.unwrap_or(INSTRUCTION_HZ));
+    if let Some(quirks) = options.quirks {
+        runner.chip8().set_quirks(quirks);
     }
-    fn key_released(&mut self, key_code: KeyCode) {
-        if let Some(&chip8_key) = self.key_mapping.get(&key_code) {
-            self.keypad[chip8_key as usize] = false;
-        }
+    runner.chip8().seed_rng(options.seed.unwrap_or(0));
+    runner.chip8().load_rom_data(&image.data)?;
+    let rom_info = lookup_rom_info(&options, runner.chip8().rom_sha1(), Some(&image));
+    apply_rom_info(&options, &mut runner, Some(&image), rom_info.as_ref());
+    let palette = options
+        .palette
+        .clone()
+        .or(rom_info.as_ref().and_then(RomInfo::palette))
+        .or(image.palette)
+        .unwrap_or_default();
+    Ok((options, runner, palette))
+}
+
+// `test`: run `--frames` frames and compare the screen with the `--expect`
+// PNG, or save it there with `--update`.
+fn run_test(options: &Options) -> Result<(), io::Error> {
+    let (options, mut runner, palette) = headless_runner(options)?;
+    for _ in 0..options.frames {
+        runner.step_frame();
     }
-    fn is_key_pressed(&self, key: u8) -> bool {
-        if key < 16 {
-            self.keypad[key as usize]
-        } else {
-            false
-        }
+    let screen = runner.screen();
+    let (width, height) = (screen.width(), screen.height());
+    let rgba = screen.to_rgba(&palette);
+    let expect = options.expect.as_deref().unwrap();
+    if options.update {
+        screenshot::write_png(expect, width, height, &rgba)?;
+        println!("Saved {}", expect.display());
+        return Ok(());
     }
+    let decoder = png::Decoder::new(std::fs::File::open(expect)?);
+    let mut reader = decoder.read_info().map_err(io::Error::other)?;
+    let mut expected = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut expected).map_err(io::Error::other)?;
+    let matches = (info.width as usize, info.height as usize) == (width, height)
+        && info.color_type == png::ColorType::Rgba
+        && expected[..info.buffer_size()] == rgba[..];
+    if !matches {
+        return Err(io::Error::other(format!(
+            "screen after {} frames differs from {}",
+            options.frames,
+            expect.display()
+        )));
+    }
+    println!("Passed: screen matches {}", expect.display());
+    Ok(())
+}
 
-    fn display_key_states(&self) {
-        println!("CHIP-8 Key States:");
-        println!(
-            "1:{} 2:{} 3:{} C:{}",
-            if self.keypad[0x1] { "█" } else { "." },
-            if self.keypad[0x2] { "█" } else { "." },
-            if self.keypad[0x3] { "█" } else { "." },
-            if self.keypad[0xC] { "█" } else { "." }
-        );
-        println!(
-            "4:{} 5:{} 6:{} D:{}",
-            if self.keypad[0x4] { "█" } else { "." },
-            if self.keypad[0x5] { "█" } else { "." },
-            if self.keypad[0x6] { "█" } else { "." },
-            if self.keypad[0xD] { "█" } else { "." }
-        );
-        println!(
-            "7:{} 8:{} 9:{} E:{}",
-            if self.keypad[0x7] { "█" } else { "." },
-            if self.keypad[0x8] { "█" } else { "." },
-            if self.keypad[0x9] { "█" } else { "." },
-            if self.keypad[0xE] { "█" } else { "." }
-        );
-        println!(
-            "A:{} 0:{} B:{} F:{}",
-            if self.keypad[0xA] { "█" } else { "." },
-            if self.keypad[0x0] { "█" } else { "." },
-            if self.keypad[0xB] { "█" } else { "." },
-            if self.keypad[0xF] { "█" } else { "." }
-        );
-        println!();
-    }
-}
-
-struct Chip8 {
-    registers: [u8; 16],
-    i: u16,
-    pc: u16,          // Program counter
-    sp: u16,          // Stack pointer
-    stack: [u16; 16], // Stack for storing return addresses
-    delay_timer: u8,
-    sound_timer: u8,
-    // keypad: [bool; 16],
-    memory: [u8; 4096],
-
-    input_handler: InputHandler,
-
-    draw_flag: bool,
-    frame_buffer: Arc<Mutex<[[u8; 64]; 32]>>, // memory: Memory,
-                                              // frame: [u8; ]
-}
-
-fn nibble(value: &u16, n: u8) -> u8 {
-    ((value >> (n * 4)) & 0xF) as u8
-}
-
-impl Chip8 {
-    fn new_with_buffer(buffer: Arc<Mutex<[[u8; 64]; 32]>>) -> Self {
-        Self {
-            registers: [0x0; 16],
-            i: 0x0,
-            pc: 0x0,
-            sp: 0x0,
-            stack: [0x0; 16],
-            delay_timer: 0x0,
-            sound_timer: 0x0,
-            // keypad: [false; 16],
-            input_handler: InputHandler::new(),
-            memory: [0x0; 4096],
-            draw_flag: false,
-            frame_buffer: buffer,
-        }
+// `bench`: run `--frames` frames flat out and report how fast that was.
+fn run_bench(options: &Options) -> Result<(), io::Error> {
+    let (options, mut runner, _) = headless_runner(options)?;
+    let start = Instant::now();
+    for _ in 0..options.frames {
+        runner.step_frame();
     }
+    let elapsed = start.elapsed().as_secs_f64();
+    let instructions = options.frames * runner.instruction_hz() / FRAME_HZ;
+    println!(
+        "{} frames, {} instructions in {:.3} s",
+        options.frames, instructions, elapsed
+    );
+    println!(
+        "{:.0} frames/s, {:.2} million instructions/s, {:.1}x real time",
+        options.frames as f64 / elapsed,
+        instructions as f64 / elapsed / 1e6,
+        options.frames as f64 / FRAME_HZ as f64 / elapsed
+    );
+    Ok(())
+}
 
-    fn init(&mut self) {
-        self.pc = PROGRAM_START_LOC as u16;
-        self.sp = 0x0;
+// `disasm`: list the ROM as Octo statements.
+fn run_disasm(options: &Options) -> Result<(), io::Error> {
+    let image = load_rom_image(options, options.rom.as_deref().unwrap_or_default())?;
+    let mut text = String::new();
+    for line in disasm::disassemble(&image.data) {
+        text += &format!("{}\n", line);
+    }
+    match options.output.as_deref() {
+        Some(path) if path != Path::new(rom::STDIN_PATH) => std::fs::write(path, text),
+        _ => io::stdout().write_all(text.as_bytes()),
+    }
+}
 
-        //load font to memory
-        for (i, &font) in FONTSET.iter().enumerate() {
-            self.memory[FONT_START_LOC + i] = font;
-        }
+// `asm`: assemble Octo source into a ROM.
+fn run_asm(options: &Options) -> Result<(), io::Error> {
+    let source_path = options.rom.as_deref().unwrap_or_default();
+    let source = if source_path == rom::STDIN_PATH {
+        io::read_to_string(io::stdin())?
+    } else {
+        std::fs::read_to_string(source_path)?
+    };
+    let data = octo::assemble(&source).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {}", source_path, e),
+        )
+    })?;
+    let output = match &options.output {
+        Some(path) => path.clone(),
+        None if source_path == rom::STDIN_PATH => PathBuf::from(rom::STDIN_PATH),
+        None => Path::new(source_path).with_extension("ch8"),
+    };
+    if output == Path::new(rom::STDIN_PATH) {
+        return io::stdout().write_all(&data);
     }
+    std::fs::write(&output, &data)?;
+    println!("Assembled {} bytes into {}", data.len(), output.display());
+    Ok(())
+}
 
-    fn load_rom(&mut self, path: String) -> Result<(), io::Error> {
-        let rom_data = fs::read(path)?;
+// Load and check the ROM at `path`, or no ROM, and work out what the options
+// and the keymap file say to run it with, along with what the window needs to
+// show it. Nothing is started until `run_rom`.
+fn start_rom(
+    options: &Options,
+    keymap_config: &KeymapConfig,
+    path: Option<&str>,
+) -> Result<(RomStart, RomSettings), String> {
+    let image = path
+        .map(|path| load_rom_image(options, path))
+        .transpose()
+        .map_err(|e| e.to_string())?;
+    if let Some(image) = &image
+        && image.data.len() > MAX_ROM_SIZE
+    {
+        return Err("ROM file too large".to_string());
+    }
+    let options = match &image {
+        Some(image) => options.for_rom(&config_sha1(options, image))?,
+        None => options.clone(),
+    };
+    let sha1 = image
+        .as_ref()
+        .map(|image| romdb::sha1_hex(&image.data))
+        .unwrap_or_default();
+    let rom_info = lookup_rom_info(&options, &sha1, image.as_ref());
+    let palette = options
+        .palette
+        .clone()
+        .or(rom_info.as_ref().and_then(RomInfo::palette))
+        .or(image.as_ref().and_then(|image| image.palette.clone()))
+        .unwrap_or_default();
+    let profile = options
+        .profile
+        .clone()
+        .or(keymap_config.rom_profile(&sha1).map(str::to_string));
+    let Some(mut keymap) = keymap_config.keymap(&sha1, profile.as_deref()) else {
+        return Err(format!(
+            "unknown keymap profile {}, have: {}",
+            profile.unwrap_or_default(),
+            keymap_config.profile_names().join(", ")
+        ));
+    };
+    if let Some(info) = &rom_info
+        && profile.is_none()
+        && !keymap_config.has_rom(&sha1)
+    {
+        info.apply_key_hints(&mut keymap);
+    }
+    let title = rom_info
+        .as_ref()
+        .map(|info| info.title.clone())
+        .or_else(|| {
+            let name = Path::new(path?).file_name()?;
+            Some(name.to_string_lossy().into_owned())
+        });
+    // movies need the same start every time, so they don't touch saves
+    let save = (!sha1.is_empty() && options.record_movie.is_none() && options.play_movie.is_none())
+        .then(|| SaveSlot::new(&options.save_dir, &sha1, options.save_memory.clone()));
+    let settings = RomSettings {
+        title,
+        sha1,
+        palette,
+        profile,
+        keymap: keymap.clone(),
+    };
+    let start = RomStart {
+        options,
+        image,
+        info: rom_info,
+        keymap,
+        save,
+    };
+    Ok((start, settings))
+}
 
-        if rom_data.len() > MEMORY_SIZE - PROGRAM_START_LOC {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "ROM file too large",
-            ));
+// Set up a runner on `screen` for a ROM `start_rom` checked, with its save
+// data restored.
+fn run_rom(start: RomStart, screen: &Arc<Mutex<FrameBuffer>>) -> (Runner, Option<SaveSlot>) {
+    let RomStart {
+        options,
+        image,
+        info,
+        keymap,
+        mut save,
+    } = start;
+    let hz = options.speed.unwrap_or(INSTRUCTION_HZ);
+    let mut runner = Runner::with_screen(Arc::clone(screen), hz);
+    if let Some(quirks) = options.quirks {
+        runner.chip8().set_quirks(quirks);
+    }
+    if let Some(seed) = options.seed {
+        runner.chip8().seed_rng(seed);
+    }
+    if let Some(image) = &image
+        && let Err(e) = runner.chip8().load_rom_data(&image.data)
+    {
+        println!("Error: {}", e);
+    }
+    apply_rom_info(&options, &mut runner, image.as_ref(), info.as_ref());
+    runner.chip8().input_handler.set_keymap(keymap);
+    for key in (0..16).filter(|k| options.turbo & (1 << k) != 0) {
+        runner
+            .chip8()
+            .input_handler
+            .set_turbo(key, Some(options.turbo_rate));
+    }
+    if let Some(slot) = &mut save {
+        match slot.restore(runner.chip8()) {
+            Ok(true) => println!("Restored save data from {}", slot.path.display()),
+            Ok(false) => {}
+            Err(e) => println!("Error: {}: {}", slot.path.display(), e),
         }
+    }
+    (runner, save)
+}
 
-        for (i, &byte) in rom_data.iter().enumerate() {
-            self.memory[PROGRAM_START_LOC + i] = byte;
-        }
-        println!("Loaded ROM: {} bytes", rom_data.len());
-        Ok(())
+// Put `path` at the top of the recently played list.
+fn remember_rom(recent: &mut RecentRoms, path: &Path) {
+    recent.add(path);
+    let path = browser::recent_path();
+    if let Err(e) = recent.save(&path) {
+        println!("Error: saving {} failed: {}", path.display(), e);
     }
+}
 
-    fn handle_input(&mut self, event: KeyEvent) {
-        if let PhysicalKey::Code(key_code) = event.physical_key {
-            match event.state {
-                ElementState::Pressed => {
-                    self.input_handler.key_pressed(key_code);
-                }
-                ElementState::Released => {
-                    self.input_handler.key_released(key_code);
-                }
+// Load the ROM at `path` with the --patch patches and, unless turned off,
+// the ones next to it applied.
+fn load_rom_image(options: &Options, path: &str) -> Result<RomImage, io::Error> {
+    let mut image = RomImage::load(path)?;
+    let mut paths = options.patches.clone();
+    if options.auto_patch && path != rom::STDIN_PATH {
+        for found in patch::discover(Path::new(path)) {
+            if !paths.contains(&found) {
+                paths.push(found);
             }
         }
     }
+    let patches = paths
+        .iter()
+        .map(|path| Patch::load(path))
+        .collect::<Result<Vec<_>, _>>()?;
+    image.apply_patches(&patches)?;
+    for patch in &patches {
+        println!("Patched with {}", patch.name);
+    }
+    Ok(image)
+}
 
-    fn update_timer(&mut self) {
-        if self.delay_timer > 0 {
-            self.delay_timer -= 1;
-        }
+// The built-in ROM database with the --rom-db file, or romdb.json if there
+// is one, on top.
+fn load_rom_db(options: &Options) -> RomDatabase {
+    let mut db = RomDatabase::bundled();
+    let (path, required) = match &options.rom_db {
+        Some(path) => (path.clone(), true),
+        None => (PathBuf::from(romdb::DEFAULT_PATH), false),
+    };
+    match db.add_file(&path) {
+        Err(e) if !required && e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => println!("Error: ROM database {}: {}", path.display(), e),
+        Ok(()) => {}
+    }
+    db
+}
+
+// Look the ROM with `sha1` up in the ROM database, or the one it was patched
+// from.
+fn lookup_rom_info(options: &Options, sha1: &str, image: Option<&RomImage>) -> Option<RomInfo> {
+    if sha1.is_empty() {
+        return None;
+    }
+    let db = load_rom_db(options);
+    let info = db.lookup(sha1).or_else(|| {
+        // a patched ROM is still the game it was made from
+        db.lookup(image?.source_sha1.as_deref()?)
+    });
+    if let Some(info) = &info {
+        println!("ROM database: {}", info.title);
+    }
+    info
+}
 
-        if self.sound_timer > 0 {
-            self.sound_timer -= 1;
+// Apply the quirks, speed and font the ROM database lists for the loaded ROM,
+// falling back to what came with the image and then to static analysis.
+// Quirks given in the options win over all of these.
+fn apply_rom_info(
+    options: &Options,
+    runner: &mut Runner,
+    image: Option<&RomImage>,
+    info: Option<&RomInfo>,
+) {
+    if runner.chip8().rom_sha1().is_empty() {
+        return;
+    }
+    let hz = info
+        .and_then(RomInfo::instruction_hz)
+        .or(image.and_then(RomImage::instruction_hz));
+    if let Some(hz) = hz
+        && options.speed.is_none()
+    {
+        runner.set_instruction_hz(hz);
+    }
+    if options.quirks.is_none() {
+        let quirks = info
+            .and_then(|info| info.quirks)
+            .or(image.and_then(|image| image.quirks))
+            .or(image.and_then(|image| image.platform).map(|p| p.quirks()))
+            .unwrap_or_else(|| {
+                // nothing to go by, guess from the code
+                let analysis = analysis::analyze(runner.chip8().rom());
+                println!(
+                    "Unknown ROM, looks like {}: {}",
+                    analysis.platform().name(),
+                    analysis.quirks()
+                );
+                analysis.quirks()
+            });
+        runner.chip8().set_quirks(quirks);
+    }
+    let font = options.font.clone().or_else(|| {
+        let name = info?.font.as_deref()?;
+        Font::builtin(name)
+    });
+    if font.is_some() || options.font_address.is_some() {
+        let font = font.unwrap_or_default();
+        let address = options.font_address.unwrap_or(font.address());
+        runner.chip8().set_font(font.with_address(address));
+    }
+}
+
+// The hash the config file knows `image` by: a patched ROM without a table
+// of its own is still the game it was made from.
+fn config_sha1(options: &Options, image: &RomImage) -> String {
+    let sha1 = romdb::sha1_hex(&image.data);
+    match &image.source_sha1 {
+        Some(source) if !options.config.has_rom(&sha1) => source.clone(),
+        _ => sha1,
+    }
+}
+
+// `config dump [rom]`: print the settings the config file and the command
+// line add up to for the ROM, as TOML, with where each one comes from.
+fn run_config_dump(options: &Options) -> Result<(), io::Error> {
+    let sha1 = match &options.rom {
+        Some(path) => Some(config_sha1(options, &load_rom_image(options, path)?)),
+        None => None,
+    };
+    let mut settings = options
+        .config
+        .settings(sha1.as_deref(), options.config_profile.as_deref())?;
+    for Setting { key, value, source } in options.given.iter().cloned() {
+        if key == "config" || key == config::PROFILE_KEY {
+            continue;
+        }
+        match settings
+            .iter_mut()
+            .find(|s| s.key == key && s.source == source)
+        {
+            // repeated, like --patch
+            Some(setting) => match &mut setting.value {
+                toml::Value::Array(values) => values.push(value),
+                first => *first = toml::Value::Array(vec![first.clone(), value]),
+            },
+            None => {
+                settings.retain(|s| s.key != key);
+                settings.push(Setting { key, value, source });
+            }
         }
     }
 
-    fn cycle(&mut self) {
-        let opcode: u16 = self.fetch();
+    match options.config.path() {
+        Some(path) => println!("# config file: {}", path.display()),
+        None => println!("# no config file"),
+    }
+    if let Some(sha1) = &sha1 {
+        println!("# rom: {}", sha1);
+    }
+    for setting in settings {
+        println!("{} = {}  # {}", setting.key, setting.value, setting.source);
+    }
+    Ok(())
+}
 
-        self.pc += 2;
+// `info <rom>`: print the ROM's hash, its ROM database entry and what
+// static analysis makes of its code.
+fn run_info(options: &Options) -> Result<(), io::Error> {
+    let path = options.rom.as_deref().unwrap_or_default();
+    let image = load_rom_image(options, path)?;
+    let data = &image.data;
+    let sha1 = romdb::sha1_hex(data);
+    println!("File:        {}", path);
+    if image.format != RomFormat::Binary {
+        println!("Format:      {}", image.format.name());
+    }
+    println!("Size:        {} bytes", data.len());
+    println!("SHA-1:       {}", sha1);
+    if let Some(source) = &image.source_sha1 {
+        println!("Unpatched:   {}", source);
+    }
+    if let Some(platform) = image.platform {
+        println!("Made for:    {}", platform.name());
+    }
+    let db = load_rom_db(options);
+    let Some(info) = db
+        .lookup(&sha1)
+        .or_else(|| db.lookup(image.source_sha1.as_deref()?))
+    else {
+        println!("Not in the ROM database\n\nAnalysis");
+        println!("{}", analysis::analyze(data));
+        return Ok(());
+    };
+    println!("Title:       {}", info.title);
+    if !info.authors.is_empty() {
+        println!("Authors:     {}", info.authors.join(", "));
+    }
+    if let Some(release) = &info.release {
+        println!("Released:    {}", release);
+    }
+    if let Some(platform) = &info.platform {
+        println!("Platform:    {}", platform);
+    }
+    if let Some(quirks) = info.quirks {
+        println!("Quirks:      {}", quirks);
+    }
+    if let Some(tickrate) = info.tickrate {
+        println!("Tickrate:    {} instructions per frame", tickrate);
+    }
+    if let Some(font) = &info.font {
+        println!("Font:        {}", font);
+    }
+    if !info.colors.is_empty() {
+        let colors: Vec<String> = info
+            .colors
+            .iter()
+            .map(|[r, g, b]| format!("#{:02x}{:02x}{:02x}", r, g, b))
+            .collect();
+        println!("Colours:     {}", colors.join(" "));
+    }
+    if !info.keys.is_empty() {
+        let keys: Vec<String> = info
+            .keys
+            .iter()
+            .map(|(name, key)| format!("{} {:X}", name, key))
+            .collect();
+        println!("Keys:        {}", keys.join(", "));
+    }
+    if let Some(description) = &info.description {
+        println!("\n{}", description);
+    }
+    println!("\nAnalysis\n{}", analysis::analyze(data));
+    Ok(())
+}
 
-        //decode and execute
-        self.decode_and_execute(opcode);
+// Load the movie to play or start the one to record, as the options ask.
+fn start_movie(options: &Options, runner: &mut Runner) -> Result<Option<MovieState>, io::Error> {
+    if let Some(path) = &options.play_movie {
+        let movie = Movie::load(path)?;
+        movie.prepare(runner)?;
+        println!("Playing {} ({} frames)", path.display(), movie.frames.len());
+        return Ok(Some(MovieState::Playing { movie, frame: 0 }));
     }
-    fn fetch(&self) -> u16 {
-        (self.memory[self.pc as usize] as u16) << 0x8
-            | (self.memory[(self.pc + 0x1) as usize]) as u16
+    if options.record_movie.is_some() {
+        return Ok(Some(MovieState::Recording(Movie::new(runner))));
     }
-    fn decode_and_execute(&mut self, inst: u16) {
-        let _opcode: u16 = (inst >> 0xC) & 0xF;
-        match _opcode {
-            0x0 => {
-                match inst & 0x0FFF {
-                    0x00E0 => {
-                        // clear screen
-                        {
-                            *self.frame_buffer.lock().unwrap() = [[0; 64]; 32];
-                            self.draw_flag = true;
-                        }
-                    }
-                    0x00EE => {
-                        // return from subroutine
-                        self.pc = match self.pop() {
-                            Some(addr) => addr,
-                            None => {
-                                print!("Stack underflow");
-                                return;
-                            }
-                        };
-                    }
-                    _ => (),
-                }
-            }
-            0x1 => {
-                let index: u16 = inst & 0x0FFF;
-                self.pc = index;
-            }
-            0x2 => {
-                let index: u16 = inst & 0x0FFF;
-                match self.push(self.pc) {
-                    Err(e) => {
-                        print!("{}", e)
-                    }
-                    _ => (),
-                }
-                self.pc = index;
-            }
-            0x3 => {
-                let x: u8 = nibble(&inst, 2);
-                let vx: u8 = self.get_register_data(&x);
-                let kk: u8 = inst as u8;
-                if vx == kk {
-                    self.pc += 2;
-                }
-            }
-            0x4 => {
-                let x: u8 = nibble(&inst, 2);
-                let vx: u8 = self.get_register_data(&x);
-                let kk: u8 = inst as u8;
-                if vx != kk {
-                    self.pc += 2;
-                }
-            }
-            0x5 => {
-                let x: u8 = nibble(&inst, 2);
-                let vx: u8 = self.get_register_data(&x);
-                let y: u8 = nibble(&inst, 1);
-                let vy: u8 = self.get_register_data(&y);
-                if vx == vy {
-                    self.pc += 2;
-                }
-            }
-            0x6 => {
-                let x: u8 = nibble(&inst, 2);
-                let kk: u8 = inst as u8;
-                self.register(x, kk);
-            }
-            0x7 => {
-                let x: u8 = nibble(&inst, 2);
-                let vx: u8 = self.get_register_data(&x);
-                let kk: u8 = inst as u8;
-                self.register(x, vx + kk);
-            }
-            0x8 => {
-                let x: u8 = nibble(&inst, 2);
-                let vx: u8 = self.get_register_data(&x);
-                let y: u8 = nibble(&inst, 1);
-                let vy: u8 = self.get_register_data(&y);
-
-                let indic: u8 = nibble(&inst, 0);
-                match indic {
-                    0x0 => {
-                        self.register(x, vy);
-                    }
-                    0x1 => {
-                        self.register(x, vx | vy);
-                    }
-                    0x2 => {
-                        self.register(x, vx & vy);
-                    }
-                    0x3 => {
-                        self.register(x, vx ^ vy);
-                    }
-                    0x4 => {
-                        let (sum, is_overflow) = vx.overflowing_add(vy);
-                        if is_overflow {
-                            self.register(0xF, 0x1);
-                            self.register(x, sum);
-                            return;
-                        }
-                        self.register(0xF, 0x0);
-                    }
-                    0x5 => {
-                        if vx > vy {
-                            self.register(0xF, 0x1);
-                            self.register(x, vx - vy);
-                            return;
-                        }
-                        self.register(0xF, 0x0);
-                    }
-                    0x6 => {
-                        let lsb: u8 = vx & 0x1;
-                        if lsb == 0x1 {
-                            self.register(0xF, lsb);
-                            self.register(x, vx >> 0x1);
-                        }
-                        self.register(0xF, 0x0);
-                    }
-                    0x7 => {
-                        if vy > vx {
-                            self.register(0xF, 0x1);
-                            self.register(x, vy - vx);
-                        }
-                        self.register(0xF, 0x0);
-                    }
-                    0xE => {
-                        let msb: u8 = (vx >> 7) & 0x1;
-                        if msb == 0x1 {
-                            self.register(0xF, 0x1);
-                            self.register(x, vx << 1);
-                        }
-                        self.register(0xF, 0x0);
-                    }
-                    _ => (),
-                }
-            }
-            0x9 => {
-                let x: u8 = nibble(&inst, 2);
-                let vx: u8 = self.get_register_data(&x);
-                let y: u8 = nibble(&inst, 1);
-                let vy: u8 = self.get_register_data(&y);
+    Ok(None)
+}
 
-                if vx != vy {
-                    self.pc -= 2;
-                }
-            }
-            0xA => {
-                let n: u16 = inst & 0x0FFF;
-                self.i = n;
-            }
-            0xB => {
-                let n: u16 = inst << 4;
-                let v0: u8 = self.get_register_data(&0x0);
-                self.pc = n + v0 as u16;
-            }
-            0xC => {
-                let x: u8 = nibble(&inst, 2);
-                let vx: u8 = self.get_register_data(&x);
-                let kk: u8 = inst as u8;
-                let ranNum: u8 = rand::thread_rng().r#gen();
+// Print how a finished playback compares to the recording. Returns false if
+// the movie has a final state hash and the emulator doesn't match it.
+fn report_playback(movie: &Movie, runner: &mut Runner) -> bool {
+    let state = runner.chip8().state_hash();
+    println!(
+        "Movie finished after {} frames, state {}",
+        movie.frames.len(),
+        state
+    );
+    match &movie.final_state {
+        Some(expected) if *expected == state => {
+            println!("Final state matches the recording");
+            true
+        }
+        Some(expected) => {
+            println!("Final state differs from the recording ({})", expected);
+            false
+        }
+        None => true,
+    }
+}
 
-                self.register(x, vx & kk);
-            }
-            0xD => {
-                let x: u8 = nibble(&inst, 2);
-                let vx = self.get_register_data(&x) as usize;
-                let y: u8 = nibble(&inst, 1);
-                let vy = self.get_register_data(&y) as usize;
+// The on-screen keypad key under window position `position`, if any, with
+// the screen drawn at `view`.
+fn keypad_key_under(view: Rect, screen: &Mutex<FrameBuffer>, position: (f32, f32)) -> Option<u8> {
+    let (width, height) = {
+        let buf = screen.lock().unwrap();
+        (buf.width(), buf.height())
+    };
+    let (x, y) = view.to_frame(position.0, position.1, width, height)?;
+    overlay::keypad_key_at(x, y, width, height, Anchor::Right)
+}
 
-                let n = (inst & 0x000F) as usize;
-                let i = self.i as usize;
+// F1 to F12 are the emulator's own keys
+fn is_hotkey(code: KeyCode) -> bool {
+    matches!(
+        code,
+        KeyCode::F1
+            | KeyCode::F2
+            | KeyCode::F3
+            | KeyCode::F4
+            | KeyCode::F5
+            | KeyCode::F6
+            | KeyCode::F7
+            | KeyCode::F8
+            | KeyCode::F9
+            | KeyCode::F10
+            | KeyCode::F11
+            | KeyCode::F12
+    )
+}
 
-                let mut vf: u8 = 0x0;
-                {
-                    let mut buffer = self.frame_buffer.lock().unwrap();
-                    for row in 0..n {
-                        let cur_sprite = self.memory[i + row];
-                        for col in 0..8usize {
-                            if cur_sprite & (0x80 >> col) != 0 {
-                                let idx_x = (vx + col) % SCREEN_WIDTH as usize;
-                                let idx_y = (vy + row) % SCREEN_HEIGHT as usize;
-                                if buffer[idx_y][idx_x] == 0x1 {
-                                    vf = 0x1;
-                                }
-                                buffer[idx_y][idx_x] ^= 1;
-                            }
-                        }
-                    }
-                }
-                self.register(0xF, vf);
-            }
-            0xE => {
-                let x: u8 = nibble(&inst, 2);
-                let vx: u8 = self.get_register_data(&x);
+fn toggle_fullscreen(window: &winit::window::Window) {
+    let fullscreen = match window.fullscreen() {
+        Some(_) => None,
+        None => Some(Fullscreen::Borderless(None)),
+    };
+    window.set_fullscreen(fullscreen);
+}
 
-                let indic: u16 = inst & 0x00FF;
+// Keep `keymap` as this ROM's bindings, or as the default when no ROM is
+// loaded, and write the keymap file.
+fn save_keymap(
+    config: &mut KeymapConfig,
+    path: &Path,
+    rom_sha1: &str,
+    keymap: &Keymap,
+    profile: Option<&str>,
+) {
+    if rom_sha1.is_empty() {
+        config.default = keymap.clone();
+    } else {
+        config.set_rom(rom_sha1, keymap, profile);
+    }
+    match config.save(path) {
+        Ok(()) => println!("Saved keymap to {}", path.display()),
+        Err(e) => println!("Error: saving {} failed: {}", path.display(), e),
+    }
+}
 
-                let is_press = self.input_handler.is_key_pressed(vx);
-                match indic {
-                    0x9E => {
-                        // let is_press = self.keypad[vx as usize];
-                        if is_press {
-                            self.pc += 2;
-                        }
-                    }
-                    0xA1 => {
-                        // let is_press = self.keypad[vx as usize];
-                        if !is_press {
-                            self.pc += 2;
-                        }
-                    }
-                    _ => (),
-                }
-            }
-            0xF => {
-                let x: u8 = nibble(&inst, 2);
-                let vx: u8 = self.get_register_data(&x);
+fn store_save(slot: Option<&SaveSlot>, runner: &mut Runner) {
+    if let Some(slot) = slot
+        && let Err(e) = slot.store(runner.chip8())
+    {
+        println!("Error: saving {} failed: {}", slot.path.display(), e);
+    }
+}
 
-                let indic: u16 = inst & 0x00FF;
-                match indic {
-                    0x7 => {
-                        self.register(x, self.delay_timer);
-                    }
-                    0xA => {
-                        for key in 0..16 {
-                            if self.input_handler.is_key_pressed(key) {
-                                self.register(x, key as u8);
-                                return;
-                            }
-                        }
-                        self.pc -= 2;
-                    }
-                    0x15 => {
-                        self.delay_timer = vx;
-                    }
-                    0x18 => {
-                        self.sound_timer = vx;
-                    }
-                    0x1E => {
-                        self.i += vx as u16;
-                    }
-                    0x29 => {
-                        self.i = FONTSET_START_ADDRESS + (vx as u16 * 5);
-                    }
-                    0x33 => {
-                        self.memory[self.i as usize] = (vx / 100) as u8; // 100
-                        self.memory[(self.i + 1) as usize] = ((vx / 10) % 10) as u8; // 10
-                        self.memory[(self.i + 2) as usize] = (vx % 10) as u8; // 1
-                    }
-                    0x55 => {
-                        for v in self.registers.iter().take((x + 1) as usize) {
-                            self.memory[self.i as usize] = *v;
-                            self.i += 1;
-                        }
-                    }
-                    0x65 => {
-                        for v in 0..x + 1 {
-                            self.register(v, self.memory[self.i as usize]);
-                            self.i += 1;
-                        }
-                    }
-                    _ => (),
-                }
-            }
-            _ => (),
-        }
+fn finish_movie(mut movie: Movie, runner: &mut Runner, path: &Path) {
+    movie.final_state = Some(runner.chip8().state_hash());
+    match movie.save(path) {
+        Ok(()) => println!("Saved {} ({} frames)", path.display(), movie.frames.len()),
+        Err(e) => println!("Error: saving {} failed: {}", path.display(), e),
     }
+}
 
-    fn get_sprite(&self, i: &u16, n: &u8) {}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chip8_emulator::input::InputHandler;
 
-    fn get_register_data(&self, regi: &u8) -> u8 {
-        self.registers[*regi as usize]
+    fn args(text: &str) -> Vec<String> {
+        text.split_whitespace().map(str::to_string).collect()
     }
 
-    fn register(&mut self, regi: u8, data: u8) -> Result<(), &'static str> {
-        if regi as usize >= self.registers.len() {
-            return Err("Overflow");
-        }
-        self.registers[regi as usize] = data;
-        Ok(())
+    #[test]
+    fn the_command_line_wins_over_the_config_file() {
+        let config = Config::parse("speed = 700\nseed = 5\n[roms.abc]\nspeed = 900\n").unwrap();
+        let options =
+            configured(Command::Run, &args("--seed 9 game.ch8"), config, None, None).unwrap();
+        assert_eq!(options.speed, Some(700));
+        assert_eq!(options.seed, Some(9));
+        assert_eq!(options.rom.as_deref(), Some("game.ch8"));
+
+        // the ROM's table goes between the file's top level and the command line
+        let options = options.for_rom("ABC").unwrap();
+        assert_eq!(options.speed, Some(900));
+        assert_eq!(options.seed, Some(9));
     }
 
-    fn push(&mut self, data: u16) -> Result<(), &'static str> {
-        if self.sp as usize >= self.stack.len() {
-            return Err("Stack Overflow");
-        }
-        self.stack[self.sp as usize] = data;
-        self.sp += 1;
-        Ok(())
+    #[test]
+    fn config_errors_name_the_setting() {
+        let config = Config::parse("speed = \"fast\"\n").unwrap();
+        let error = configured(Command::Run, &args("game.ch8"), config, None, None);
+        assert!(error.is_err_and(|e| e.contains("speed")));
+    }
+
+    #[test]
+    fn config_dump_sees_the_options_as_parsed() {
+        let config = Config::parse("seed = 5\n").unwrap();
+        let cli = args("-o out.8o --border 000000 --seed 9 --patch a.ips game.ch8");
+        let options = configured(Command::Disasm, &cli, config, None, None).unwrap();
+        let given: Vec<_> = options
+            .given
+            .iter()
+            .map(|s| (s.key.as_str(), s.value.clone()))
+            .collect();
+        assert_eq!(
+            given,
+            [
+                ("output", toml::Value::String("out.8o".to_string())),
+                ("border", toml::Value::String("000000".to_string())),
+                ("seed", toml::Value::Integer(9)),
+                ("patch", toml::Value::String("a.ips".to_string())),
+            ]
+        );
     }
 
-    fn pop(&mut self) -> Option<u16> {
-        if self.sp == 0 {
-            return None; // Stack underflow
+    #[test]
+    fn verify_needs_a_movie_to_play() {
+        let error = parse_options(Command::Headless, &args("--verify game.ch8"));
+        assert!(error.is_err_and(|e| e.contains("--play-movie")));
+        assert!(
+            parse_options(Command::Headless, &args("--verify --play-movie m game.ch8")).is_ok()
+        );
+    }
+
+    #[test]
+    fn a_stopped_macro_binds_the_next_key_after_f10() {
+        let mut input = InputHandler::new();
+        input.start_macro();
+        input.key_pressed(KeyCode::KeyW);
+        input.on_frame();
+        input.key_released(KeyCode::KeyW);
+        input.on_frame();
+        assert_eq!(input.stop_macro(), 2);
+        while input.next_event().is_some() {}
+
+        // the F10 that stopped it is followed by the key to bind it to
+        for code in [KeyCode::F10, KeyCode::KeyQ] {
+            if !is_hotkey(code) {
+                input.key_pressed(code);
+                input.key_released(code);
+            }
         }
-        self.sp -= 1;
-        Some(self.stack[self.sp as usize])
+        input.key_pressed(KeyCode::KeyQ);
+        input.on_frame();
+        assert_eq!(
+            input.next_event().map(|e| (e.key, e.pressed)),
+            Some((0x5, true))
+        );
     }
 }
@@ -0,0 +1,325 @@
+use crate::chip8::FONT_START_LOC;
+use crate::font::{FONT_SIZE, Font};
+use crate::input::InputHandler;
+use crate::quirks::Quirks;
+use crate::runner::Runner;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+const MAGIC: &str = "CHIP8MOVIE 1";
+
+/// Key events for every frame of a run plus everything else needed to
+/// repeat it exactly.
+///
+/// Saved as text: a header of `key value` lines, a `frames` line, then one
+/// line per frame listing the keys pressed (`5+`) and released (`5-`) before
+/// it in order, or `.` for none.
+#[derive(Debug, Clone)]
+pub struct Movie {
+    pub rom_sha1: String,
+    pub quirks: Quirks,
+    pub seed: u64,
+    pub font: Font,
+    pub instruction_hz: u64,
+    pub final_state: Option<String>, // `Chip8::state_hash` after the last frame
+    pub frames: Vec<Vec<(u8, bool)>>, // (key, pressed) queued before each frame
+}
+
+impl Movie {
+    /// Start an empty movie from the current settings of `runner`.
+    pub fn new(runner: &mut Runner) -> Self {
+        let instruction_hz = runner.instruction_hz();
+        let chip8 = runner.chip8();
+        chip8.input_handler.record_events(true);
+        Self {
+            rom_sha1: chip8.rom_sha1().to_string(),
+            quirks: chip8.quirks(),
+            seed: chip8.seed(),
+            font: chip8.font().clone(),
+            instruction_hz,
+            final_state: None,
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, io::Error> {
+        let text = fs::read_to_string(path)?;
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+        let mut lines = text.lines();
+        if lines.next() != Some(MAGIC) {
+            return Err(invalid(format!("{}: not a movie file", path.display())));
+        }
+
+        let mut movie = Movie {
+            rom_sha1: String::new(),
+            quirks: Quirks::default(),
+            seed: 0,
+            font: Font::default(),
+            instruction_hz: 0,
+            final_state: None,
+            frames: Vec::new(),
+        };
+        let mut font_name = String::new();
+        let mut font_glyphs = None;
+        let mut font_address = FONT_START_LOC as u16;
+        for line in lines.by_ref() {
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            let bad_value = || invalid(format!("invalid movie {}: {}", key, value));
+            match key {
+                "rom" => movie.rom_sha1 = value.to_string(),
+                "quirks" => movie.quirks = Quirks::parse(value).ok_or_else(bad_value)?,
+                "seed" => movie.seed = value.parse().map_err(|_| bad_value())?,
+                "font" => font_name = value.to_string(),
+                "font-address" => {
+                    font_address = u16::from_str_radix(value, 16)
+                        .ok()
+                        .filter(|&address| Font::valid_address(address))
+                        .ok_or_else(bad_value)?;
+                }
+                "font-glyphs" => {
+                    let glyphs: [u8; FONT_SIZE] = value
+                        .split_whitespace()
+                        .map(|byte| u8::from_str_radix(byte, 16).ok())
+                        .collect::<Option<Vec<u8>>>()
+                        .and_then(|glyphs| glyphs.try_into().ok())
+                        .ok_or_else(bad_value)?;
+                    font_glyphs = Some(glyphs);
+                }
+                "hz" => movie.instruction_hz = value.parse().map_err(|_| bad_value())?,
+                "final" => movie.final_state = Some(value.to_string()),
+                "frames" => break,
+                _ => return Err(invalid(format!("unknown movie field: {}", key))),
+            }
+        }
+        if movie.instruction_hz == 0 {
+            return Err(invalid("movie has no hz".to_string()));
+        }
+        // without glyphs the movie was recorded with the default font
+        movie.font = match font_glyphs {
+            Some(glyphs) => Font::new(&font_name, glyphs),
+            None => Font::default(),
+        }
+        .with_address(font_address);
+        for line in lines {
+            let bad_frame = || invalid(format!("invalid movie frame: {}", line));
+            let events = line
+                .split_whitespace()
+                .filter(|token| *token != ".")
+                .map(|token| parse_event(token).ok_or_else(bad_frame))
+                .collect::<Result<_, _>>()?;
+            movie.frames.push(events);
+        }
+        Ok(movie)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), io::Error> {
+        let mut text = format!(
+            "{}\nrom {}\nquirks {}\nseed {}\nhz {}\n",
+            MAGIC, self.rom_sha1, self.quirks, self.seed, self.instruction_hz
+        );
+        let font = &self.font;
+        let glyphs: Vec<String> = font.glyphs().iter().map(|b| format!("{:02x}", b)).collect();
+        text += &format!(
+            "font {}\nfont-address {:03x}\nfont-glyphs {}\n",
+            font.name(),
+            font.address(),
+            glyphs.join(" ")
+        );
+        if let Some(hash) = &self.final_state {
+            text += &format!("final {}\n", hash);
+        }
+        text += "frames\n";
+        for events in &self.frames {
+            if events.is_empty() {
+                text += ".";
+            }
+            for (i, (key, pressed)) in events.iter().enumerate() {
+                let sep = if i == 0 { "" } else { " " };
+                text += &format!("{}{:x}{}", sep, key, if *pressed { '+' } else { '-' });
+            }
+            text += "\n";
+        }
+        fs::write(path, text)
+    }
+
+    /// Set up a freshly loaded `runner` the way the movie was recorded.
+    /// Fails if a different ROM is loaded.
+    pub fn prepare(&self, runner: &mut Runner) -> Result<(), io::Error> {
+        runner.set_instruction_hz(self.instruction_hz);
+        let chip8 = runner.chip8();
+        if chip8.rom_sha1() != self.rom_sha1 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "movie was recorded with ROM {}, loaded ROM is {}",
+                    self.rom_sha1,
+                    chip8.rom_sha1()
+                ),
+            ));
+        }
+        chip8.set_quirks(self.quirks);
+        chip8.seed_rng(self.seed);
+        chip8.set_font(self.font.clone());
+        Ok(())
+    }
+}
+
+pub enum MovieState {
+    Recording(Movie),
+    Playing { movie: Movie, frame: usize },
+}
+
+impl MovieState {
+    /// Call before running each frame. Recording appends the key events
+    /// queued since the last frame; playback queues the recorded ones.
+    /// Returns false once playback has run out of frames.
+    pub fn before_frame(&mut self, input: &mut InputHandler) -> bool {
+        match self {
+            MovieState::Recording(movie) => {
+                let events = input.take_recorded();
+                movie
+                    .frames
+                    .push(events.iter().map(|e| (e.key, e.pressed)).collect());
+                true
+            }
+            MovieState::Playing { movie, frame } => match movie.frames.get(*frame) {
+                Some(events) => {
+                    for &(key, pressed) in events {
+                        input.queue_key(key, pressed);
+                    }
+                    *frame += 1;
+                    true
+                }
+                None => false,
+            },
+        }
+    }
+}
+
+// `5+` or `5-`
+fn parse_event(token: &str) -> Option<(u8, bool)> {
+    let pressed = match token.chars().last()? {
+        '+' => true,
+        '-' => false,
+        _ => return None,
+    };
+    let key = u8::from_str_radix(&token[..token.len() - 1], 16).ok()?;
+    (key < 16).then_some((key, pressed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::tests::counter;
+
+    const HZ: u64 = 700;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("chip8-movie-{}-{}.txt", std::process::id(), name))
+    }
+
+    // run the counter for a few seconds pressing `keys` at (frame, key, pressed)
+    fn record(keys: &[(usize, u8, bool)]) -> Movie {
+        let mut runner = counter(HZ);
+        runner.chip8().seed_rng(42);
+        runner
+            .chip8()
+            .set_font(Font::builtin("vip").unwrap().with_address(0x80));
+        let mut state = MovieState::Recording(Movie::new(&mut runner));
+        for frame in 0..300 {
+            let input = &mut runner.chip8().input_handler;
+            for &(_, key, pressed) in keys.iter().filter(|(at, _, _)| *at == frame) {
+                input.queue_key(key, pressed);
+            }
+            state.before_frame(input);
+            runner.step_frame();
+        }
+        let MovieState::Recording(mut movie) = state else {
+            unreachable!()
+        };
+        movie.final_state = Some(runner.chip8().state_hash());
+        movie
+    }
+
+    // replay `movie` on a runner set up differently, as `prepare` has to fix
+    fn play(movie: &Movie) -> String {
+        let mut runner = counter(HZ * 2);
+        movie.prepare(&mut runner).unwrap();
+        let mut state = MovieState::Playing {
+            movie: movie.clone(),
+            frame: 0,
+        };
+        while state.before_frame(&mut runner.chip8().input_handler) {
+            runner.step_frame();
+        }
+        runner.chip8().state_hash()
+    }
+
+    const KEYS: [(usize, u8, bool); 4] = [
+        (60, 0x9, true),
+        (62, 0x9, false),
+        (150, 0x5, true),
+        (151, 0x5, false),
+    ];
+
+    #[test]
+    fn saved_movies_replay_to_the_same_state() {
+        let movie = record(&KEYS);
+        let path = temp_path("replay");
+        movie.save(&path).unwrap();
+        let loaded = Movie::load(&path).unwrap();
+        let _ = fs::remove_file(&path);
+
+        assert_eq!(loaded.rom_sha1, movie.rom_sha1);
+        assert_eq!(loaded.seed, 42);
+        assert_eq!(loaded.font, movie.font);
+        assert_eq!(loaded.instruction_hz, HZ);
+        assert_eq!(loaded.final_state, movie.final_state);
+        assert_eq!(loaded.frames, movie.frames);
+        assert_eq!(loaded.frames[60], [(0x9, true)]);
+        assert!(loaded.frames[61].is_empty());
+        assert_eq!(Some(play(&loaded)), movie.final_state);
+    }
+
+    #[test]
+    fn other_input_ends_elsewhere() {
+        let movie = record(&KEYS);
+        let mut edited = movie.clone();
+        edited.frames[60].clear();
+        edited.frames[62].clear();
+        assert_ne!(Some(play(&edited)), movie.final_state);
+        assert_ne!(record(&[]).final_state, movie.final_state);
+    }
+
+    #[test]
+    fn another_rom_is_refused() {
+        let mut movie = record(&[]);
+        movie.rom_sha1 = "0".repeat(40);
+        assert!(movie.prepare(&mut counter(HZ)).is_err());
+    }
+
+    #[test]
+    fn bad_movies_are_refused() {
+        for (i, text) in [
+            "CHIP8MOVIE 9\nhz 500\nframes\n",
+            "CHIP8MOVIE 1\nrom abc\nframes\n",
+            "CHIP8MOVIE 1\nhz fast\nframes\n",
+            "CHIP8MOVIE 1\nhz 500\nseed -1\nframes\n",
+            "CHIP8MOVIE 1\nhz 500\nfont-address fff\nframes\n",
+            "CHIP8MOVIE 1\nhz 500\nfont-glyphs 00 01\nframes\n",
+            "CHIP8MOVIE 1\nhz 500\nquirks nonsense\nframes\n",
+            "CHIP8MOVIE 1\nhz 500\nspeed 3\nframes\n",
+            "CHIP8MOVIE 1\nhz 500\nframes\n5+ g-\n",
+            "CHIP8MOVIE 1\nhz 500\nframes\n10+\n",
+        ]
+        .iter()
+        .enumerate()
+        {
+            let path = temp_path(&format!("bad-{}", i));
+            fs::write(&path, text).unwrap();
+            assert!(Movie::load(&path).is_err(), "{:?}", text);
+            let _ = fs::remove_file(&path);
+        }
+    }
+}
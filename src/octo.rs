@@ -0,0 +1,785 @@
+// An assembler for the core of Octo, the CHIP-8 assembly language most
+// community games are written in (https://github.com/JohnEarnest/Octo).
+// It covers labels, constants, aliases, simple macros, the structured
+// `if`/`loop` statements and the SCHIP and XO-CHIP instructions, but not
+// `:calc`, `:stringmode` or `:assert`.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+
+const START: usize = 0x200;
+const END: usize = 0x10000; // XO-CHIP's 64K of memory
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    line: usize,
+}
+
+// How a label's address is patched into code emitted before it was known.
+#[derive(Debug, Clone, Copy)]
+enum Patch {
+    Low12,        // nnn of an opcode
+    Long,         // the word after `F000`
+    UnpackHi(u8), // nibble << 4 | top four address bits
+    UnpackLo,
+}
+
+enum Block {
+    If(usize),               // jump over the `begin` body
+    Else(usize),             // jump over the `else` body
+    Loop(usize, Vec<usize>), // start, `while` jumps out
+}
+
+struct Assembler {
+    tokens: VecDeque<Token>,
+    line: usize,
+    rom: Vec<u8>,
+    here: usize,
+    labels: HashMap<String, usize>,
+    constants: HashMap<String, usize>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, (Vec<String>, Vec<Token>)>,
+    fixups: Vec<(usize, String, Patch, usize)>, // address, label, how, line
+    blocks: Vec<Block>,
+    next_label: Option<String>,
+}
+
+/// Assemble Octo `source` into a ROM image that loads at 0x200. The image
+/// starts with a jump to the `main` label unless the source starts there.
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    let mut tokens = VecDeque::new();
+    for (number, line) in source.lines().enumerate() {
+        let code = line.split('#').next().unwrap_or_default();
+        for text in code.split_whitespace() {
+            tokens.push_back(Token {
+                text: text.to_string(),
+                line: number + 1,
+            });
+        }
+    }
+    let mut asm = Assembler {
+        tokens,
+        line: 1,
+        rom: Vec::new(),
+        here: START,
+        labels: HashMap::new(),
+        constants: HashMap::new(),
+        aliases: HashMap::new(),
+        macros: HashMap::new(),
+        fixups: Vec::new(),
+        blocks: Vec::new(),
+        next_label: None,
+    };
+    let starts_at_main =
+        asm.tokens.len() >= 2 && asm.tokens[0].text == ":" && asm.tokens[1].text == "main";
+    if !starts_at_main {
+        asm.fixups
+            .push((START, "main".to_string(), Patch::Low12, 1));
+        asm.emit(0x1000);
+    }
+    while let Some(token) = asm.tokens.pop_front() {
+        asm.line = token.line;
+        asm.statement(&token.text)?;
+    }
+    asm.finish()
+}
+
+fn register(text: &str) -> Option<u8> {
+    let digit = text.strip_prefix(['v', 'V'])?;
+    if digit.len() != 1 {
+        return None;
+    }
+    u8::from_str_radix(digit, 16).ok()
+}
+
+fn number(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(bin) = digits.strip_prefix("0b") {
+        i64::from_str_radix(bin, 2).ok()?
+    } else {
+        digits.parse().ok()?
+    };
+    Some(if negative { -value } else { value })
+}
+
+// the condition that holds when `op` doesn't
+fn negate(op: &str) -> Option<&'static str> {
+    Some(match op {
+        "==" => "!=",
+        "!=" => "==",
+        "<" => ">=",
+        ">=" => "<",
+        ">" => "<=",
+        "<=" => ">",
+        "key" => "-key",
+        "-key" => "key",
+        _ => return None,
+    })
+}
+
+impl Assembler {
+    fn error<T>(&self, message: impl Into<String>) -> Result<T, AsmError> {
+        Err(AsmError {
+            line: self.line,
+            message: message.into(),
+        })
+    }
+
+    fn next(&mut self) -> Result<String, AsmError> {
+        match self.tokens.pop_front() {
+            Some(token) => {
+                self.line = token.line;
+                Ok(token.text)
+            }
+            None => self.error("unexpected end of file"),
+        }
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.front().map(|token| token.text.as_str())
+    }
+
+    fn expect(&mut self, word: &str) -> Result<(), AsmError> {
+        let token = self.next()?;
+        if token != word {
+            return self.error(format!("expected {}, found {}", word, token));
+        }
+        Ok(())
+    }
+
+    fn put(&mut self, addr: usize, byte: u8) {
+        let offset = addr - START;
+        if self.rom.len() <= offset {
+            self.rom.resize(offset + 1, 0);
+        }
+        self.rom[offset] = byte;
+    }
+
+    fn emit(&mut self, op: u16) {
+        if let Some(name) = self.next_label.take() {
+            self.labels.insert(name, self.here + 1);
+        }
+        let [hi, lo] = op.to_be_bytes();
+        self.put(self.here, hi);
+        self.put(self.here + 1, lo);
+        self.here += 2;
+    }
+
+    fn emit_byte(&mut self, byte: u8) {
+        self.put(self.here, byte);
+        self.here += 1;
+    }
+
+    fn register(&mut self) -> Result<u8, AsmError> {
+        let token = self.next()?;
+        match register(&token).or_else(|| self.aliases.get(&token).copied()) {
+            Some(reg) => Ok(reg),
+            None => self.error(format!("expected a register, found {}", token)),
+        }
+    }
+
+    fn is_register(&self, text: &str) -> bool {
+        register(text).is_some() || self.aliases.contains_key(text)
+    }
+
+    // a number or constant
+    fn value(&mut self, token: &str) -> Result<i64, AsmError> {
+        if let Some(value) = number(token) {
+            return Ok(value);
+        }
+        match self.constants.get(token).or(self.labels.get(token)) {
+            Some(&value) => Ok(value as i64),
+            None => self.error(format!("unknown value {}", token)),
+        }
+    }
+
+    fn byte(&mut self) -> Result<u8, AsmError> {
+        let token = self.next()?;
+        let value = self.value(&token)?;
+        if !(-128..=255).contains(&value) {
+            return self.error(format!("{} does not fit in a byte", token));
+        }
+        Ok(value as u8)
+    }
+
+    fn nibble(&mut self) -> Result<u16, AsmError> {
+        let token = self.next()?;
+        let value = self.value(&token)?;
+        if !(0..16).contains(&value) {
+            return self.error(format!("{} does not fit in a nibble", token));
+        }
+        Ok(value as u16)
+    }
+
+    // an address, patched in later if it names a label not defined yet
+    fn address(&mut self, at: usize, patch: Patch) -> Result<usize, AsmError> {
+        let token = self.next()?;
+        if let Some(value) = number(&token).or(self.constants.get(&token).map(|&v| v as i64)) {
+            return Ok(value as usize);
+        }
+        if let Some(&addr) = self.labels.get(&token) {
+            return Ok(addr);
+        }
+        if self.is_register(&token) || token.starts_with(':') {
+            return self.error(format!("expected an address, found {}", token));
+        }
+        self.fixups.push((at, token, patch, self.line));
+        Ok(0)
+    }
+
+    fn emit_address(&mut self, opcode: u16) -> Result<(), AsmError> {
+        let addr = self.address(self.here, Patch::Low12)?;
+        if addr > 0xFFF {
+            return self.error(format!("address {:#x} is out of range", addr));
+        }
+        self.emit(opcode | addr as u16);
+        Ok(())
+    }
+
+    fn statement(&mut self, token: &str) -> Result<(), AsmError> {
+        let op = |x: u8, y: u8, n: u16| (x as u16) << 8 | (y as u16) << 4 | n;
+        match token {
+            ":" => {
+                let name = self.next()?;
+                if self.labels.insert(name.clone(), self.here).is_some() {
+                    return self.error(format!("label {} is defined twice", name));
+                }
+            }
+            ":const" => {
+                let name = self.next()?;
+                let token = self.next()?;
+                let value = self.value(&token)?;
+                self.constants.insert(name, value as usize);
+            }
+            ":alias" => {
+                let name = self.next()?;
+                let reg = self.register()?;
+                self.aliases.insert(name, reg);
+            }
+            ":org" => {
+                let token = self.next()?;
+                let addr = self.value(&token)?;
+                if !(START as i64..END as i64).contains(&addr) {
+                    return self.error(format!(":org {} is outside 0x200-0xffff", token));
+                }
+                self.here = addr as usize;
+            }
+            ":byte" => {
+                let byte = self.byte()?;
+                self.emit_byte(byte);
+            }
+            ":call" => self.emit_address(0x2000)?,
+            ":next" => self.next_label = Some(self.next()?),
+            ":unpack" => {
+                let token = self.next()?;
+                let nibble = if token == "long" {
+                    0
+                } else {
+                    match self.value(&token)? {
+                        n @ 0..=15 => n as u8,
+                        _ => return self.error(format!("{} does not fit in a nibble", token)),
+                    }
+                };
+                let pending = self.fixups.len();
+                let addr = self.address(self.here + 1, Patch::UnpackHi(nibble))?;
+                if let Some((_, label, _, line)) = self.fixups.get(pending).cloned() {
+                    self.fixups
+                        .push((self.here + 3, label, Patch::UnpackLo, line));
+                }
+                self.emit(0x6000 | (nibble as u16) << 4 | (addr >> 8) as u16);
+                self.emit(0x6100 | (addr & 0xFF) as u16);
+            }
+            ":macro" => {
+                let name = self.next()?;
+                let mut params = Vec::new();
+                loop {
+                    let token = self.next()?;
+                    if token == "{" {
+                        break;
+                    }
+                    params.push(token);
+                }
+                let mut body = Vec::new();
+                let mut depth = 1;
+                loop {
+                    let Some(token) = self.tokens.pop_front() else {
+                        return self.error(format!("macro {} is missing its closing }}", name));
+                    };
+                    match token.text.as_str() {
+                        "{" => depth += 1,
+                        "}" => depth -= 1,
+                        _ => {}
+                    }
+                    if depth == 0 {
+                        break;
+                    }
+                    body.push(token);
+                }
+                self.macros.insert(name, (params, body));
+            }
+            ":breakpoint" | ":proto" => {
+                self.next()?;
+            }
+            ":monitor" => {
+                self.next()?;
+                self.next()?;
+            }
+            ";" | "return" => self.emit(0x00EE),
+            "clear" => self.emit(0x00E0),
+            "hires" => self.emit(0x00FF),
+            "lores" => self.emit(0x00FE),
+            "scroll-left" => self.emit(0x00FC),
+            "scroll-right" => self.emit(0x00FB),
+            "exit" => self.emit(0x00FD),
+            "audio" => self.emit(0xF002),
+            "scroll-down" => {
+                let n = self.nibble()?;
+                self.emit(0x00C0 | n);
+            }
+            "scroll-up" => {
+                let n = self.nibble()?;
+                self.emit(0x00D0 | n);
+            }
+            "plane" => {
+                let n = self.nibble()?;
+                self.emit(0xF001 | n << 8);
+            }
+            "bcd" => {
+                let x = self.register()?;
+                self.emit(0xF033 | op(x, 0, 0));
+            }
+            "saveflags" => {
+                let x = self.register()?;
+                self.emit(0xF075 | op(x, 0, 0));
+            }
+            "loadflags" => {
+                let x = self.register()?;
+                self.emit(0xF085 | op(x, 0, 0));
+            }
+            "save" | "load" => {
+                let x = self.register()?;
+                if self.peek() == Some("-") {
+                    self.next()?;
+                    let y = self.register()?;
+                    let n = if token == "save" { 2 } else { 3 };
+                    self.emit(0x5000 | op(x, y, n));
+                } else {
+                    let nn = if token == "save" { 0x55 } else { 0x65 };
+                    self.emit(0xF000 | op(x, 0, nn));
+                }
+            }
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let n = self.nibble()?;
+                self.emit(0xD000 | op(x, y, n));
+            }
+            "jump" => self.emit_address(0x1000)?,
+            "jump0" => self.emit_address(0xB000)?,
+            "native" => self.emit_address(0x0000)?,
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.register()?;
+                let nn = match token {
+                    "delay" => 0x15,
+                    "buzzer" => 0x18,
+                    _ => 0x3A,
+                };
+                self.emit(0xF000 | op(x, 0, nn));
+            }
+            "i" => self.index()?,
+            "loop" => self.blocks.push(Block::Loop(self.here, Vec::new())),
+            "while" => {
+                let (reg, cond, rhs) = self.condition()?;
+                // skip the jump out while the condition holds
+                self.skip_unless(reg, negate(&cond).unwrap(), &rhs)?;
+                let jump = self.here;
+                self.emit(0x1000);
+                match self.blocks.iter_mut().rev().find_map(|block| match block {
+                    Block::Loop(_, exits) => Some(exits),
+                    _ => None,
+                }) {
+                    Some(exits) => exits.push(jump),
+                    None => return self.error("while outside a loop"),
+                }
+            }
+            "again" => {
+                let Some(Block::Loop(start, exits)) = self.blocks.pop() else {
+                    return self.error("again without a loop");
+                };
+                self.emit(0x1000 | start as u16);
+                for exit in exits {
+                    self.patch(exit, self.here);
+                }
+            }
+            "if" => {
+                let (reg, cond, rhs) = self.condition()?;
+                match self.next()?.as_str() {
+                    "then" => self.skip_unless(reg, &cond, &rhs)?,
+                    "begin" => {
+                        self.skip_unless(reg, negate(&cond).unwrap(), &rhs)?;
+                        self.blocks.push(Block::If(self.here));
+                        self.emit(0x1000);
+                    }
+                    other => return self.error(format!("expected then or begin, found {}", other)),
+                }
+            }
+            "else" => {
+                let Some(Block::If(jump)) = self.blocks.pop() else {
+                    return self.error("else without if ... begin");
+                };
+                self.blocks.push(Block::Else(self.here));
+                self.emit(0x1000);
+                self.patch(jump, self.here);
+            }
+            "end" => match self.blocks.pop() {
+                Some(Block::If(jump) | Block::Else(jump)) => self.patch(jump, self.here),
+                _ => return self.error("end without if ... begin"),
+            },
+            _ if self.is_register(token) => self.assignment(token)?,
+            _ if number(token).is_some() || self.constants.contains_key(token) => {
+                let value = self.value(token)?;
+                self.emit_byte(value as u8);
+            }
+            _ if self.macros.contains_key(token) => {
+                let (params, body) = self.macros[token].clone();
+                let mut args = HashMap::new();
+                for param in params {
+                    args.insert(param, self.next()?);
+                }
+                for mut token in body.into_iter().rev() {
+                    if let Some(arg) = args.get(&token.text) {
+                        token.text = arg.clone();
+                    }
+                    self.tokens.push_front(token);
+                }
+            }
+            _ if token.starts_with(':') => {
+                return self.error(format!("{} is not supported", token));
+            }
+            _ => {
+                // a bare label is a call
+                self.tokens.push_front(Token {
+                    text: token.to_string(),
+                    line: self.line,
+                });
+                self.emit_address(0x2000)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn index(&mut self) -> Result<(), AsmError> {
+        let operator = self.next()?;
+        match operator.as_str() {
+            ":=" => match self.peek() {
+                Some("hex") | Some("bighex") => {
+                    let nn = if self.next()? == "hex" { 0x29 } else { 0x30 };
+                    let x = self.register()?;
+                    self.emit(0xF000 | (x as u16) << 8 | nn);
+                }
+                Some("long") => {
+                    self.next()?;
+                    self.emit(0xF000);
+                    let addr = self.address(self.here, Patch::Long)?;
+                    self.put(self.here, (addr >> 8) as u8);
+                    self.put(self.here + 1, addr as u8);
+                    self.here += 2;
+                }
+                _ => self.emit_address(0xA000)?,
+            },
+            "+=" => {
+                let x = self.register()?;
+                self.emit(0xF01E | (x as u16) << 8);
+            }
+            _ => return self.error(format!("unknown operator i {}", operator)),
+        }
+        Ok(())
+    }
+
+    fn assignment(&mut self, target: &str) -> Result<(), AsmError> {
+        let x = register(target).unwrap_or_else(|| self.aliases[target]) as u16;
+        let operator = self.next()?;
+        let rhs = self.peek().unwrap_or_default().to_string();
+        if self.is_register(&rhs) {
+            let y = self.register()? as u16;
+            let n = match operator.as_str() {
+                ":=" => 0x0,
+                "|=" => 0x1,
+                "&=" => 0x2,
+                "^=" => 0x3,
+                "+=" => 0x4,
+                "-=" => 0x5,
+                ">>=" => 0x6,
+                "=-" => 0x7,
+                "<<=" => 0xE,
+                _ => return self.error(format!("unknown operator {}", operator)),
+            };
+            self.emit(0x8000 | x << 8 | y << 4 | n);
+            return Ok(());
+        }
+        match (operator.as_str(), rhs.as_str()) {
+            (":=", "key") => {
+                self.next()?;
+                self.emit(0xF00A | x << 8);
+            }
+            (":=", "delay") => {
+                self.next()?;
+                self.emit(0xF007 | x << 8);
+            }
+            (":=", "random") => {
+                self.next()?;
+                let mask = self.byte()?;
+                self.emit(0xC000 | x << 8 | mask as u16);
+            }
+            (":=", _) => {
+                let nn = self.byte()?;
+                self.emit(0x6000 | x << 8 | nn as u16);
+            }
+            ("+=", _) => {
+                let nn = self.byte()?;
+                self.emit(0x7000 | x << 8 | nn as u16);
+            }
+            ("-=", _) => {
+                let nn = self.byte()?;
+                self.emit(0x7000 | x << 8 | nn.wrapping_neg() as u16);
+            }
+            _ => return self.error(format!("unknown operator {} {}", operator, rhs)),
+        }
+        Ok(())
+    }
+
+    // `vx op rhs` after `if` or `while`
+    fn condition(&mut self) -> Result<(u8, String, String), AsmError> {
+        let reg = self.register()?;
+        let cond = self.next()?;
+        if negate(&cond).is_none() {
+            return self.error(format!("unknown comparison {}", cond));
+        }
+        let rhs = if cond.ends_with("key") {
+            String::new()
+        } else {
+            self.next()?
+        };
+        Ok((reg, cond, rhs))
+    }
+
+    // Emit code that skips the next instruction unless `vx cond rhs` holds.
+    fn skip_unless(&mut self, x: u8, cond: &str, rhs: &str) -> Result<(), AsmError> {
+        let x16 = (x as u16) << 8;
+        let y = register(rhs).or_else(|| self.aliases.get(rhs).copied());
+        let immediate = |asm: &mut Self| -> Result<i64, AsmError> {
+            let value = asm.value(rhs)?;
+            if !(-128..=255).contains(&value) {
+                return asm.error(format!("{} does not fit in a byte", rhs));
+            }
+            Ok(value & 0xFF)
+        };
+        match (cond, y) {
+            ("key", _) => self.emit(0xE0A1 | x16),
+            ("-key", _) => self.emit(0xE09E | x16),
+            ("==", Some(y)) => self.emit(0x9000 | x16 | (y as u16) << 4),
+            ("!=", Some(y)) => self.emit(0x5000 | x16 | (y as u16) << 4),
+            ("==", None) => {
+                let nn = immediate(self)?;
+                self.emit(0x4000 | x16 | nn as u16);
+            }
+            ("!=", None) => {
+                let nn = immediate(self)?;
+                self.emit(0x3000 | x16 | nn as u16);
+            }
+            _ => {
+                // vf := a; vf =- b leaves vf = 1 exactly when b >= a
+                let (a, b, holds) = match (cond, y) {
+                    (">=", _) => (y, Some(x), 1),
+                    ("<", _) => (y, Some(x), 0),
+                    (">", Some(_)) => (Some(x), y, 0),
+                    ("<=", Some(_)) => (Some(x), y, 1),
+                    _ => {
+                        // x > n is x >= n + 1, x <= n is x < n + 1
+                        let n = immediate(self)?;
+                        if n == 0xFF {
+                            return self.error(format!("{} {} is constant", cond, rhs));
+                        }
+                        self.emit(0x6F00 | (n as u16 + 1));
+                        self.emit(0x8F07 | x16 >> 4);
+                        let holds = if cond == ">" { 1 } else { 0 };
+                        self.emit(0x4F00 | holds);
+                        return Ok(());
+                    }
+                };
+                match a {
+                    Some(a) => self.emit(0x8F00 | (a as u16) << 4),
+                    None => {
+                        let n = immediate(self)?;
+                        self.emit(0x6F00 | n as u16);
+                    }
+                }
+                self.emit(0x8F07 | (b.unwrap() as u16) << 4);
+                self.emit(0x4F00 | holds);
+            }
+        }
+        Ok(())
+    }
+
+    // point the jump at `at` to `target`
+    fn patch(&mut self, at: usize, target: usize) {
+        let offset = at - START;
+        self.rom[offset] = self.rom[offset] & 0xF0 | (target >> 8) as u8 & 0x0F;
+        self.rom[offset + 1] = target as u8;
+    }
+
+    fn finish(mut self) -> Result<Vec<u8>, AsmError> {
+        if !self.blocks.is_empty() {
+            return self.error("missing end or again at the end of the file");
+        }
+        for (at, label, how, line) in std::mem::take(&mut self.fixups) {
+            let Some(&addr) = self.labels.get(&label) else {
+                let message = if label == "main" {
+                    "the program has no main label".to_string()
+                } else {
+                    format!("undefined label {}", label)
+                };
+                return Err(AsmError { line, message });
+            };
+            match how {
+                Patch::Low12 => {
+                    if addr > 0xFFF {
+                        return Err(AsmError {
+                            line,
+                            message: format!("label {} is out of range", label),
+                        });
+                    }
+                    self.patch(at, addr);
+                }
+                Patch::Long => {
+                    self.put(at, (addr >> 8) as u8);
+                    self.put(at + 1, addr as u8);
+                }
+                Patch::UnpackHi(nibble) => self.put(at, nibble << 4 | (addr >> 8) as u8 & 0xF),
+                Patch::UnpackLo => self.put(at, addr as u8),
+            }
+        }
+        Ok(self.rom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(source: &str) -> AsmError {
+        assemble(source).unwrap_err()
+    }
+
+    #[test]
+    fn starting_at_main_needs_no_jump() {
+        let source =
+            ": main\n clear\n v0 := 5\n v1 += v0\n i := hex v0\n sprite v0 v1 5\n return\n";
+        assert_eq!(
+            assemble(source).unwrap(),
+            [
+                0x00, 0xE0, 0x60, 0x05, 0x81, 0x04, 0xF0, 0x29, 0xD0, 0x15, 0x00, 0xEE
+            ]
+        );
+    }
+
+    #[test]
+    fn main_elsewhere_is_jumped_to() {
+        let source = ": sub\n return\n: main\n sub\n jump main\n";
+        assert_eq!(
+            assemble(source).unwrap(),
+            [0x12, 0x04, 0x00, 0xEE, 0x22, 0x02, 0x12, 0x04]
+        );
+    }
+
+    #[test]
+    fn structured_statements_become_skips_and_jumps() {
+        let source = ": main
+            loop
+                v0 += 1
+                if v0 == 10 then v1 := 1
+                while v0 != 20
+            again
+            if v2 key begin v3 := 1 else v3 := 2 end
+        ";
+        assert_eq!(
+            assemble(source).unwrap(),
+            [
+                0x70, 0x01, 0x40, 0x0A, 0x61, 0x01, 0x40, 0x14, 0x12, 0x0C, 0x12, 0x00, 0xE2, 0x9E,
+                0x12, 0x14, 0x63, 0x01, 0x12, 0x16, 0x63, 0x02
+            ]
+        );
+    }
+
+    #[test]
+    fn constants_aliases_and_macros_expand() {
+        let source = ": main
+            :const SPEED 3
+            :alias x v4
+            :macro twice reg { reg += 1 reg += 1 }
+            x := SPEED
+            twice x
+            i := long data
+            :unpack 0xA data
+            : data 0xAB 7 # two bytes
+        ";
+        assert_eq!(
+            assemble(source).unwrap(),
+            [
+                0x64, 0x03, 0x74, 0x01, 0x74, 0x01, 0xF0, 0x00, 0x02, 0x0E, 0x60, 0xA2, 0x61, 0x0E,
+                0xAB, 0x07
+            ]
+        );
+    }
+
+    #[test]
+    fn errors_give_the_line() {
+        assert_eq!(
+            error(": main\n jump nowhere\n"),
+            AsmError {
+                line: 2,
+                message: "undefined label nowhere".to_string()
+            }
+        );
+        assert_eq!(error("clear\n").message, "the program has no main label");
+        assert_eq!(error(": main\n: main\n").line, 2);
+        assert!(error(": main\n loop\n").message.contains("missing end"));
+        assert!(error(": main\n else\n").message.contains("without if"));
+        assert!(
+            error(": main\n :calc x { 1 }\n")
+                .message
+                .contains("not supported")
+        );
+    }
+
+    #[test]
+    fn org_stays_inside_memory() {
+        for address in ["0x100", "-1", "0x10000", "0xffffffff"] {
+            let source = format!(": main\n :org {}\n clear\n", address);
+            assert_eq!(error(&source).line, 2, "{}", address);
+        }
+        let rom = assemble(": main\n clear\n :org 0x206\n :byte 1\n").unwrap();
+        assert_eq!(rom, [0x00, 0xE0, 0, 0, 0, 0, 1]);
+    }
+}
@@ -0,0 +1,198 @@
+// On-screen drawing over the presented frame, at the frame buffer's native
+// resolution so post-processing filters apply to it as well.
+
+use crate::chip8::FONTSET;
+use crate::keymap::KEYPAD_LAYOUT;
+use crate::palette::{Palette, Rgb};
+
+// one key: a 4x5 font glyph with a pixel of padding around it
+const CELL_WIDTH: usize = 6;
+const CELL_HEIGHT: usize = 7;
+// the keys plus a one pixel border
+const PANEL_WIDTH: usize = CELL_WIDTH * 4 + 2;
+const PANEL_HEIGHT: usize = CELL_HEIGHT * 4 + 2;
+
+/// Where on the screen the keypad goes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Anchor {
+    Center,
+    Right,
+}
+
+// Left and top edge of the keypad and its pixel size, or `None` when the
+// screen is too small for it.
+fn keypad_rect(width: usize, height: usize, anchor: Anchor) -> Option<(usize, usize, usize)> {
+    // lores fits the keypad once, hires twice as big
+    let scale = (width / 64).min(height / 32).max(1);
+    let (panel_width, panel_height) = (PANEL_WIDTH * scale, PANEL_HEIGHT * scale);
+    if panel_width > width || panel_height > height {
+        return None;
+    }
+    let left = match anchor {
+        Anchor::Center => (width - panel_width) / 2,
+        Anchor::Right => width - panel_width,
+    };
+    Some((left, (height - panel_height) / 2, scale))
+}
+
+/// Draw the 4x4 CHIP-8 keypad onto the `width` x `height` RGBA `frame`.
+/// Keys whose bit is set in `highlight` are drawn inverted.
+pub fn draw_keypad(
+    frame: &mut [u8],
+    width: usize,
+    height: usize,
+    anchor: Anchor,
+    highlight: u16,
+    palette: &Palette,
+) {
+    let Some((left, top, scale)) = keypad_rect(width, height, anchor) else {
+        return;
+    };
+    let (background, foreground) = (palette.background(), palette.foreground());
+
+    let mut put = |x: usize, y: usize, color: Rgb| {
+        for py in y * scale..(y + 1) * scale {
+            for px in x * scale..(x + 1) * scale {
+                let i = ((top + py) * width + left + px) * 4;
+                frame[i..i + 4].copy_from_slice(&[color[0], color[1], color[2], 0xFF]);
+            }
+        }
+    };
+
+    for y in 0..PANEL_HEIGHT {
+        for x in 0..PANEL_WIDTH {
+            let border = x == 0 || y == 0 || x == PANEL_WIDTH - 1 || y == PANEL_HEIGHT - 1;
+            put(x, y, if border { foreground } else { background });
+        }
+    }
+
+    for (slot, &key) in KEYPAD_LAYOUT.iter().enumerate() {
+        let (cell_x, cell_y) = (1 + slot % 4 * CELL_WIDTH, 1 + slot / 4 * CELL_HEIGHT);
+        let inverted = highlight & (1 << key) != 0;
+        let (paper, ink) = if inverted {
+            (foreground, background)
+        } else {
+            (background, foreground)
+        };
+        for y in 0..CELL_HEIGHT {
+            for x in 0..CELL_WIDTH {
+                let lit = (1..5).contains(&x)
+                    && (1..6).contains(&y)
+                    && FONTSET[key as usize * 5 + y - 1] & (0x80 >> (x - 1)) != 0;
+                put(cell_x + x, cell_y + y, if lit { ink } else { paper });
+            }
+        }
+    }
+}
+
+// 3x5 glyphs for text, one row of three bits per byte. Lowercase letters
+// are drawn as capitals, anything missing as `?`.
+const GLYPHS: [(char, [u8; 5]); 60] = [
+    ('A', [0b010, 0b101, 0b111, 0b101, 0b101]),
+    ('B', [0b110, 0b101, 0b110, 0b101, 0b110]),
+    ('C', [0b011, 0b100, 0b100, 0b100, 0b011]),
+    ('D', [0b110, 0b101, 0b101, 0b101, 0b110]),
+    ('E', [0b111, 0b100, 0b110, 0b100, 0b111]),
+    ('F', [0b111, 0b100, 0b110, 0b100, 0b100]),
+    ('G', [0b011, 0b100, 0b101, 0b101, 0b011]),
+    ('H', [0b101, 0b101, 0b111, 0b101, 0b101]),
+    ('I', [0b111, 0b010, 0b010, 0b010, 0b111]),
+    ('J', [0b001, 0b001, 0b001, 0b101, 0b010]),
+    ('K', [0b101, 0b101, 0b110, 0b101, 0b101]),
+    ('L', [0b100, 0b100, 0b100, 0b100, 0b111]),
+    ('M', [0b101, 0b111, 0b111, 0b101, 0b101]),
+    ('N', [0b110, 0b101, 0b101, 0b101, 0b101]),
+    ('O', [0b010, 0b101, 0b101, 0b101, 0b010]),
+    ('P', [0b110, 0b101, 0b110, 0b100, 0b100]),
+    ('Q', [0b010, 0b101, 0b101, 0b110, 0b011]),
+    ('R', [0b110, 0b101, 0b110, 0b101, 0b101]),
+    ('S', [0b011, 0b100, 0b010, 0b001, 0b110]),
+    ('T', [0b111, 0b010, 0b010, 0b010, 0b010]),
+    ('U', [0b101, 0b101, 0b101, 0b101, 0b111]),
+    ('V', [0b101, 0b101, 0b101, 0b101, 0b010]),
+    ('W', [0b101, 0b101, 0b111, 0b111, 0b101]),
+    ('X', [0b101, 0b101, 0b010, 0b101, 0b101]),
+    ('Y', [0b101, 0b101, 0b010, 0b010, 0b010]),
+    ('Z', [0b111, 0b001, 0b010, 0b100, 0b111]),
+    ('0', [0b111, 0b101, 0b101, 0b101, 0b111]),
+    ('1', [0b010, 0b110, 0b010, 0b010, 0b111]),
+    ('2', [0b110, 0b001, 0b010, 0b100, 0b111]),
+    ('3', [0b110, 0b001, 0b010, 0b001, 0b110]),
+    ('4', [0b101, 0b101, 0b111, 0b001, 0b001]),
+    ('5', [0b111, 0b100, 0b110, 0b001, 0b110]),
+    ('6', [0b011, 0b100, 0b110, 0b101, 0b010]),
+    ('7', [0b111, 0b001, 0b010, 0b010, 0b010]),
+    ('8', [0b010, 0b101, 0b010, 0b101, 0b010]),
+    ('9', [0b010, 0b101, 0b011, 0b001, 0b110]),
+    (' ', [0b000, 0b000, 0b000, 0b000, 0b000]),
+    ('.', [0b000, 0b000, 0b000, 0b000, 0b010]),
+    (',', [0b000, 0b000, 0b000, 0b010, 0b100]),
+    (':', [0b000, 0b010, 0b000, 0b010, 0b000]),
+    (';', [0b000, 0b010, 0b000, 0b010, 0b100]),
+    ('-', [0b000, 0b000, 0b111, 0b000, 0b000]),
+    ('_', [0b000, 0b000, 0b000, 0b000, 0b111]),
+    ('/', [0b001, 0b001, 0b010, 0b100, 0b100]),
+    ('(', [0b001, 0b010, 0b010, 0b010, 0b001]),
+    (')', [0b100, 0b010, 0b010, 0b010, 0b100]),
+    ('!', [0b010, 0b010, 0b010, 0b000, 0b010]),
+    ('?', [0b110, 0b001, 0b010, 0b000, 0b010]),
+    ('\'', [0b010, 0b010, 0b000, 0b000, 0b000]),
+    ('"', [0b101, 0b101, 0b000, 0b000, 0b000]),
+    ('+', [0b000, 0b010, 0b111, 0b010, 0b000]),
+    ('=', [0b000, 0b111, 0b000, 0b111, 0b000]),
+    ('*', [0b000, 0b101, 0b010, 0b101, 0b000]),
+    ('#', [0b101, 0b111, 0b101, 0b111, 0b101]),
+    ('&', [0b010, 0b101, 0b010, 0b101, 0b011]),
+    ('<', [0b001, 0b010, 0b100, 0b010, 0b001]),
+    ('>', [0b100, 0b010, 0b001, 0b010, 0b100]),
+    ('[', [0b011, 0b010, 0b010, 0b010, 0b011]),
+    (']', [0b110, 0b010, 0b010, 0b010, 0b110]),
+    ('%', [0b101, 0b001, 0b010, 0b100, 0b101]),
+];
+
+/// Text is drawn in cells this many pixels wide and high.
+pub const TEXT_CELL_WIDTH: usize = 4;
+pub const TEXT_CELL_HEIGHT: usize = 6;
+
+fn glyph(c: char) -> [u8; 5] {
+    let c = c.to_ascii_uppercase();
+    let find = |c| GLYPHS.iter().find(|(g, _)| *g == c).map(|(_, rows)| *rows);
+    find(c).or_else(|| find('?')).unwrap()
+}
+
+/// Draw `text` onto the `width` pixel wide RGBA `frame` with its top left
+/// at `x`, `y`, cutting it off at the right edge.
+pub fn draw_text(frame: &mut [u8], width: usize, x: usize, y: usize, text: &str, color: Rgb) {
+    let height = frame.len() / 4 / width;
+    for (i, c) in text.chars().enumerate() {
+        let left = x + i * TEXT_CELL_WIDTH;
+        if left + 3 > width {
+            break;
+        }
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for col in 0..3 {
+                if bits & (0b100 >> col) != 0 && y + row < height {
+                    let at = ((y + row) * width + left + col) * 4;
+                    frame[at..at + 4].copy_from_slice(&[color[0], color[1], color[2], 0xFF]);
+                }
+            }
+        }
+    }
+}
+
+/// The CHIP-8 key drawn by `draw_keypad` at frame pixel `x`, `y`.
+pub fn keypad_key_at(
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+    anchor: Anchor,
+) -> Option<u8> {
+    let (left, top, scale) = keypad_rect(width, height, anchor)?;
+    let col = (x.checked_sub(left)? / scale).checked_sub(1)? / CELL_WIDTH;
+    let row = (y.checked_sub(top)? / scale).checked_sub(1)? / CELL_HEIGHT;
+    if col >= 4 || row >= 4 {
+        return None;
+    }
+    Some(KEYPAD_LAYOUT[row * 4 + col])
+}
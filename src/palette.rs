@@ -0,0 +1,197 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+pub type Rgb = [u8; 3];
+
+// (name, [background, plane 1, plane 2, both planes])
+const BUILTIN: [(&str, [Rgb; 4]); 6] = [
+    (
+        "classic",
+        [
+            [0x00, 0x00, 0x00],
+            [0xFF, 0xFF, 0xFF],
+            [0xAA, 0xAA, 0xAA],
+            [0x55, 0x55, 0x55],
+        ],
+    ),
+    (
+        "green",
+        [
+            [0x0A, 0x1A, 0x0A],
+            [0x33, 0xFF, 0x66],
+            [0x1F, 0x9E, 0x40],
+            [0x99, 0xFF, 0xB3],
+        ],
+    ),
+    (
+        "amber",
+        [
+            [0x1A, 0x0F, 0x00],
+            [0xFF, 0xB0, 0x00],
+            [0xB3, 0x7A, 0x00],
+            [0xFF, 0xD8, 0x80],
+        ],
+    ),
+    (
+        "gameboy",
+        [
+            [0x9B, 0xBC, 0x0F],
+            [0x0F, 0x38, 0x0F],
+            [0x8B, 0xAC, 0x0F],
+            [0x30, 0x62, 0x30],
+        ],
+    ),
+    (
+        "high-contrast",
+        [
+            [0x00, 0x00, 0x00],
+            [0xFF, 0xFF, 0x00],
+            [0x00, 0xFF, 0xFF],
+            [0xFF, 0xFF, 0xFF],
+        ],
+    ),
+    // Okabe-Ito colours, distinguishable with the common forms of colour blindness
+    (
+        "colorblind",
+        [
+            [0x00, 0x00, 0x00],
+            [0xE6, 0x9F, 0x00],
+            [0x56, 0xB4, 0xE9],
+            [0xF0, 0xE4, 0x42],
+        ],
+    ),
+];
+
+/// Display colours indexed by XO-CHIP plane bits: 0 is the background, 1 is
+/// plane 1 (the only plane plain CHIP-8 draws), 2 is plane 2 and 3 is where
+/// both planes overlap.
+#[derive(Clone)]
+pub struct Palette {
+    name: String,
+    colors: [Rgb; 4],
+    // RGBA expansion of 8 packed plane-1 pixels, used by `FrameBuffer::blit_dirty`
+    lut: Box<[[u8; 32]; 256]>,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self::new("classic", BUILTIN[0].1)
+    }
+}
+
+impl Palette {
+    pub fn new(name: &str, colors: [Rgb; 4]) -> Self {
+        let mut lut = Box::new([[0u8; 32]; 256]);
+        for (byte, entry) in lut.iter_mut().enumerate() {
+            for bit in 0..8 {
+                let [r, g, b] = colors[(byte >> (7 - bit)) & 1];
+                entry[bit * 4..bit * 4 + 4].copy_from_slice(&[r, g, b, 0xFF]);
+            }
+        }
+        Self {
+            name: name.to_string(),
+            colors,
+            lut,
+        }
+    }
+
+    pub fn builtin_names() -> impl Iterator<Item = &'static str> {
+        BUILTIN.iter().map(|(name, _)| *name)
+    }
+
+    pub fn builtin(name: &str) -> Option<Self> {
+        BUILTIN
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(n, colors)| Self::new(n, *colors))
+    }
+
+    /// The built-in palette after `self` in `builtin_names` order, wrapping
+    /// around. Custom palettes cycle back to the first built-in.
+    pub fn next_builtin(&self) -> Self {
+        let next = BUILTIN
+            .iter()
+            .position(|(n, _)| *n == self.name)
+            .map_or(0, |i| (i + 1) % BUILTIN.len());
+        Self::new(BUILTIN[next].0, BUILTIN[next].1)
+    }
+
+    /// Load a palette file of `key = value` lines. Keys are `background`,
+    /// `foreground` (alias `plane1`), `plane2` and `blend`; values are
+    /// `#RRGGBB` colours. Missing keys keep the classic colours and lines
+    /// starting with `#` are comments.
+    pub fn load(path: &Path) -> Result<Self, io::Error> {
+        let text = fs::read_to_string(path)?;
+        let name = path
+            .file_stem()
+            .map_or("custom".into(), |s| s.to_string_lossy());
+        let mut palette = Self::default();
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| invalid(format!("expected key = value: {}", line)))?;
+            let color = parse_color(value.trim())
+                .ok_or_else(|| invalid(format!("invalid colour: {}", value.trim())))?;
+            palette = palette
+                .with_color(key.trim(), color)
+                .ok_or_else(|| invalid(format!("unknown palette key: {}", key.trim())))?;
+        }
+        palette.name = name.into_owned();
+        Ok(palette)
+    }
+
+    /// Replace one colour by key name (`background`, `foreground`/`plane1`,
+    /// `plane2`, `blend`). Returns `None` for an unknown key.
+    pub fn with_color(&self, key: &str, color: Rgb) -> Option<Self> {
+        let index = match key {
+            "background" | "bg" => 0,
+            "foreground" | "fg" | "plane1" => 1,
+            "plane2" => 2,
+            "blend" | "plane3" => 3,
+            _ => return None,
+        };
+        let mut colors = self.colors;
+        colors[index] = color;
+        Some(Self::new(&self.name, colors))
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn color(&self, index: usize) -> Rgb {
+        self.colors[index & 0x3]
+    }
+
+    pub fn background(&self) -> Rgb {
+        self.colors[0]
+    }
+
+    pub fn foreground(&self) -> Rgb {
+        self.colors[1]
+    }
+
+    /// RGBA for the 8 pixels of a packed byte, MSB first.
+    pub fn expand_byte(&self, byte: u8) -> &[u8; 32] {
+        &self.lut[byte as usize]
+    }
+}
+
+/// Parse `#RRGGBB`, `RRGGBB` or `0xRRGGBB`.
+pub fn parse_color(text: &str) -> Option<Rgb> {
+    let hex = text
+        .strip_prefix('#')
+        .or_else(|| text.strip_prefix("0x"))
+        .unwrap_or(text);
+    if hex.len() != 6 {
+        return None;
+    }
+    let value = u32::from_str_radix(hex, 16).ok()?;
+    Some([(value >> 16) as u8, (value >> 8) as u8, value as u8])
+}
@@ -0,0 +1,359 @@
+// IPS and BPS patches, applied to a ROM image before it is loaded. Several
+// patches can be stacked as long as they don't change the same bytes.
+
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+pub const EXTENSIONS: [&str; 2] = ["ips", "bps"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PatchFormat {
+    Ips,
+    Bps,
+}
+
+#[derive(Debug, Clone)]
+pub struct Patch {
+    pub name: String,
+    pub format: PatchFormat,
+    data: Vec<u8>,
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+impl Patch {
+    pub fn load(path: &Path) -> Result<Self, io::Error> {
+        Self::parse(&path.display().to_string(), fs::read(path)?)
+    }
+
+    /// Check the header of `data` to see which format it is in.
+    pub fn parse(name: &str, data: Vec<u8>) -> Result<Self, io::Error> {
+        let format = if data.starts_with(b"PATCH") {
+            PatchFormat::Ips
+        } else if data.starts_with(b"BPS1") {
+            PatchFormat::Bps
+        } else {
+            return Err(invalid(format!("{}: not an IPS or BPS patch", name)));
+        };
+        Ok(Self {
+            name: name.to_string(),
+            format,
+            data,
+        })
+    }
+
+    /// The patched copy of `rom`.
+    pub fn apply(&self, rom: &[u8]) -> Result<Vec<u8>, io::Error> {
+        let result = match self.format {
+            PatchFormat::Ips => apply_ips(&self.data, rom),
+            PatchFormat::Bps => apply_bps(&self.data, rom),
+        };
+        result.map_err(|e| invalid(format!("{}: {}", self.name, e)))
+    }
+}
+
+// `PATCH`, records of a 24-bit offset, 16-bit size and the bytes (or an
+// RLE run when the size is 0), `EOF`, then optionally a 24-bit size to
+// truncate to.
+fn apply_ips(patch: &[u8], rom: &[u8]) -> Result<Vec<u8>, String> {
+    let mut out = rom.to_vec();
+    let mut pos = 5;
+    let mut take = |n: usize| -> Result<&[u8], String> {
+        let bytes = patch.get(pos..pos + n).ok_or("truncated patch")?;
+        pos += n;
+        Ok(bytes)
+    };
+    let be = |bytes: &[u8]| bytes.iter().fold(0, |n, &b| n << 8 | b as usize);
+    loop {
+        let record = take(3)?;
+        if record == b"EOF" {
+            break;
+        }
+        let offset = be(record);
+        let size = be(take(2)?);
+        let (bytes, len) = if size == 0 {
+            let run = be(take(2)?);
+            (None, run)
+        } else {
+            (Some(take(size)?.to_vec()), size)
+        };
+        if out.len() < offset + len {
+            out.resize(offset + len, 0);
+        }
+        match bytes {
+            Some(bytes) => out[offset..offset + len].copy_from_slice(&bytes),
+            None => out[offset..offset + len].fill(take(1)?[0]),
+        }
+    }
+    if let Ok(size) = take(3) {
+        out.truncate(be(size));
+    }
+    Ok(out)
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    crc32fast::hash(data)
+}
+
+// BPS numbers: 7 bits a byte, least significant first, with the top bit
+// marking the last byte.
+fn varint(data: &[u8], pos: &mut usize) -> Result<usize, String> {
+    let (mut value, mut shift) = (0usize, 1usize);
+    loop {
+        let byte = *data.get(*pos).ok_or("truncated patch")?;
+        *pos += 1;
+        value += (byte & 0x7F) as usize * shift;
+        if byte & 0x80 != 0 {
+            return Ok(value);
+        }
+        shift <<= 7;
+        value += shift;
+    }
+}
+
+// `BPS1`, source, target and metadata sizes as varints, the metadata, the
+// copy actions, then CRC32s of the source, target and patch.
+fn apply_bps(patch: &[u8], rom: &[u8]) -> Result<Vec<u8>, String> {
+    if patch.len() < 16 {
+        return Err("truncated patch".into());
+    }
+    let (body, footer) = patch.split_at(patch.len() - 12);
+    let word = |i: usize| u32::from_le_bytes(footer[i..i + 4].try_into().unwrap());
+    if crc32(&patch[..patch.len() - 4]) != word(8) {
+        return Err("patch checksum mismatch, the file is damaged".into());
+    }
+    if crc32(rom) != word(0) {
+        return Err("made for a different ROM (source checksum mismatch)".into());
+    }
+
+    let mut pos = 4;
+    let source_size = varint(body, &mut pos)?;
+    let target_size = varint(body, &mut pos)?;
+    let metadata_size = varint(body, &mut pos)?;
+    if source_size != rom.len() {
+        return Err("made for a different ROM (size mismatch)".into());
+    }
+    pos += metadata_size;
+
+    let mut out: Vec<u8> = Vec::with_capacity(target_size);
+    let (mut source_offset, mut target_offset) = (0isize, 0isize);
+    let relative = |offset: &mut isize, data: usize| {
+        let delta = (data >> 1) as isize;
+        *offset += if data & 1 != 0 { -delta } else { delta };
+    };
+    while pos < body.len() {
+        let action = varint(body, &mut pos)?;
+        let len = (action >> 2) + 1;
+        let bad = || "copies from outside the ROM".to_string();
+        match action & 3 {
+            0 => {
+                let at = out.len();
+                out.extend_from_slice(rom.get(at..at + len).ok_or_else(bad)?);
+            }
+            1 => {
+                out.extend_from_slice(body.get(pos..pos + len).ok_or("truncated patch")?);
+                pos += len;
+            }
+            2 => {
+                relative(&mut source_offset, varint(body, &mut pos)?);
+                let at = usize::try_from(source_offset).map_err(|_| bad())?;
+                out.extend_from_slice(rom.get(at..at + len).ok_or_else(bad)?);
+                source_offset += len as isize;
+            }
+            _ => {
+                relative(&mut target_offset, varint(body, &mut pos)?);
+                // may overlap what it writes, so copy byte by byte
+                for _ in 0..len {
+                    let at = usize::try_from(target_offset).map_err(|_| bad())?;
+                    let byte = *out.get(at).ok_or_else(bad)?;
+                    out.push(byte);
+                    target_offset += 1;
+                }
+            }
+        }
+    }
+    if out.len() != target_size || crc32(&out) != word(4) {
+        return Err("result checksum mismatch".into());
+    }
+    Ok(out)
+}
+
+// Byte ranges that differ between `before` and `after`, including bytes
+// added or cut off at the end.
+fn changed(before: &[u8], after: &[u8]) -> Vec<Range<usize>> {
+    let mut ranges: Vec<Range<usize>> = Vec::new();
+    for i in 0..before.len().max(after.len()) {
+        if before.get(i) != after.get(i) {
+            match ranges.last_mut() {
+                Some(range) if range.end == i => range.end = i + 1,
+                _ => ranges.push(i..i + 1),
+            }
+        }
+    }
+    ranges
+}
+
+/// Apply `patches` to `rom` in order. Fails if two of them change the same
+/// bytes, naming both and the first address they collide at.
+pub fn apply_all(rom: &[u8], patches: &[Patch]) -> Result<Vec<u8>, io::Error> {
+    let mut data = rom.to_vec();
+    let mut touched: Vec<(Range<usize>, &str)> = Vec::new();
+    for patch in patches {
+        let patched = patch.apply(&data)?;
+        for range in changed(&data, &patched) {
+            if let Some((other, name)) = touched
+                .iter()
+                .find(|(other, _)| other.start < range.end && range.start < other.end)
+            {
+                return Err(invalid(format!(
+                    "{} and {} both change the ROM at {:#05x}",
+                    name,
+                    patch.name,
+                    0x200 + range.start.max(other.start)
+                )));
+            }
+            touched.push((range, &patch.name));
+        }
+        data = patched;
+    }
+    Ok(data)
+}
+
+/// Patches next to `rom_path` with the same name, e.g. `game.ips` and
+/// `game.bps` for `game.ch8`.
+pub fn discover(rom_path: &Path) -> Vec<PathBuf> {
+    EXTENSIONS
+        .iter()
+        .map(|ext| rom_path.with_extension(ext))
+        .filter(|path| path.is_file())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ips(records: &[u8], truncate: Option<usize>) -> Patch {
+        let mut data = b"PATCH".to_vec();
+        data.extend_from_slice(records);
+        data.extend_from_slice(b"EOF");
+        if let Some(size) = truncate {
+            data.extend_from_slice(&(size as u32).to_be_bytes()[1..]);
+        }
+        Patch::parse("test.ips", data).unwrap()
+    }
+
+    fn encode(mut n: usize) -> Vec<u8> {
+        let mut out = Vec::new();
+        loop {
+            let byte = (n & 0x7F) as u8;
+            n >>= 7;
+            if n == 0 {
+                out.push(byte | 0x80);
+                return out;
+            }
+            out.push(byte);
+            n -= 1;
+        }
+    }
+
+    fn bps(source: &[u8], target: &[u8], actions: &[u8]) -> Patch {
+        let mut data = b"BPS1".to_vec();
+        data.extend(encode(source.len()));
+        data.extend(encode(target.len()));
+        data.extend(encode(0));
+        data.extend_from_slice(actions);
+        data.extend(crc32(source).to_le_bytes());
+        data.extend(crc32(target).to_le_bytes());
+        data.extend(crc32(&data).to_le_bytes());
+        Patch::parse("test.bps", data).unwrap()
+    }
+
+    #[test]
+    fn ips_writes_records_and_runs() {
+        // 2 bytes at 1, then a run of 3 0xEE at 6 past the end
+        let patch = ips(
+            &[0, 0, 1, 0, 2, 0xAA, 0xBB, 0, 0, 6, 0, 0, 0, 3, 0xEE],
+            None,
+        );
+        assert_eq!(patch.format, PatchFormat::Ips);
+        assert_eq!(
+            patch.apply(&[1, 2, 3, 4]).unwrap(),
+            [1, 0xAA, 0xBB, 4, 0, 0, 0xEE, 0xEE, 0xEE]
+        );
+    }
+
+    #[test]
+    fn ips_truncates_to_the_size_after_eof() {
+        let patch = ips(&[0, 0, 0, 0, 1, 9], Some(2));
+        assert_eq!(patch.apply(&[1, 2, 3, 4]).unwrap(), [9, 2]);
+    }
+
+    #[test]
+    fn truncated_ips_fails() {
+        let patch = Patch::parse("test.ips", b"PATCH\0\0\0\0\x05AB".to_vec()).unwrap();
+        let error = patch.apply(&[0; 8]).unwrap_err();
+        assert_eq!(error.to_string(), "test.ips: truncated patch");
+    }
+
+    #[test]
+    fn unknown_formats_are_refused() {
+        assert!(Patch::parse("test.txt", b"hello".to_vec()).is_err());
+    }
+
+    #[test]
+    fn bps_runs_every_action() {
+        let source = b"ABCDEFGH";
+        let target = b"ABxyFGHABxy";
+        let actions = [
+            0x84, // read 2 from the source
+            0x85, b'x', b'y', // 2 from the patch
+            0x8A, 0x8A, // copy 3 from source offset +5
+            0x8F, 0x80, // copy 4 from the start of the target
+        ];
+        let patch = bps(source, target, &actions);
+        assert_eq!(patch.format, PatchFormat::Bps);
+        assert_eq!(patch.apply(source).unwrap(), target);
+    }
+
+    #[test]
+    fn bps_for_another_rom_fails() {
+        let patch = bps(b"AB", b"AB", &[0x84]);
+        let error = patch.apply(b"CD").unwrap_err();
+        assert!(error.to_string().contains("different ROM"), "{}", error);
+    }
+
+    #[test]
+    fn damaged_bps_fails() {
+        let patch = bps(b"AB", b"AB", &[0x84]);
+        let mut data = patch.data.clone();
+        data[5] ^= 1;
+        let error = Patch::parse("test.bps", data)
+            .unwrap()
+            .apply(b"AB")
+            .unwrap_err();
+        assert!(error.to_string().contains("damaged"), "{}", error);
+    }
+
+    #[test]
+    fn stacked_patches_apply_in_order() {
+        let first = ips(&[0, 0, 0, 0, 1, 7], None);
+        let second = ips(&[0, 0, 2, 0, 1, 8], None);
+        assert_eq!(apply_all(&[0; 4], &[first, second]).unwrap(), [7, 0, 8, 0]);
+    }
+
+    #[test]
+    fn overlapping_patches_are_refused() {
+        let first = ips(&[0, 0, 1, 0, 2, 7, 7], None);
+        let second = ips(&[0, 0, 2, 0, 1, 8], None);
+        let error = apply_all(&[0; 4], &[first, second]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "test.ips and test.ips both change the ROM at 0x202"
+        );
+    }
+}
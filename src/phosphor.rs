@@ -0,0 +1,203 @@
+use crate::framebuffer::{FrameBuffer, HIRES_WIDTH};
+use crate::palette::Palette;
+
+pub const DEFAULT_FADE_FRAMES: u8 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PhosphorMode {
+    Off,
+    Fade,  // lit pixels decay to the background over `fade_frames`
+    Blend, // a pixel shows lit if it was lit in this or the previous frame
+}
+
+impl PhosphorMode {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "off" | "none" => Some(PhosphorMode::Off),
+            "fade" => Some(PhosphorMode::Fade),
+            "blend" | "or" => Some(PhosphorMode::Blend),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            PhosphorMode::Off => "off",
+            PhosphorMode::Fade => "fade",
+            PhosphorMode::Blend => "blend",
+        }
+    }
+
+    pub fn next(&self) -> Self {
+        match self {
+            PhosphorMode::Off => PhosphorMode::Fade,
+            PhosphorMode::Fade => PhosphorMode::Blend,
+            PhosphorMode::Blend => PhosphorMode::Off,
+        }
+    }
+}
+
+/// Anti-flicker filter applied between the frame buffer and the RGBA frame.
+/// XOR-drawn sprites are erased and redrawn on consecutive frames, so
+/// keeping some memory of recently lit pixels hides the flicker.
+pub struct PhosphorFilter {
+    mode: PhosphorMode,
+    fade_frames: u8,
+    width: usize,
+    height: usize,
+    intensity: Vec<u8>,  // per pixel, 0 = background, 255 = foreground
+    previous: Vec<u128>, // rows of the frame before the latest
+    latest: Vec<u128>,   // rows as last seen
+}
+
+impl PhosphorFilter {
+    pub fn new(mode: PhosphorMode, fade_frames: u8) -> Self {
+        Self {
+            mode,
+            fade_frames: fade_frames.max(1),
+            width: 0,
+            height: 0,
+            intensity: Vec::new(),
+            previous: Vec::new(),
+            latest: Vec::new(),
+        }
+    }
+
+    pub fn mode(&self) -> PhosphorMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: PhosphorMode) {
+        self.mode = mode;
+        // forget history so switching modes never shows stale pixels
+        self.width = 0;
+    }
+
+    /// Present `fb` into `frame`. With the filter off only dirty rows are
+    /// written; otherwise the whole frame is recomputed every call.
+    /// `frames` is how many emulated frames ran since the last call; the
+    /// filter only moves on by that many, however often it is redrawn.
+    pub fn apply(
+        &mut self,
+        fb: &mut FrameBuffer,
+        frame: &mut [u8],
+        palette: &Palette,
+        frames: u64,
+    ) {
+        if self.mode == PhosphorMode::Off {
+            fb.blit_dirty(frame, palette);
+            return;
+        }
+
+        let (width, height) = (fb.width(), fb.height());
+        if self.width != width || self.height != height {
+            self.width = width;
+            self.height = height;
+            self.intensity = vec![0; width * height];
+            self.previous = vec![0; height];
+            self.latest = vec![0; height];
+        }
+        // every row is redrawn below, so the next unfiltered frame must be too
+        fb.mark_all_dirty();
+
+        match self.mode {
+            PhosphorMode::Off => unreachable!(),
+            PhosphorMode::Fade => self.fade(fb, frame, palette, frames),
+            PhosphorMode::Blend => self.blend(fb, frame, palette, frames),
+        }
+    }
+
+    fn fade(&mut self, fb: &FrameBuffer, frame: &mut [u8], palette: &Palette, frames: u64) {
+        let step = 255u8.div_ceil(self.fade_frames);
+        let step = (step as u64 * frames).min(255) as u8;
+        let background = palette.background();
+        let foreground = palette.foreground();
+        let mix = |c: usize, level: u8| {
+            let (from, to) = (background[c] as i32, foreground[c] as i32);
+            (from + (to - from) * level as i32 / 255) as u8
+        };
+
+        for y in 0..self.height {
+            let bits = fb.row_bits(y);
+            for x in 0..self.width {
+                let idx = y * self.width + x;
+                let level = &mut self.intensity[idx];
+                if (bits >> (self.width - 1 - x)) & 1 == 1 {
+                    *level = 255;
+                } else {
+                    *level = level.saturating_sub(step);
+                }
+                let level = *level;
+                frame[idx * 4..idx * 4 + 4].copy_from_slice(&[
+                    mix(0, level),
+                    mix(1, level),
+                    mix(2, level),
+                    0xFF,
+                ]);
+            }
+        }
+    }
+
+    fn blend(&mut self, fb: &FrameBuffer, frame: &mut [u8], palette: &Palette, frames: u64) {
+        let width = self.width;
+        if frames > 0 {
+            self.previous.copy_from_slice(&self.latest);
+        }
+        for y in 0..self.height {
+            let bits = fb.row_bits(y);
+            let shown = bits | self.previous[y];
+            self.latest[y] = bits;
+
+            let bytes = (shown << (HIRES_WIDTH - width)).to_be_bytes();
+            let line = &mut frame[y * width * 4..(y + 1) * width * 4];
+            for (byte, out) in bytes.iter().zip(line.chunks_exact_mut(32)) {
+                out.copy_from_slice(palette.expand_byte(*byte));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redraws_between_frames_do_not_fade() {
+        let palette = Palette::default();
+        let mut fb = FrameBuffer::new();
+        let mut frame = vec![0; fb.width() * fb.height() * 4];
+        let mut filter = PhosphorFilter::new(PhosphorMode::Fade, 4);
+        fb.draw_sprite(0, 0, &[0x80], false);
+        filter.apply(&mut fb, &mut frame, &palette, 1);
+        fb.draw_sprite(0, 0, &[0x80], false);
+
+        filter.apply(&mut fb, &mut frame, &palette, 1);
+        let faded = filter.intensity[0];
+        assert!(faded > 0 && faded < 255);
+        for _ in 0..10 {
+            filter.apply(&mut fb, &mut frame, &palette, 0);
+        }
+        assert_eq!(filter.intensity[0], faded);
+        filter.apply(&mut fb, &mut frame, &palette, 4);
+        assert_eq!(filter.intensity[0], 0);
+    }
+
+    #[test]
+    fn blend_keeps_the_previous_frame_across_redraws() {
+        let palette = Palette::default();
+        let mut fb = FrameBuffer::new();
+        let mut frame = vec![0; fb.width() * fb.height() * 4];
+        let mut filter = PhosphorFilter::new(PhosphorMode::Blend, 4);
+        fb.draw_sprite(0, 0, &[0x80], false);
+        filter.apply(&mut fb, &mut frame, &palette, 1);
+        fb.draw_sprite(0, 0, &[0x80], false);
+
+        filter.apply(&mut fb, &mut frame, &palette, 1);
+        for _ in 0..3 {
+            assert_eq!(frame[..3], palette.foreground()[..]);
+            filter.apply(&mut fb, &mut frame, &palette, 0);
+        }
+        filter.apply(&mut fb, &mut frame, &palette, 1);
+        assert_eq!(frame[..3], palette.background()[..]);
+    }
+}
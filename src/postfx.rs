@@ -0,0 +1,247 @@
+// CPU post-processing of the presented RGBA frame. Everything here works on
+// plain byte slices so the same code feeds the window, screenshots and video
+// export without needing a GPU.
+
+pub const DEFAULT_SCALE: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PostFilter {
+    None,      // nearest-neighbour upscale
+    Scanlines, // darkened gap at the bottom of every pixel row
+    Grid,      // LCD-style dark line between pixels
+    Bloom,     // blurred glow added around bright pixels
+    Rounded,   // pixels drawn as rounded dots
+    Smooth,    // Scale2x edge smoothing
+}
+
+const ALL: [PostFilter; 6] = [
+    PostFilter::None,
+    PostFilter::Scanlines,
+    PostFilter::Grid,
+    PostFilter::Bloom,
+    PostFilter::Rounded,
+    PostFilter::Smooth,
+];
+
+impl PostFilter {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "none" | "off" => Some(PostFilter::None),
+            "scanlines" => Some(PostFilter::Scanlines),
+            "grid" => Some(PostFilter::Grid),
+            "bloom" | "glow" => Some(PostFilter::Bloom),
+            "rounded" => Some(PostFilter::Rounded),
+            "smooth" | "scale2x" => Some(PostFilter::Smooth),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            PostFilter::None => "none",
+            PostFilter::Scanlines => "scanlines",
+            PostFilter::Grid => "grid",
+            PostFilter::Bloom => "bloom",
+            PostFilter::Rounded => "rounded",
+            PostFilter::Smooth => "smooth",
+        }
+    }
+
+    pub fn next(&self) -> Self {
+        let i = ALL.iter().position(|f| f == self).unwrap_or(0);
+        ALL[(i + 1) % ALL.len()]
+    }
+
+    /// Upscale the `width` x `height` RGBA image `src` by `scale` into `dst`,
+    /// which must hold `width * scale` x `height * scale` RGBA pixels.
+    pub fn apply(&self, src: &[u8], width: usize, height: usize, scale: usize, dst: &mut [u8]) {
+        let scale = scale.max(1);
+        assert_eq!(dst.len(), width * scale * height * scale * 4);
+        match self {
+            PostFilter::None => nearest(src, width, height, scale, dst),
+            PostFilter::Scanlines => {
+                nearest(src, width, height, scale, dst);
+                // bottom third of each source row, at least one line
+                let gap = (scale / 3).max(1);
+                shade_blocks(dst, width * scale, scale, |_, sy| sy >= scale - gap, 100);
+            }
+            PostFilter::Grid => {
+                nearest(src, width, height, scale, dst);
+                shade_blocks(
+                    dst,
+                    width * scale,
+                    scale,
+                    |sx, sy| sx == scale - 1 || sy == scale - 1,
+                    150,
+                );
+            }
+            PostFilter::Bloom => {
+                nearest(src, width, height, scale, dst);
+                bloom(dst, width * scale, height * scale, scale);
+            }
+            PostFilter::Rounded => {
+                nearest(src, width, height, scale, dst);
+                let r = scale as f32 / 2.0;
+                shade_blocks(
+                    dst,
+                    width * scale,
+                    scale,
+                    |sx, sy| {
+                        let (dx, dy) = (sx as f32 + 0.5 - r, sy as f32 + 0.5 - r);
+                        dx * dx + dy * dy > r * r * 1.1
+                    },
+                    90,
+                );
+            }
+            PostFilter::Smooth => smooth(src, width, height, scale, dst),
+        }
+    }
+}
+
+/// Nearest-neighbour resize of an RGBA image to any size.
+pub fn resize_nearest(
+    src: &[u8],
+    width: usize,
+    height: usize,
+    out_width: usize,
+    out_height: usize,
+) -> Vec<u8> {
+    let mut dst = Vec::with_capacity(out_width * out_height * 4);
+    for y in 0..out_height {
+        let row = y * height / out_height * width;
+        for x in 0..out_width {
+            let i = (row + x * width / out_width) * 4;
+            dst.extend_from_slice(&src[i..i + 4]);
+        }
+    }
+    dst
+}
+
+fn nearest(src: &[u8], width: usize, height: usize, scale: usize, dst: &mut [u8]) {
+    let out_width = width * scale;
+    for y in 0..height {
+        let out_row = &mut dst[y * scale * out_width * 4..(y * scale + 1) * out_width * 4];
+        for x in 0..width {
+            let px = &src[(y * width + x) * 4..(y * width + x) * 4 + 4];
+            for out in out_row[x * scale * 4..(x + 1) * scale * 4].chunks_exact_mut(4) {
+                out.copy_from_slice(px);
+            }
+        }
+        for copy in 1..scale {
+            let (done, rest) = dst.split_at_mut((y * scale + copy) * out_width * 4);
+            let first = &done[y * scale * out_width * 4..(y * scale + 1) * out_width * 4];
+            rest[..out_width * 4].copy_from_slice(first);
+        }
+    }
+}
+
+// Scale the colour of every output pixel whose offset inside its source
+// block matches `mask` by `level / 255`.
+fn shade_blocks(
+    dst: &mut [u8],
+    out_width: usize,
+    scale: usize,
+    mask: impl Fn(usize, usize) -> bool,
+    level: u16,
+) {
+    for (i, px) in dst.chunks_exact_mut(4).enumerate() {
+        let (x, y) = (i % out_width, i / out_width);
+        if mask(x % scale, y % scale) {
+            for c in &mut px[..3] {
+                *c = (*c as u16 * level / 255) as u8;
+            }
+        }
+    }
+}
+
+// Add a box-blurred copy of the image on top of itself.
+fn bloom(img: &mut [u8], width: usize, height: usize, radius: usize) {
+    let mut blurred = img.to_vec();
+    box_blur(&mut blurred, width, height, radius);
+    for (px, glow) in img.chunks_exact_mut(4).zip(blurred.chunks_exact(4)) {
+        for c in 0..3 {
+            px[c] = px[c].saturating_add((glow[c] as u16 * 3 / 5) as u8);
+        }
+    }
+}
+
+// Separable box blur with a sliding window sum.
+fn box_blur(img: &mut [u8], width: usize, height: usize, radius: usize) {
+    let mut line = Vec::new();
+    let passes = [(width, height, 1, width), (height, width, width, 1)];
+    for (len, lines, step, stride) in passes {
+        for l in 0..lines {
+            let base = l * stride;
+            line.clear();
+            line.extend((0..len).map(|i| {
+                let p = (base + i * step) * 4;
+                [img[p] as u32, img[p + 1] as u32, img[p + 2] as u32]
+            }));
+            let window = (2 * radius + 1) as u32;
+            let mut sum = [0u32; 3];
+            for px in &line[..=radius.min(len - 1)] {
+                (0..3).for_each(|c| sum[c] += px[c]);
+            }
+            for i in 0..len {
+                let p = (base + i * step) * 4;
+                for c in 0..3 {
+                    img[p + c] = (sum[c] / window) as u8;
+                }
+                if i + radius + 1 < len {
+                    (0..3).for_each(|c| sum[c] += line[i + radius + 1][c]);
+                }
+                if i >= radius {
+                    (0..3).for_each(|c| sum[c] -= line[i - radius][c]);
+                }
+            }
+        }
+    }
+}
+
+// Scale2x as many times as divides `scale`, then nearest for the rest.
+fn smooth(src: &[u8], width: usize, height: usize, scale: usize, dst: &mut [u8]) {
+    let mut img = src.to_vec();
+    let (mut w, mut h, mut remaining) = (width, height, scale);
+    while remaining % 2 == 0 {
+        img = scale2x(&img, w, h);
+        w *= 2;
+        h *= 2;
+        remaining /= 2;
+    }
+    nearest(&img, w, h, remaining, dst);
+}
+
+fn scale2x(src: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let px = |x: usize, y: usize| -> [u8; 4] {
+        let i = (y * width + x) * 4;
+        [src[i], src[i + 1], src[i + 2], src[i + 3]]
+    };
+    let mut dst = vec![0u8; width * height * 16];
+    let out_width = width * 2;
+    for y in 0..height {
+        for x in 0..width {
+            let e = px(x, y);
+            let b = px(x, y.saturating_sub(1));
+            let h = px(x, (y + 1).min(height - 1));
+            let d = px(x.saturating_sub(1), y);
+            let f = px((x + 1).min(width - 1), y);
+
+            let (e0, e1, e2, e3) = if b != h && d != f {
+                (
+                    if d == b { d } else { e },
+                    if b == f { f } else { e },
+                    if d == h { d } else { e },
+                    if h == f { f } else { e },
+                )
+            } else {
+                (e, e, e, e)
+            };
+            let (ox, oy) = (x * 2, y * 2);
+            for (dx, dy, c) in [(0, 0, e0), (1, 0, e1), (0, 1, e2), (1, 1, e3)] {
+                let i = ((oy + dy) * out_width + ox + dx) * 4;
+                dst[i..i + 4].copy_from_slice(&c);
+            }
+        }
+    }
+    dst
+}
@@ -0,0 +1,126 @@
+use std::fmt;
+
+/// Behaviours that differ between CHIP-8 interpreters. `Default` is what
+/// this emulator has always done.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    pub vf_reset: bool, // 8xy1/8xy2/8xy3 clear VF
+    pub memory: bool,   // Fx55/Fx65 leave I pointing past the last register
+    pub shifting: bool, // 8xy6/8xyE shift Vx in place instead of Vy into Vx
+    pub jumping: bool,  // Bnnn jumps to nnn + Vx (x = top nibble of nnn) instead of V0
+    pub clipping: bool, // sprites are cut off at the screen edge instead of wrapping
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Self {
+            vf_reset: false,
+            memory: true,
+            shifting: true,
+            jumping: false,
+            clipping: false,
+        }
+    }
+}
+
+const NAMES: [&str; 5] = ["vf_reset", "memory", "shifting", "jumping", "clipping"];
+
+impl Quirks {
+    /// COSMAC VIP CHIP-8.
+    pub const CHIP8: Quirks = Quirks {
+        vf_reset: true,
+        memory: true,
+        shifting: false,
+        jumping: false,
+        clipping: true,
+    };
+
+    /// SUPER-CHIP 1.1 on the HP-48.
+    pub const SCHIP: Quirks = Quirks {
+        vf_reset: false,
+        memory: false,
+        shifting: true,
+        jumping: true,
+        clipping: true,
+    };
+
+    /// Octo's XO-CHIP.
+    pub const XOCHIP: Quirks = Quirks {
+        vf_reset: false,
+        memory: true,
+        shifting: false,
+        jumping: false,
+        clipping: false,
+    };
+
+    pub fn preset(name: &str) -> Option<Self> {
+        match name {
+            "default" => Some(Quirks::default()),
+            "chip8" | "chip-8" | "vip" => Some(Quirks::CHIP8),
+            "schip" | "superchip" => Some(Quirks::SCHIP),
+            "xochip" | "xo-chip" => Some(Quirks::XOCHIP),
+            _ => None,
+        }
+    }
+
+    /// A preset name, `none`, or a comma separated list of quirks to enable,
+    /// e.g. `memory,clipping`.
+    pub fn parse(text: &str) -> Option<Self> {
+        if let Some(preset) = Quirks::preset(text) {
+            return Some(preset);
+        }
+        let mut quirks = Quirks {
+            vf_reset: false,
+            memory: false,
+            shifting: false,
+            jumping: false,
+            clipping: false,
+        };
+        if text == "none" {
+            return Some(quirks);
+        }
+        for name in text.split(',').map(str::trim) {
+            *quirks.flag_mut(name)? = true;
+        }
+        Some(quirks)
+    }
+
+    fn flags(&self) -> [bool; 5] {
+        [
+            self.vf_reset,
+            self.memory,
+            self.shifting,
+            self.jumping,
+            self.clipping,
+        ]
+    }
+
+    fn flag_mut(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "vf_reset" => Some(&mut self.vf_reset),
+            "memory" => Some(&mut self.memory),
+            "shifting" => Some(&mut self.shifting),
+            "jumping" => Some(&mut self.jumping),
+            "clipping" => Some(&mut self.clipping),
+            _ => None,
+        }
+    }
+}
+
+/// The enabled quirks as a comma separated list, or `none`; `parse` reads
+/// it back.
+impl fmt::Display for Quirks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let enabled: Vec<&str> = NAMES
+            .iter()
+            .zip(self.flags())
+            .filter(|(_, on)| *on)
+            .map(|(name, _)| *name)
+            .collect();
+        if enabled.is_empty() {
+            write!(f, "none")
+        } else {
+            write!(f, "{}", enabled.join(","))
+        }
+    }
+}
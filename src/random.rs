@@ -0,0 +1,40 @@
+/// Where `Cxkk` gets its random bytes from. Implementations must give the
+/// same sequence every time they are started from the same seed, otherwise
+/// movies and headless runs can't be repeated.
+pub trait RandomSource: Send {
+    /// Restart the sequence from `seed`.
+    fn reseed(&mut self, seed: u64);
+    fn next_byte(&mut self) -> u8;
+}
+
+/// SplitMix64, the default source. Tiny, fast and fully specified here, so
+/// a seed gives the same bytes on every platform and every build.
+#[derive(Debug, Clone)]
+pub struct SplitMix64 {
+    state: u64,
+}
+
+impl SplitMix64 {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+}
+
+impl RandomSource for SplitMix64 {
+    fn reseed(&mut self, seed: u64) {
+        self.state = seed;
+    }
+
+    fn next_byte(&mut self) -> u8 {
+        // the top bits are the best mixed
+        (self.next_u64() >> 56) as u8
+    }
+}
@@ -0,0 +1,225 @@
+use crate::framebuffer::FrameBuffer;
+use crate::palette::{Palette, Rgb};
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+
+pub const FRAME_RATE: u32 = 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordFormat {
+    Gif,
+    Apng,
+}
+
+impl RecordFormat {
+    /// `.gif` records a GIF, `.png` and `.apng` an animated PNG.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "gif" => Some(RecordFormat::Gif),
+            "png" | "apng" => Some(RecordFormat::Apng),
+            _ => None,
+        }
+    }
+}
+
+// One distinct picture and how many 1/60 s frames it stayed on screen.
+struct Frame {
+    pixels: Vec<u8>, // palette indices at canvas size
+    colors: [Rgb; 4],
+    frames: u32,
+}
+
+/// Records presented frames to an animated GIF or APNG at 60 fps.
+///
+/// Consecutive identical frames are merged into one longer frame, so static
+/// screens cost nothing. GIF frames are streamed to disk as soon as they
+/// are complete; APNG needs the frame count up front, so those frames are
+/// kept in memory until `finish`.
+pub struct Recorder {
+    path: PathBuf,
+    format: RecordFormat,
+    scale: usize,
+    canvas: (usize, usize),
+    last_frame: Option<u64>,
+    pending: Option<Frame>,
+    gif: Option<gif::Encoder<BufWriter<File>>>,
+    gif_centis: u64, // GIF time written so far, in 1/100 s
+    gif_frames: u64, // 1/60 s frames written so far
+    apng: Vec<Frame>,
+    written: usize,
+}
+
+impl Recorder {
+    /// Start a recording at `path`; the format follows the extension. The
+    /// canvas is the first captured frame's size times `scale`; frames at
+    /// another resolution are resampled to fit it.
+    pub fn create(path: &Path, scale: usize) -> Result<Self, io::Error> {
+        let format = RecordFormat::from_path(path).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{}: recordings must end in .gif, .png or .apng",
+                    path.display()
+                ),
+            )
+        })?;
+        // fail now rather than when the first frame arrives
+        File::create(path)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            format,
+            scale: scale.max(1),
+            canvas: (0, 0),
+            last_frame: None,
+            pending: None,
+            gif: None,
+            gif_centis: 0,
+            gif_frames: 0,
+            apng: Vec::new(),
+            written: 0,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Capture the picture shown at `frame_number` (counted in 1/60 s since
+    /// the recording started). Calls with a frame number that was already
+    /// captured are ignored; gaps extend the previous frame.
+    pub fn capture(
+        &mut self,
+        frame_number: u64,
+        fb: &FrameBuffer,
+        palette: &Palette,
+    ) -> Result<(), io::Error> {
+        if self.last_frame.is_some_and(|last| frame_number <= last) {
+            return Ok(());
+        }
+        if let (Some(pending), Some(last)) = (&mut self.pending, self.last_frame) {
+            pending.frames += (frame_number - last - 1) as u32;
+        }
+        self.last_frame = Some(frame_number);
+
+        if self.canvas == (0, 0) {
+            self.canvas = (fb.width() * self.scale, fb.height() * self.scale);
+        }
+        let frame = Frame {
+            pixels: self.resample(fb),
+            colors: [0, 1, 2, 3].map(|i| palette.color(i)),
+            frames: 1,
+        };
+
+        match &mut self.pending {
+            Some(pending) if pending.pixels == frame.pixels && pending.colors == frame.colors => {
+                pending.frames += 1;
+            }
+            _ => {
+                if let Some(done) = self.pending.replace(frame) {
+                    self.emit(done)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Flush the last frame and close the file. Returns the number of
+    /// distinct frames written.
+    pub fn finish(mut self) -> Result<usize, io::Error> {
+        if let Some(done) = self.pending.take() {
+            self.emit(done)?;
+        }
+        if self.written == 0 {
+            fs::remove_file(&self.path)?;
+            return Ok(0);
+        }
+        match self.format {
+            RecordFormat::Gif => {
+                if let Some(encoder) = self.gif.take() {
+                    encoder.into_inner()?;
+                }
+            }
+            RecordFormat::Apng => self.write_apng()?,
+        }
+        Ok(self.written)
+    }
+
+    // nearest-neighbour from the frame buffer to the canvas
+    fn resample(&self, fb: &FrameBuffer) -> Vec<u8> {
+        let (cw, ch) = self.canvas;
+        let (w, h) = (fb.width(), fb.height());
+        let mut pixels = Vec::with_capacity(cw * ch);
+        for cy in 0..ch {
+            let bits = fb.row_bits(cy * h / ch);
+            pixels.extend((0..cw).map(|cx| ((bits >> (w - 1 - cx * w / cw)) & 1) as u8));
+        }
+        pixels
+    }
+
+    fn emit(&mut self, frame: Frame) -> Result<(), io::Error> {
+        self.written += 1;
+        match self.format {
+            RecordFormat::Gif => self.write_gif_frame(frame),
+            RecordFormat::Apng => {
+                self.apng.push(frame);
+                Ok(())
+            }
+        }
+    }
+
+    fn write_gif_frame(&mut self, frame: Frame) -> Result<(), io::Error> {
+        let (width, height) = (self.canvas.0 as u16, self.canvas.1 as u16);
+        if self.gif.is_none() {
+            let file = BufWriter::new(File::create(&self.path)?);
+            let mut encoder =
+                gif::Encoder::new(file, width, height, &[]).map_err(io::Error::other)?;
+            encoder
+                .set_repeat(gif::Repeat::Infinite)
+                .map_err(io::Error::other)?;
+            self.gif = Some(encoder);
+        }
+
+        // GIF delays are in 1/100 s; carry the rounding so 60 fps stays in sync
+        self.gif_frames += frame.frames as u64;
+        let target = (self.gif_frames * 100 + FRAME_RATE as u64 / 2) / FRAME_RATE as u64;
+        let delay = (target - self.gif_centis).max(1);
+        self.gif_centis += delay;
+
+        let mut gif_frame = gif::Frame::from_palette_pixels(
+            width,
+            height,
+            frame.pixels,
+            frame.colors.as_flattened(),
+            None,
+        );
+        gif_frame.delay = delay.min(u16::MAX as u64) as u16;
+        self.gif
+            .as_mut()
+            .unwrap()
+            .write_frame(&gif_frame)
+            .map_err(io::Error::other)
+    }
+
+    fn write_apng(&mut self) -> Result<(), io::Error> {
+        let (width, height) = self.canvas;
+        let file = BufWriter::new(File::create(&self.path)?);
+        let mut encoder = png::Encoder::new(file, width as u32, height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_animated(self.apng.len() as u32, 0)?;
+        let mut writer = encoder.write_header()?;
+        for frame in &self.apng {
+            writer.set_frame_delay(frame.frames.min(u16::MAX as u32) as u16, FRAME_RATE as u16)?;
+            let rgb: Vec<u8> = frame
+                .pixels
+                .iter()
+                .flat_map(|&i| frame.colors[i as usize])
+                .collect();
+            writer.write_image_data(&rgb)?;
+        }
+        writer.finish()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,124 @@
+// Reading a ROM from disk or stdin. Besides raw images this understands
+// Octo source (`.8o`) and Octo cartridge GIFs, and takes the `.c8`, `.sc8`
+// and `.xo8` extensions as hints for the platform a ROM was written for.
+// `.ch8` is used for every platform, so it hints at nothing.
+
+use crate::analysis::Platform;
+use crate::cartridge::Cartridge;
+use crate::octo;
+use crate::palette::Palette;
+use crate::patch::{self, Patch};
+use crate::quirks::Quirks;
+use crate::romdb;
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// Read from stdin when given as the ROM path.
+pub const STDIN_PATH: &str = "-";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RomFormat {
+    Binary,
+    OctoSource,
+    OctoCartridge,
+}
+
+impl RomFormat {
+    pub fn name(&self) -> &'static str {
+        match self {
+            RomFormat::Binary => "binary",
+            RomFormat::OctoSource => "Octo source",
+            RomFormat::OctoCartridge => "Octo cartridge",
+        }
+    }
+}
+
+/// A loaded ROM image plus whatever settings came with it.
+#[derive(Clone)]
+pub struct RomImage {
+    pub data: Vec<u8>,
+    pub format: RomFormat,
+    pub platform: Option<Platform>,
+    pub quirks: Option<Quirks>,
+    pub palette: Option<Palette>,
+    pub tickrate: Option<u32>,       // instructions per 1/60 s frame
+    pub source_sha1: Option<String>, // SHA-1 before patching, if patched
+}
+
+impl RomImage {
+    /// Read `path`, or stdin if it is `-`.
+    pub fn load(path: &str) -> Result<Self, io::Error> {
+        if path == STDIN_PATH {
+            let mut data = Vec::new();
+            io::stdin().read_to_end(&mut data)?;
+            return Self::from_bytes(data, None);
+        }
+        let extension = Path::new(path)
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_ascii_lowercase());
+        Self::from_bytes(fs::read(path)?, extension.as_deref())
+    }
+
+    /// Decode `data`, using the file `extension` if there is one. Without
+    /// one, cartridges are told apart by their GIF header and anything else
+    /// is taken as a raw image.
+    pub fn from_bytes(data: Vec<u8>, extension: Option<&str>) -> Result<Self, io::Error> {
+        let mut image = RomImage {
+            data: Vec::new(),
+            format: RomFormat::Binary,
+            platform: None,
+            quirks: None,
+            palette: None,
+            tickrate: None,
+            source_sha1: None,
+        };
+        if data.starts_with(b"GIF8") || extension == Some("gif") {
+            let cartridge = Cartridge::decode(&data)?;
+            image.data = assemble(&cartridge.program)?;
+            image.format = RomFormat::OctoCartridge;
+            image.platform = cartridge.options.platform();
+            image.quirks = cartridge.options.quirks();
+            image.palette = cartridge.options.palette();
+            image.tickrate = cartridge.options.tickrate;
+            return Ok(image);
+        }
+        image.platform = match extension {
+            Some("c8") => Some(Platform::Chip8),
+            Some("sc8") => Some(Platform::Schip),
+            Some("xo8") => Some(Platform::XoChip),
+            _ => None,
+        };
+        if extension == Some("8o") {
+            let source = String::from_utf8(data)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            image.data = assemble(&source)?;
+            image.format = RomFormat::OctoSource;
+        } else {
+            image.data = data;
+        }
+        Ok(image)
+    }
+
+    /// Apply `patches` in order, keeping the unpatched SHA-1 so the ROM can
+    /// still be found in the ROM database.
+    pub fn apply_patches(&mut self, patches: &[Patch]) -> Result<(), io::Error> {
+        if patches.is_empty() {
+            return Ok(());
+        }
+        let patched = patch::apply_all(&self.data, patches)?;
+        self.source_sha1
+            .get_or_insert_with(|| romdb::sha1_hex(&self.data));
+        self.data = patched;
+        Ok(())
+    }
+
+    /// Instructions per second from the tickrate.
+    pub fn instruction_hz(&self) -> Option<u64> {
+        self.tickrate.map(|tickrate| tickrate as u64 * 60)
+    }
+}
+
+fn assemble(source: &str) -> Result<Vec<u8>, io::Error> {
+    octo::assemble(source).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
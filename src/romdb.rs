@@ -0,0 +1,354 @@
+// ROM metadata in the format of the community CHIP-8 database
+// (https://github.com/chip-8/chip-8-database): `programs.json` lists
+// programs with their ROMs keyed by SHA-1, `platforms.json` lists the
+// quirks of each platform. A small database for the bundled ROMs is built
+// in; the full community files or a local file in the same format can be
+// layered on top.
+
+use crate::keymap::Keymap;
+use crate::palette::{Palette, Rgb, parse_color};
+use crate::quirks::Quirks;
+use serde::Deserialize;
+use sha1::{Digest, Sha1};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::Path;
+use winit::keyboard::KeyCode;
+
+pub const DEFAULT_PATH: &str = "romdb.json";
+
+const BUNDLED_PROGRAMS: &str = include_str!("../data/programs.json");
+const BUNDLED_PLATFORMS: &str = include_str!("../data/platforms.json");
+
+#[derive(Debug, Clone, Deserialize)]
+struct Program {
+    title: String,
+    description: Option<String>,
+    release: Option<String>,
+    #[serde(default)]
+    authors: Vec<String>,
+    #[serde(default)]
+    roms: HashMap<String, Rom>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Rom {
+    file: Option<String>,
+    #[serde(default)]
+    platforms: Vec<String>,
+    #[serde(default)]
+    quirky_platforms: HashMap<String, QuirkFlags>,
+    tickrate: Option<u32>,
+    colors: Option<Colors>,
+    #[serde(default)]
+    keys: BTreeMap<String, u8>,
+    font_style: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Colors {
+    #[serde(default)]
+    pixels: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Platform {
+    id: String,
+    #[serde(default)]
+    quirks: QuirkFlags,
+}
+
+// The database's quirk names. `vblank` and `memoryIncrementByX` have no
+// equivalent here and are ignored.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct QuirkFlags {
+    shift: Option<bool>,
+    memory_leave_i_unchanged: Option<bool>,
+    wrap: Option<bool>,
+    jump: Option<bool>,
+    logic: Option<bool>,
+}
+
+impl QuirkFlags {
+    fn apply(&self, quirks: &mut Quirks) {
+        let flags = [
+            (self.shift, &mut quirks.shifting, false),
+            (self.memory_leave_i_unchanged, &mut quirks.memory, true),
+            (self.wrap, &mut quirks.clipping, true),
+            (self.jump, &mut quirks.jumping, false),
+            (self.logic, &mut quirks.vf_reset, false),
+        ];
+        for (value, quirk, inverted) in flags {
+            if let Some(on) = value {
+                *quirk = on != inverted;
+            }
+        }
+    }
+}
+
+/// What the database knows about one ROM.
+#[derive(Debug, Clone)]
+pub struct RomInfo {
+    pub sha1: String,
+    pub title: String,
+    pub description: Option<String>,
+    pub release: Option<String>,
+    pub authors: Vec<String>,
+    pub file: Option<String>,
+    pub platform: Option<String>,
+    pub quirks: Option<Quirks>,
+    pub tickrate: Option<u32>, // instructions per 1/60 s frame
+    pub colors: Vec<Rgb>,
+    pub keys: BTreeMap<String, u8>, // e.g. "up" -> 5
+    pub font: Option<String>,       // e.g. "vip"
+}
+
+// host keys the database's key hints are bound to
+const KEY_HINTS: [(&str, KeyCode); 12] = [
+    ("up", KeyCode::ArrowUp),
+    ("down", KeyCode::ArrowDown),
+    ("left", KeyCode::ArrowLeft),
+    ("right", KeyCode::ArrowRight),
+    ("a", KeyCode::Space),
+    ("b", KeyCode::Enter),
+    ("player2Up", KeyCode::KeyI),
+    ("player2Down", KeyCode::KeyK),
+    ("player2Left", KeyCode::KeyJ),
+    ("player2Right", KeyCode::KeyL),
+    ("player2A", KeyCode::KeyU),
+    ("player2B", KeyCode::KeyO),
+];
+
+impl RomInfo {
+    /// Instructions per second from the tickrate.
+    pub fn instruction_hz(&self) -> Option<u64> {
+        self.tickrate.map(|tickrate| tickrate as u64 * 60)
+    }
+
+    /// The ROM's colours over the classic palette, if it has any.
+    pub fn palette(&self) -> Option<Palette> {
+        if self.colors.is_empty() {
+            return None;
+        }
+        let mut colors = [0, 1, 2, 3].map(|i| Palette::default().color(i));
+        for (slot, color) in colors.iter_mut().zip(&self.colors) {
+            *slot = *color;
+        }
+        Some(Palette::new(&self.title, colors))
+    }
+
+    /// `keymap` plus arrow keys, Space and Enter for the ROM's key hints,
+    /// and IJKL, U and O for the second player's.
+    pub fn apply_key_hints(&self, keymap: &mut Keymap) {
+        for (hint, code) in KEY_HINTS {
+            if let Some(&chip8_key) = self.keys.get(hint) {
+                let mut codes = keymap.keys(chip8_key).to_vec();
+                codes.retain(|&c| c != code);
+                codes.push(code);
+                keymap.bind(chip8_key, &codes);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RomDatabase {
+    programs: Vec<Program>,
+    index: HashMap<String, usize>, // ROM SHA-1 -> program
+    platforms: HashMap<String, QuirkFlags>,
+}
+
+impl RomDatabase {
+    /// The database built into the emulator.
+    pub fn bundled() -> Self {
+        let mut db = RomDatabase::default();
+        db.add_programs(BUNDLED_PROGRAMS)
+            .expect("bundled programs.json is valid");
+        db.add_platforms(BUNDLED_PLATFORMS)
+            .expect("bundled platforms.json is valid");
+        db
+    }
+
+    /// Add the programs of a `programs.json` file. ROMs already known are
+    /// replaced, so a local file can override the bundled entries.
+    pub fn add_programs(&mut self, json: &str) -> Result<(), io::Error> {
+        let programs: Vec<Program> = serde_json::from_str(json).map_err(io::Error::other)?;
+        for program in programs {
+            for sha1 in program.roms.keys() {
+                self.index
+                    .insert(sha1.to_ascii_lowercase(), self.programs.len());
+            }
+            self.programs.push(program);
+        }
+        Ok(())
+    }
+
+    /// Add the platforms of a `platforms.json` file.
+    pub fn add_platforms(&mut self, json: &str) -> Result<(), io::Error> {
+        let platforms: Vec<Platform> = serde_json::from_str(json).map_err(io::Error::other)?;
+        for platform in platforms {
+            self.platforms.insert(platform.id, platform.quirks);
+        }
+        Ok(())
+    }
+
+    /// Add a local file: a `programs.json` or `platforms.json` style array,
+    /// told apart by whether the entries have an `id`.
+    pub fn add_file(&mut self, path: &Path) -> Result<(), io::Error> {
+        let json = fs::read_to_string(path)?;
+        let entries: Vec<serde_json::Value> =
+            serde_json::from_str(&json).map_err(io::Error::other)?;
+        let result = if entries.iter().any(|entry| entry.get("id").is_some()) {
+            self.add_platforms(&json)
+        } else {
+            self.add_programs(&json)
+        };
+        result.map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), e),
+            )
+        })
+    }
+
+    pub fn lookup(&self, sha1: &str) -> Option<RomInfo> {
+        let sha1 = sha1.to_ascii_lowercase();
+        let program = &self.programs[*self.index.get(&sha1)?];
+        let rom = program
+            .roms
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(&sha1))
+            .map(|(_, rom)| rom)?;
+
+        let platform = rom.platforms.first().cloned();
+        let quirks = platform.as_ref().and_then(|id| {
+            let mut quirks = Quirks::default();
+            self.platforms.get(id)?.apply(&mut quirks);
+            if let Some(flags) = rom.quirky_platforms.get(id) {
+                flags.apply(&mut quirks);
+            }
+            Some(quirks)
+        });
+        let colors = rom
+            .colors
+            .iter()
+            .flat_map(|colors| &colors.pixels)
+            .filter_map(|color| parse_color(color))
+            .collect();
+
+        Some(RomInfo {
+            sha1,
+            title: program.title.clone(),
+            description: program.description.clone(),
+            release: program.release.clone(),
+            authors: program.authors.clone(),
+            file: rom.file.clone(),
+            platform,
+            quirks,
+            tickrate: rom.tickrate,
+            colors,
+            keys: rom.keys.clone(),
+            font: rom.font_style.clone(),
+        })
+    }
+}
+
+/// Lowercase hex SHA-1 of `data`.
+pub fn sha1_hex(data: &[u8]) -> String {
+    format!("{:x}", Sha1::digest(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHA1: &str = "0123456789abcdef0123456789abcdef01234567";
+
+    fn program(rom: &str) -> String {
+        format!(
+            r#"[{{"title": "Test", "authors": ["Someone"], "roms": {{"{}": {}}}}}]"#,
+            SHA1, rom
+        )
+    }
+
+    #[test]
+    fn bundled_roms_are_known() {
+        let db = RomDatabase::bundled();
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("roms");
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            let info = db.lookup(&sha1_hex(&fs::read(&path).unwrap())).unwrap();
+            let name = path.file_name().unwrap().to_str().unwrap();
+            assert_eq!(info.file.as_deref(), Some(name));
+            assert!(info.quirks.is_some(), "{}", name);
+        }
+        assert!(db.lookup(SHA1).is_none());
+    }
+
+    #[test]
+    fn platform_quirks_take_rom_overrides() {
+        let mut db = RomDatabase::bundled();
+        db.add_programs(&program(
+            r#"{"platforms": ["originalChip8", "modernChip8"],
+                "quirkyPlatforms": {"originalChip8": {"wrap": true, "logic": false}}}"#,
+        ))
+        .unwrap();
+        let info = db.lookup(&SHA1.to_ascii_uppercase()).unwrap();
+        assert_eq!(info.sha1, SHA1);
+        assert_eq!(info.platform.as_deref(), Some("originalChip8"));
+        assert_eq!(
+            info.quirks,
+            Some(Quirks {
+                clipping: false,
+                vf_reset: false,
+                ..Quirks::CHIP8
+            })
+        );
+
+        // a platform the database doesn't list has no quirks
+        db.add_programs(&program(r#"{"platforms": ["unknown"]}"#))
+            .unwrap();
+        assert_eq!(db.lookup(SHA1).unwrap().quirks, None);
+    }
+
+    #[test]
+    fn settings_come_from_the_rom_entry() {
+        let mut db = RomDatabase::default();
+        db.add_programs(&program(
+            r##"{"tickrate": 20, "colors": {"pixels": ["#000000", "#ff8000", "bad"]},
+                "keys": {"up": 5, "player2A": 12}}"##,
+        ))
+        .unwrap();
+        let info = db.lookup(SHA1).unwrap();
+        assert_eq!(info.authors, ["Someone"]);
+        assert_eq!(info.instruction_hz(), Some(1200));
+        assert_eq!(info.colors, [[0, 0, 0], [0xFF, 0x80, 0]]);
+        let palette = info.palette().unwrap();
+        assert_eq!(palette.foreground(), [0xFF, 0x80, 0]);
+        assert_eq!(palette.color(2), Palette::default().color(2));
+
+        let mut keymap = Keymap::default();
+        info.apply_key_hints(&mut keymap);
+        assert_eq!(keymap.keys(0x5), [KeyCode::KeyW, KeyCode::ArrowUp]);
+        assert_eq!(keymap.lookup(KeyCode::KeyU), Some(0xC));
+    }
+
+    #[test]
+    fn local_files_are_told_apart() {
+        let path = std::env::temp_dir().join(format!("chip8-romdb-{}.json", std::process::id()));
+        let mut db = RomDatabase::default();
+        fs::write(&path, program(r#"{"platforms": ["mine"]}"#)).unwrap();
+        db.add_file(&path).unwrap();
+        fs::write(&path, r#"[{"id": "mine", "quirks": {"jump": true}}]"#).unwrap();
+        db.add_file(&path).unwrap();
+        fs::write(&path, r#"[{"title": 5}]"#).unwrap();
+        assert!(db.add_file(&path).is_err());
+        let _ = fs::remove_file(&path);
+
+        let quirks = db.lookup(SHA1).unwrap().quirks.unwrap();
+        assert!(quirks.jumping);
+    }
+}
@@ -0,0 +1,170 @@
+use crate::chip8::Chip8;
+use crate::framebuffer::FrameBuffer;
+use std::io;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+pub const FRAME_HZ: u64 = 60;
+
+/// Runs a `Chip8` one 1/60 s frame at a time. The same number of
+/// instructions runs every frame regardless of wall-clock time, so output
+/// depends only on the ROM, quirks, RNG seed and the keypad state each frame.
+pub struct Runner {
+    chip8: Chip8,
+    screen: Arc<Mutex<FrameBuffer>>,
+    instruction_hz: u64,
+    frame: u64,
+}
+
+impl Runner {
+    pub fn new(instruction_hz: u64) -> Self {
+        Self::with_screen(Arc::new(Mutex::new(FrameBuffer::new())), instruction_hz)
+    }
+
+    /// Run against a frame buffer shared with a display thread.
+    pub fn with_screen(screen: Arc<Mutex<FrameBuffer>>, instruction_hz: u64) -> Self {
+        let mut chip8 = Chip8::new_with_buffer(Arc::clone(&screen));
+        chip8.init();
+        Self {
+            chip8,
+            screen,
+            instruction_hz,
+            frame: 0,
+        }
+    }
+
+    pub fn load_rom(&mut self, path: String) -> Result<(), io::Error> {
+        self.chip8.load_rom(path)
+    }
+
+    pub fn chip8(&mut self) -> &mut Chip8 {
+        &mut self.chip8
+    }
+
+    pub fn instruction_hz(&self) -> u64 {
+        self.instruction_hz
+    }
+
+    pub fn set_instruction_hz(&mut self, instruction_hz: u64) {
+        self.instruction_hz = instruction_hz;
+    }
+
+    /// Soft or hard reset the machine, see `Chip8::soft_reset` and
+    /// `Chip8::hard_reset`, and let go of every key so none stays stuck.
+    pub fn reset(&mut self, hard: bool) {
+        if hard {
+            self.chip8.hard_reset();
+        } else {
+            self.chip8.soft_reset();
+        }
+        self.chip8.input_handler.release_all();
+    }
+
+    /// Frames run so far.
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    pub fn screen(&self) -> MutexGuard<'_, FrameBuffer> {
+        self.screen.lock().unwrap()
+    }
+
+    /// Run one frame's worth of instructions followed by one timer tick.
+    /// Returns whether the buzzer sounded during the frame.
+    pub fn step_frame(&mut self) -> bool {
+        // spread instruction_hz / 60 over frames without drift
+        let start = self.frame * self.instruction_hz / FRAME_HZ;
+        let end = (self.frame + 1) * self.instruction_hz / FRAME_HZ;
+        for _ in start..end {
+            self.chip8.cycle();
+        }
+        let sound = self.chip8.sound_active();
+        self.chip8.update_timer();
+        self.chip8.input_handler.on_frame();
+        self.frame += 1;
+        sound
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Draws a random number into V0 and counts the cycles key 9 is held in
+    /// V2, so runs with a different seed or input end in different states.
+    pub(crate) const COUNTER_ROM: [u8; 12] = [
+        0x61, 0x09, // ld v1, 9
+        0xC0, 0xFF, // rnd v0, 0xff
+        0xE1, 0x9E, // skp v1
+        0x12, 0x02, // jp 0x202
+        0x72, 0x01, // add v2, 1
+        0x12, 0x02, // jp 0x202
+    ];
+
+    /// A runner at `instruction_hz` with `rom` loaded.
+    pub(crate) fn with_rom(rom: &[u8], instruction_hz: u64) -> Runner {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "chip8-rom-{}-{}.ch8",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::write(&path, rom).unwrap();
+        let mut runner = Runner::new(instruction_hz);
+        runner.load_rom(path.display().to_string()).unwrap();
+        let _ = std::fs::remove_file(&path);
+        runner
+    }
+
+    /// A runner at `instruction_hz` with `COUNTER_ROM` loaded.
+    pub(crate) fn counter(instruction_hz: u64) -> Runner {
+        with_rom(&COUNTER_ROM, instruction_hz)
+    }
+
+    fn run(runner: &mut Runner, frames: usize, key_at: Option<usize>) -> String {
+        for frame in 0..frames {
+            if key_at == Some(frame) {
+                runner.chip8().input_handler.set_keypad_bits(1 << 9);
+            }
+            runner.step_frame();
+        }
+        runner.chip8().state_hash()
+    }
+
+    fn seeded(seed: u64) -> Runner {
+        let mut runner = counter(700);
+        runner.chip8().seed_rng(seed);
+        runner
+    }
+
+    #[test]
+    fn same_seed_and_input_give_the_same_state() {
+        let state = run(&mut seeded(7), 120, Some(30));
+        assert_eq!(run(&mut seeded(7), 120, Some(30)), state);
+        assert_ne!(run(&mut seeded(8), 120, Some(30)), state);
+        assert_ne!(run(&mut seeded(7), 120, Some(31)), state);
+        assert_ne!(run(&mut seeded(7), 120, None), state);
+    }
+
+    #[test]
+    fn frames_run_the_same_instructions_at_any_rate() {
+        // 700 / 60 doesn't divide, but a second of frames is still 700
+        let mut fast = seeded(7);
+        run(&mut fast, 60, None);
+        let mut slow = seeded(7);
+        slow.set_instruction_hz(350);
+        run(&mut slow, 120, None);
+        assert_eq!(fast.frame(), 60);
+        assert_eq!(fast.chip8().state_hash(), slow.chip8().state_hash());
+    }
+
+    #[test]
+    fn hard_reset_starts_over() {
+        let fresh = run(&mut seeded(7), 60, Some(10));
+        let mut reset = seeded(7);
+        run(&mut reset, 45, Some(5));
+        reset.reset(true);
+        assert!(!reset.chip8().input_handler.is_key_pressed(0x9));
+        assert_eq!(run(&mut reset, 60, Some(10)), fresh);
+    }
+}
@@ -0,0 +1,256 @@
+// What a ROM keeps between runs: the SCHIP user flags `Fx75` saves, where
+// games put their high scores, and optionally a range of memory for games
+// that keep them elsewhere. One file per ROM, named by its SHA-1.
+
+use crate::chip8::{Chip8, MEMORY_SIZE};
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+const MAGIC: &str = "CHIP8SAVE 1";
+
+/// `saves` in the user data directory, e.g.
+/// `~/.local/share/chip8-emulator/saves` on Linux, or in the working
+/// directory on systems without one.
+pub fn default_dir() -> PathBuf {
+    match dirs::data_dir() {
+        Some(dir) => dir.join("chip8-emulator").join("saves"),
+        None => PathBuf::from("saves"),
+    }
+}
+
+/// Saved as text: the flags as hex bytes on a `flags` line and the memory
+/// range as `memory <start> <bytes>`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SaveData {
+    pub flags: [u8; 16],
+    pub memory: Option<(u16, Vec<u8>)>, // start address and the bytes there
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let hex: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    hex.join(" ")
+}
+
+impl SaveData {
+    pub fn load(path: &Path) -> Result<Self, io::Error> {
+        let text = fs::read_to_string(path)?;
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+        let mut lines = text.lines();
+        if lines.next() != Some(MAGIC) {
+            return Err(invalid(format!("{}: not a save file", path.display())));
+        }
+        let mut save = SaveData::default();
+        for line in lines {
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            let bad_value = || invalid(format!("invalid save {}: {}", key, value));
+            let bytes = |text: &str| {
+                text.split_whitespace()
+                    .map(|byte| u8::from_str_radix(byte, 16).map_err(|_| bad_value()))
+                    .collect::<Result<Vec<u8>, _>>()
+            };
+            match key {
+                "flags" => {
+                    let flags = bytes(value)?;
+                    if flags.len() > save.flags.len() {
+                        return Err(bad_value());
+                    }
+                    save.flags[..flags.len()].copy_from_slice(&flags);
+                }
+                "memory" => {
+                    let (start, data) = value.split_once(' ').unwrap_or((value, ""));
+                    let start = u16::from_str_radix(start, 16).map_err(|_| bad_value())?;
+                    let data = bytes(data)?;
+                    if start as usize + data.len() > MEMORY_SIZE {
+                        return Err(bad_value());
+                    }
+                    save.memory = Some((start, data));
+                }
+                _ => return Err(invalid(format!("unknown save field: {}", key))),
+            }
+        }
+        Ok(save)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), io::Error> {
+        let mut text = format!("{}\nflags {}\n", MAGIC, hex(&self.flags));
+        if let Some((start, data)) = &self.memory {
+            text += &format!("memory {:03x} {}\n", start, hex(data));
+        }
+        fs::write(path, text)
+    }
+
+    /// The flags of `chip8`, and its memory in `range` if there is one.
+    pub fn capture(chip8: &Chip8, range: Option<Range<u16>>) -> Self {
+        let memory = range.map(|range| {
+            let data = chip8.memory()[range.start as usize..range.end as usize].to_vec();
+            (range.start, data)
+        });
+        Self {
+            flags: *chip8.rpl_flags(),
+            memory,
+        }
+    }
+
+    /// Put the saved flags and memory back into `chip8`.
+    pub fn restore(&self, chip8: &mut Chip8) {
+        chip8.set_rpl_flags(self.flags);
+        if let Some((start, data)) = &self.memory {
+            let start = *start as usize;
+            chip8.memory_mut()[start..start + data.len()].copy_from_slice(data);
+        }
+    }
+
+    /// The memory range the save covers.
+    pub fn range(&self) -> Option<Range<u16>> {
+        let (start, data) = self.memory.as_ref()?;
+        Some(*start..*start + data.len() as u16)
+    }
+}
+
+/// Where a ROM's save data lives and which memory it covers.
+#[derive(Debug, Clone)]
+pub struct SaveSlot {
+    pub path: PathBuf,
+    pub memory: Option<Range<u16>>,
+}
+
+impl SaveSlot {
+    pub fn new(dir: &Path, rom_sha1: &str, memory: Option<Range<u16>>) -> Self {
+        Self {
+            path: dir.join(format!("{}.sav", rom_sha1)),
+            memory,
+        }
+    }
+
+    /// Load the save into `chip8` if there is one. A range saved before is
+    /// used unless the slot was given its own. Returns whether anything was
+    /// restored.
+    pub fn restore(&mut self, chip8: &mut Chip8) -> Result<bool, io::Error> {
+        if !self.path.is_file() {
+            return Ok(false);
+        }
+        let mut save = SaveData::load(&self.path)?;
+        if let Some(range) = &self.memory
+            && save.range().as_ref() != Some(range)
+        {
+            // a different range than last time, only the flags still apply
+            save.memory = None;
+        }
+        self.memory = self.memory.clone().or(save.range());
+        save.restore(chip8);
+        Ok(true)
+    }
+
+    /// Write the flags and memory range of `chip8` out. Nothing is written
+    /// for a ROM that never set a flag and has no range to keep.
+    pub fn store(&self, chip8: &Chip8) -> Result<(), io::Error> {
+        let save = SaveData::capture(chip8, self.memory.clone());
+        if save == SaveData::default() && !self.path.exists() {
+            return Ok(());
+        }
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        save.save(&self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::framebuffer::FrameBuffer;
+    use std::sync::{Arc, Mutex};
+
+    // a fresh directory under the system temp dir for one test
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("chip8-savedata-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn chip8() -> Chip8 {
+        Chip8::new_with_buffer(Arc::new(Mutex::new(FrameBuffer::new())))
+    }
+
+    #[test]
+    fn saves_load_back() {
+        let path = temp_dir("round-trip").join("game.sav");
+        let mut save = SaveData::default();
+        save.flags[0] = 0x12;
+        save.flags[15] = 0xFF;
+        save.memory = Some((0x300, vec![1, 2, 0xAB]));
+        save.save(&path).unwrap();
+        assert_eq!(SaveData::load(&path).unwrap(), save);
+        assert_eq!(save.range(), Some(0x300..0x303));
+    }
+
+    #[test]
+    fn bad_files_are_refused() {
+        let dir = temp_dir("bad");
+        let files = [
+            "not a save\n",
+            "CHIP8SAVE 1\nflags 00 zz\n",
+            "CHIP8SAVE 1\nflags 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00\n",
+            "CHIP8SAVE 1\nmemory fff 01 02\n",
+            "CHIP8SAVE 1\nscore 100\n",
+        ];
+        for (i, text) in files.iter().enumerate() {
+            let path = dir.join(format!("{}.sav", i));
+            fs::write(&path, text).unwrap();
+            assert!(SaveData::load(&path).is_err(), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn slots_store_and_restore_flags_and_memory() {
+        let dir = temp_dir("slot");
+        let mut running = chip8();
+        running.set_rpl_flags([7; 16]);
+        running.memory_mut()[0x400..0x404].copy_from_slice(&[1, 2, 3, 4]);
+        SaveSlot::new(&dir, "abc", Some(0x400..0x404))
+            .store(&running)
+            .unwrap();
+
+        // no range of its own, so it takes the one in the file
+        let mut slot = SaveSlot::new(&dir, "abc", None);
+        let mut restored = chip8();
+        assert!(slot.restore(&mut restored).unwrap());
+        assert_eq!(slot.memory, Some(0x400..0x404));
+        assert_eq!(restored.rpl_flags(), &[7; 16]);
+        assert_eq!(restored.memory()[0x400..0x404], [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn another_range_only_restores_the_flags() {
+        let dir = temp_dir("range");
+        let mut running = chip8();
+        running.set_rpl_flags([7; 16]);
+        running.memory_mut()[0x400] = 9;
+        SaveSlot::new(&dir, "abc", Some(0x400..0x401))
+            .store(&running)
+            .unwrap();
+
+        let mut slot = SaveSlot::new(&dir, "abc", Some(0x500..0x501));
+        let mut restored = chip8();
+        assert!(slot.restore(&mut restored).unwrap());
+        assert_eq!(restored.rpl_flags(), &[7; 16]);
+        assert_eq!(restored.memory()[0x400], 0);
+    }
+
+    #[test]
+    fn nothing_to_save_writes_no_file() {
+        let dir = temp_dir("empty");
+        let slot = SaveSlot::new(&dir, "abc", None);
+        slot.store(&chip8()).unwrap();
+        assert!(!slot.path.exists());
+        assert!(
+            !SaveSlot::new(&dir, "abc", None)
+                .restore(&mut chip8())
+                .unwrap()
+        );
+    }
+}
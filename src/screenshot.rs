@@ -0,0 +1,76 @@
+use crate::framebuffer::FrameBuffer;
+use crate::palette::Palette;
+use crate::postfx::PostFilter;
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const DEFAULT_DIR: &str = "screenshots";
+
+/// Save `fb` twice into `dir`: once at native resolution and once upscaled
+/// by `scale` through `filter`, both in `palette` colours. File names are
+/// `chip8-<UTC timestamp>.png` and `chip8-<UTC timestamp>-x<scale>.png`.
+/// The directory is created if needed. Returns the two paths written.
+pub fn save(
+    dir: &Path,
+    fb: &FrameBuffer,
+    palette: &Palette,
+    filter: PostFilter,
+    scale: usize,
+) -> Result<[PathBuf; 2], io::Error> {
+    fs::create_dir_all(dir)?;
+    let stamp = timestamp(SystemTime::now());
+    let (width, height) = (fb.width(), fb.height());
+    let raw = fb.to_rgba(palette);
+
+    let raw_path = dir.join(format!("chip8-{}.png", stamp));
+    write_png(&raw_path, width, height, &raw)?;
+
+    let mut scaled = vec![0; width * scale * height * scale * 4];
+    filter.apply(&raw, width, height, scale, &mut scaled);
+    let scaled_path = dir.join(format!("chip8-{}-x{}.png", stamp, scale));
+    write_png(&scaled_path, width * scale, height * scale, &scaled)?;
+
+    Ok([raw_path, scaled_path])
+}
+
+/// Write an RGBA image as an 8-bit PNG.
+pub fn write_png(path: &Path, width: usize, height: usize, rgba: &[u8]) -> Result<(), io::Error> {
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(rgba)?;
+    Ok(())
+}
+
+/// `YYYYMMDD-HHMMSS-mmm` in UTC.
+pub fn timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, rem) = ((secs / 86_400) as i64, secs % 86_400);
+
+    // civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}-{:03}",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem / 60 % 60,
+        rem % 60,
+        since_epoch.subsec_millis()
+    )
+}
@@ -0,0 +1,272 @@
+// Where the frame goes in a window that isn't a multiple of its size, and
+// what fills the rest.
+
+use crate::palette::Rgb;
+use pixels::wgpu;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScaleMode {
+    Integer, // whole multiples only, centred
+    Fit,     // as large as fits, keeping the 2:1 shape
+    Stretch, // fill the window
+}
+
+const ALL: [ScaleMode; 3] = [ScaleMode::Integer, ScaleMode::Fit, ScaleMode::Stretch];
+
+/// A window position and size in physical pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl ScaleMode {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "integer" => Some(ScaleMode::Integer),
+            "fit" | "aspect" => Some(ScaleMode::Fit),
+            "stretch" => Some(ScaleMode::Stretch),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ScaleMode::Integer => "integer",
+            ScaleMode::Fit => "fit",
+            ScaleMode::Stretch => "stretch",
+        }
+    }
+
+    pub fn next(&self) -> Self {
+        let i = ALL.iter().position(|m| m == self).unwrap_or(0);
+        ALL[(i + 1) % ALL.len()]
+    }
+
+    /// Where a `width`x`height` frame is drawn in a window of
+    /// `window_width`x`window_height`. A window smaller than the frame gets
+    /// the frame squeezed into it in integer mode.
+    pub fn view(
+        &self,
+        width: usize,
+        height: usize,
+        window_width: usize,
+        window_height: usize,
+    ) -> Rect {
+        let (w, h) = match self {
+            ScaleMode::Integer => {
+                let scale = (window_width / width).min(window_height / height).max(1);
+                (width * scale, height * scale)
+            }
+            ScaleMode::Fit if window_width * height <= window_height * width => {
+                (window_width, window_width * height / width)
+            }
+            ScaleMode::Fit => (window_height * width / height, window_height),
+            ScaleMode::Stretch => (window_width, window_height),
+        };
+        let (w, h) = (w.min(window_width), h.min(window_height));
+        Rect {
+            x: (window_width - w) / 2,
+            y: (window_height - h) / 2,
+            width: w,
+            height: h,
+        }
+    }
+}
+
+impl Rect {
+    /// The pixel of a `width`x`height` frame shown at window position
+    /// `(x, y)`, if it is inside the frame.
+    pub fn to_frame(&self, x: f32, y: f32, width: usize, height: usize) -> Option<(usize, usize)> {
+        let (x, y) = (x - self.x as f32, y - self.y as f32);
+        if x < 0.0 || y < 0.0 || x >= self.width as f32 || y >= self.height as f32 {
+            return None;
+        }
+        Some((
+            x as usize * width / self.width,
+            y as usize * height / self.height,
+        ))
+    }
+}
+
+const SHADER: &str = r"
+@group(0) @binding(0) var frame: texture_2d<f32>;
+@group(0) @binding(1) var frame_sampler: sampler;
+
+struct Vertex {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+// one triangle over the whole viewport
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> Vertex {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: Vertex;
+    out.position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+@fragment
+fn fs_main(in: Vertex) -> @location(0) vec4<f32> {
+    return textureSample(frame, frame_sampler, in.uv);
+}
+";
+
+/// Draws the `pixels` texture nearest-neighbour scaled into a `Rect` of the
+/// window on the GPU, with the border colour all around it, so the texture
+/// stays at the frame's own size whatever the window's. `format` is the
+/// render target's, `Pixels::render_texture_format`.
+pub struct Scaler {
+    pipeline: wgpu::RenderPipeline,
+    layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    // rebuilt when `pixels` replaces its texture on a resize
+    bind_group: Option<(wgpu::Id<wgpu::Texture>, wgpu::BindGroup)>,
+    border: wgpu::Color,
+}
+
+impl Scaler {
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat, border: Rgb) -> Self {
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("scaler_shader"),
+            source: wgpu::ShaderSource::Wgsl(SHADER.into()),
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("scaler_sampler"),
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("scaler_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("scaler_pipeline_layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("scaler_pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &module,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            fragment: Some(wgpu::FragmentState {
+                module: &module,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            multiview: None,
+        });
+        let [r, g, b] = linear(border);
+        Self {
+            pipeline,
+            layout,
+            sampler,
+            bind_group: None,
+            border: wgpu::Color { r, g, b, a: 1.0 },
+        }
+    }
+
+    /// Draw `texture` into `view` of `target`, from `Pixels::render_with`.
+    pub fn render(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        texture: &wgpu::Texture,
+        target: &wgpu::TextureView,
+        view: Rect,
+    ) {
+        let id = texture.global_id();
+        if self.bind_group.as_ref().map(|(bound, _)| *bound) != Some(id) {
+            let texture_view = texture.create_view(&Default::default());
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("scaler_bind_group"),
+                layout: &self.layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&texture_view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&self.sampler),
+                    },
+                ],
+            });
+            self.bind_group = Some((id, bind_group));
+        }
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("scaler_render_pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(self.border),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        if view.width == 0 || view.height == 0 {
+            return;
+        }
+        let (_, bind_group) = self.bind_group.as_ref().unwrap();
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, bind_group, &[]);
+        pass.set_viewport(
+            view.x as f32,
+            view.y as f32,
+            view.width as f32,
+            view.height as f32,
+            0.0,
+            1.0,
+        );
+        pass.draw(0..3, 0..1);
+    }
+}
+
+// `color` as the linear colour the GPU clears an sRGB surface with
+fn linear(color: Rgb) -> [f64; 3] {
+    color.map(|c| {
+        let c = c as f64 / 255.0;
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    })
+}